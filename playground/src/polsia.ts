@@ -3,37 +3,41 @@ import type { StreamParser, StringStream } from '@codemirror/language'
 
 interface State {
   inString: boolean
+  close: string
   escape: boolean
   indent: number
 }
 
 const parser: StreamParser<State> = {
   startState() {
-    return { inString: false, escape: false, indent: 0 }
+    return { inString: false, close: '"', escape: false, indent: 0 }
   },
 
   token(stream: StringStream, state: State) {
     if (state.inString) {
       while (!stream.eol()) {
+        if (!state.escape && stream.match(state.close)) {
+          state.inString = false
+          break
+        }
         const ch = stream.next()
         if (state.escape) {
           state.escape = false
-          continue
-        }
-        if (ch === '\\') {
+        } else if (ch === '\\' && !state.close.startsWith('"#')) {
           state.escape = true
-        } else if (ch === '"') {
-          state.inString = false
-          break
         }
       }
       return 'string'
     }
 
-    if (stream.match(/^#.*/)) return 'comment'
+    if (stream.match(/^#(?!#*").*/)) return 'comment'
 
-    if (stream.match('"')) {
+    const open = stream.match(/^(?:#+"|"""(?=[ \t]*$)|")/)
+    if (open) {
+      const quote = (open as RegExpMatchArray)[0]
       state.inString = true
+      // `##"` closes with `"##`.
+      state.close = quote.startsWith('#') ? '"' + quote.slice(0, -1) : quote
       return 'string'
     }
 
@@ -41,7 +45,7 @@ const parser: StreamParser<State> = {
 
     if (
       stream.match(
-        /\b(?:null|true|false|Any|Nothing|Int|Number|Rational|Float|String|Boolean|NoExport)\b/
+        /\b(?:null|true|false|Any|Nothing|Int|Number|Rational|Float|String|Boolean|NoExport|import)\b/
       )
     ) {
       return 'keyword'
@@ -73,7 +77,7 @@ const parser: StreamParser<State> = {
 
     if (stream.match(/[{},[\]]/)) return 'bracket'
 
-    if (stream.match(/:/)) return 'operator'
+    if (stream.match(/\?:|:|\.\.\./)) return 'operator'
 
     if (stream.match(/[A-Za-z_][\w.]*/)) return 'variableName'
 
@@ -0,0 +1,11 @@
+//! The Polsia language server, speaking LSP over stdin and stdout.
+
+use std::io;
+use std::process;
+
+fn main() {
+    if let Err(e) = polsia::lsp::run(io::stdin().lock(), io::stdout().lock()) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
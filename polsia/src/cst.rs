@@ -0,0 +1,747 @@
+//! A lossless concrete syntax tree. Unlike [`crate::parser`], which builds
+//! values, this keeps every comment, line break and token exactly as written,
+//! so the tree prints back to the original source. It is what the formatter
+//! works on.
+//!
+//! Only well-formed input is expected here: callers check the source with
+//! [`crate::parser::document`] first, which has the better error messages.
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    Colon,
+    /// `?` after the key of an optional member.
+    Question,
+    Comma,
+    Pipe,
+    /// An operator such as `+` or `<=`.
+    Op,
+    /// `...` before the type of the rest of a list.
+    Ellipsis,
+    /// An identifier, keyword or dotted reference such as `a.b`.
+    Word,
+    Str,
+    Number,
+    /// `@NoExport` and friends.
+    Annotation,
+    /// Carries the trivia at the end of the file.
+    Eof,
+}
+
+/// Layout between tokens. Each token owns the trivia before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trivia {
+    /// Spaces, tabs and other non-newline whitespace.
+    Whitespace(String),
+    Newline,
+    /// A `#` comment, without its line break.
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub span: Range<usize>,
+}
+
+impl Token {
+    /// Whether a line break or comment precedes this token.
+    pub fn starts_line(&self) -> bool {
+        self.leading
+            .iter()
+            .any(|t| matches!(t, Trivia::Newline | Trivia::Comment(_)))
+    }
+
+    pub fn has_comments(&self) -> bool {
+        self.leading.iter().any(|t| matches!(t, Trivia::Comment(_)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub imports: Vec<ImportDecl>,
+    pub body: Body,
+    pub eof: Token,
+}
+
+/// `import "path" as alias`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportDecl {
+    pub import: Token,
+    pub path: Token,
+    pub as_: Token,
+    pub alias: Token,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// A brace-less top-level object. Items are members or inline objects.
+    Items(Vec<Item>),
+    Value(Expr),
+}
+
+/// One entry of an object, array or brace-less top level, with the comma
+/// that followed it, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub value: Expr,
+    pub comma: Option<Token>,
+}
+
+/// `{ ... }`, `[ ... ]` or `( ... )`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub open: Token,
+    pub items: Vec<Item>,
+    pub close: Token,
+}
+
+/// `key: value` or `key?: value`. A chain such as `foo: bar: baz` is a
+/// member whose value is another member.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub key: Token,
+    pub question: Option<Token>,
+    pub colon: Token,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A literal, type, annotation or reference.
+    Token(Token),
+    Object(Container),
+    Array(Container),
+    /// A list type followed by its length bounds, such as `[...Int]{1,}`.
+    List(Container, Box<Container>),
+    /// `String` followed by length bounds, such as `String{1,63}`.
+    Sized(Token, Box<Container>),
+    Group(Container),
+    Member(Box<Member>),
+    Call(Token, Box<Expr>),
+    /// `-` applied to something other than a number literal, `...` before
+    /// the rest of a list, or `*` before the default of a union.
+    Unary(Token, Box<Expr>),
+    Binary(Box<Expr>, Token, Box<Expr>),
+    /// Alternatives and the `|` tokens between them.
+    Union(Vec<Expr>, Vec<Token>),
+}
+
+impl Expr {
+    pub fn first_token(&self) -> &Token {
+        match self {
+            Expr::Token(t) | Expr::Call(t, _) | Expr::Unary(t, _) | Expr::Sized(t, _) => t,
+            Expr::Object(c) | Expr::Array(c) | Expr::List(c, _) | Expr::Group(c) => &c.open,
+            Expr::Member(m) => &m.key,
+            Expr::Binary(left, _, _) => left.first_token(),
+            Expr::Union(items, _) => items[0].first_token(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub msg: String,
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+pub fn parse(src: &str) -> Result<SourceFile, SyntaxError> {
+    let tokens = lex(src)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    parser.source_file()
+}
+
+pub(crate) const KEYWORDS: &[&str] = &[
+    "null", "true", "false", "Any", "Nothing", "Int", "Number", "Rational", "Float", "String",
+    "Boolean",
+];
+
+fn lex(src: &str) -> Result<Vec<Token>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut leading = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let mut take_while = |f: &dyn Fn(char) -> bool| {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !f(c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            end
+        };
+        if c == '\n' {
+            chars.next();
+            leading.push(Trivia::Newline);
+            continue;
+        }
+        if c.is_whitespace() {
+            let end = take_while(&|c| c.is_whitespace() && c != '\n');
+            leading.push(Trivia::Whitespace(src[start..end].to_string()));
+            continue;
+        }
+        if c == '#' && raw_hashes(&src[start..]).is_none() {
+            let end = take_while(&|c| c != '\n');
+            leading.push(Trivia::Comment(src[start..end].to_string()));
+            continue;
+        }
+        let next = src[start + c.len_utf8()..].chars().next();
+        let (kind, end) = match c {
+            '{' => (TokenKind::LBrace, start + 1),
+            '}' => (TokenKind::RBrace, start + 1),
+            '[' => (TokenKind::LBracket, start + 1),
+            ']' => (TokenKind::RBracket, start + 1),
+            '(' => (TokenKind::LParen, start + 1),
+            ')' => (TokenKind::RParen, start + 1),
+            ':' => (TokenKind::Colon, start + 1),
+            '?' => (TokenKind::Question, start + 1),
+            ',' => (TokenKind::Comma, start + 1),
+            '|' => (TokenKind::Pipe, start + 1),
+            '.' if src[start..].starts_with("...") => (TokenKind::Ellipsis, start + 3),
+            '-' if next.is_some_and(|n| n.is_ascii_digit())
+                && starts_number(tokens.last(), !leading.is_empty()) =>
+            {
+                (TokenKind::Number, number_end(src, start + 1))
+            }
+            '+' | '-' | '*' | '%' | '&' => (TokenKind::Op, start + 1),
+            '/' if next == Some('/') => (TokenKind::Op, start + 2),
+            '/' => (TokenKind::Op, start + 1),
+            '<' | '>' if next == Some('=') => (TokenKind::Op, start + 2),
+            '<' | '>' => (TokenKind::Op, start + 1),
+            '=' | '!' if next == Some('=') || next == Some('~') => (TokenKind::Op, start + 2),
+            '"' | '#' => (TokenKind::Str, string_end(src, start)?),
+            '0'..='9' => (TokenKind::Number, number_end(src, start)),
+            '@' => (TokenKind::Annotation, word_end(src, start + 1, false)),
+            c if c.is_alphabetic() || c == '_' => (TokenKind::Word, word_end(src, start, true)),
+            _ => {
+                return Err(SyntaxError {
+                    msg: format!("unexpected character {:?}", c),
+                    span: start..start + c.len_utf8(),
+                });
+            }
+        };
+        while chars.peek().is_some_and(|&(i, _)| i < end) {
+            chars.next();
+        }
+        tokens.push(Token {
+            kind,
+            text: src[start..end].to_string(),
+            leading: std::mem::take(&mut leading),
+            span: start..end,
+        });
+    }
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        text: String::new(),
+        leading,
+        span: src.len()..src.len(),
+    });
+    Ok(tokens)
+}
+
+/// Whether a `-` directly before a digit is the sign of a number rather
+/// than subtraction. It is not after an operand, except that in `f -1` it
+/// starts the argument of `f`. `spaced` says whether whitespace precedes it.
+fn starts_number(prev: Option<&Token>, spaced: bool) -> bool {
+    match prev {
+        Some(t) if t.kind == TokenKind::Word => spaced && !KEYWORDS.contains(&t.text.as_str()),
+        Some(t) => !matches!(
+            t.kind,
+            TokenKind::Number
+                | TokenKind::Str
+                | TokenKind::Annotation
+                | TokenKind::RParen
+                | TokenKind::RBracket
+                | TokenKind::RBrace
+        ),
+        None => true,
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// End of an identifier starting at `start`, including `.`-separated parts
+/// when `dotted` is set.
+fn word_end(src: &str, start: usize, dotted: bool) -> usize {
+    let mut end = start;
+    loop {
+        end += src[end..]
+            .find(|c: char| !is_ident_char(c))
+            .unwrap_or(src.len() - end);
+        let rest = &src[end..];
+        if dotted
+            && rest.starts_with('.')
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+        {
+            end += 1;
+        } else {
+            return end;
+        }
+    }
+}
+
+fn number_end(src: &str, start: usize) -> usize {
+    let digits = |from: usize| {
+        from + src[from..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(src.len() - from)
+    };
+    let mut end = digits(start);
+    if src[end..].starts_with('.') && src[end + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+        end = digits(end + 1);
+    }
+    if src[end..].starts_with(['e', 'E']) {
+        let mut exp = end + 1;
+        if src[exp..].starts_with(['+', '-']) {
+            exp += 1;
+        }
+        if src[exp..].starts_with(|c: char| c.is_ascii_digit()) {
+            end = digits(exp);
+        }
+    }
+    end
+}
+
+/// The end of the string starting at `start`, in any of its forms. In a
+/// quoted string, values interpolated with `\(...)` are skipped over, and
+/// may contain parentheses and strings.
+fn string_end(src: &str, start: usize) -> Result<usize, SyntaxError> {
+    let unterminated = |msg: &str| SyntaxError {
+        msg: msg.into(),
+        span: start..src.len(),
+    };
+    if let Some(hashes) = raw_hashes(&src[start..]) {
+        let close = format!("\"{}", "#".repeat(hashes));
+        let body = start + hashes + 1;
+        return src[body..]
+            .find(&close)
+            .map(|i| body + i + close.len())
+            .ok_or_else(|| unterminated("unterminated string"));
+    }
+    let multi_line = src[start..]
+        .strip_prefix("\"\"\"")
+        .map(|rest| rest.trim_start_matches([' ', '\t']))
+        .is_some_and(|rest| rest.starts_with(['\n', '\r']));
+    let close = if multi_line { "\"\"\"" } else { "\"" };
+    let mut escaped = false;
+    let mut i = start + close.len();
+    while let Some(c) = src[i..].chars().next() {
+        match c {
+            '(' if escaped => {
+                escaped = false;
+                i = interpolation_end(src, i + 1)
+                    .ok_or_else(|| unterminated("unterminated interpolation"))?;
+                continue;
+            }
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if src[i..].starts_with(close) => return Ok(i + close.len()),
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    Err(unterminated("unterminated string"))
+}
+
+/// The number of hashes before the opening quote if `src` starts with a
+/// raw string such as `##"..."##`.
+fn raw_hashes(src: &str) -> Option<usize> {
+    let hashes = src.len() - src.trim_start_matches('#').len();
+    (hashes > 0 && src[hashes..].starts_with('"')).then_some(hashes)
+}
+
+/// The end of the interpolation whose value starts at `start`, just past
+/// its closing parenthesis.
+fn interpolation_end(src: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while let Some(c) = src[i..].chars().next() {
+        match c {
+            '"' => {
+                i = string_end(src, i).ok()?;
+                continue;
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i + 1),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    None
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_at(&self, n: usize) -> &Token {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)]
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, SyntaxError> {
+        Err(SyntaxError {
+            msg: msg.into(),
+            span: self.peek().span.clone(),
+        })
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token, SyntaxError> {
+        if self.peek().kind == kind {
+            Ok(self.bump())
+        } else {
+            self.error(&format!("expected {}", what))
+        }
+    }
+
+    fn at_key(&self) -> bool {
+        let t = self.peek();
+        let is_key =
+            t.kind == TokenKind::Str || (t.kind == TokenKind::Word && !t.text.contains('.'));
+        let colon = match self.peek_at(1).kind {
+            TokenKind::Question => 2,
+            _ => 1,
+        };
+        is_key && self.peek_at(colon).kind == TokenKind::Colon
+    }
+
+    fn source_file(&mut self) -> Result<SourceFile, SyntaxError> {
+        let mut imports = Vec::new();
+        while self.peek().kind == TokenKind::Word
+            && self.peek().text == "import"
+            && self.peek_at(1).kind == TokenKind::Str
+        {
+            let import = self.bump();
+            let path = self.bump();
+            let as_ = self.expect(TokenKind::Word, "as")?;
+            let alias = self.expect(TokenKind::Word, "an alias")?;
+            imports.push(ImportDecl {
+                import,
+                path,
+                as_,
+                alias,
+            });
+        }
+        let body = if self.at_key() || self.peek().kind == TokenKind::LBrace {
+            let items = self.items(TokenKind::Eof, true)?;
+            match items.as_slice() {
+                [Item { value, comma: None }] if !matches!(value, Expr::Member(_)) => {
+                    Body::Value(value.clone())
+                }
+                _ => Body::Items(items),
+            }
+        } else if self.peek().kind == TokenKind::Eof {
+            Body::Items(Vec::new())
+        } else {
+            Body::Value(self.value()?)
+        };
+        let eof = self.expect(TokenKind::Eof, "end of input")?;
+        Ok(SourceFile { imports, body, eof })
+    }
+
+    /// Items up to (not including) a `close` token. Object items are members;
+    /// the top level also allows inline objects.
+    fn items(&mut self, close: TokenKind, members: bool) -> Result<Vec<Item>, SyntaxError> {
+        let mut items = Vec::new();
+        while self.peek().kind != close {
+            let value = if members && self.at_key() {
+                self.member()?
+            } else if members && close != TokenKind::Eof {
+                return self.error("expected a key");
+            } else {
+                self.value()?
+            };
+            let comma = if self.peek().kind == TokenKind::Comma {
+                Some(self.bump())
+            } else {
+                None
+            };
+            items.push(Item { value, comma });
+        }
+        Ok(items)
+    }
+
+    fn member(&mut self) -> Result<Expr, SyntaxError> {
+        let key = self.bump();
+        let question = if self.peek().kind == TokenKind::Question {
+            Some(self.bump())
+        } else {
+            None
+        };
+        let colon = self.expect(TokenKind::Colon, ":")?;
+        let value = self.value()?;
+        Ok(Expr::Member(Box::new(Member {
+            key,
+            question,
+            colon,
+            value,
+        })))
+    }
+
+    fn container(&mut self, close: TokenKind, members: bool) -> Result<Container, SyntaxError> {
+        let open = self.bump();
+        let items = self.items(close, members)?;
+        let close = self.expect(close, "closing bracket")?;
+        Ok(Container { open, items, close })
+    }
+
+    /// Length bounds such as `{1,5}`. `{,5}` has no minimum, so each number
+    /// and comma is kept as an item of its own.
+    fn bounds(&mut self) -> Result<Container, SyntaxError> {
+        let open = self.bump();
+        let mut items = Vec::new();
+        while matches!(self.peek().kind, TokenKind::Number | TokenKind::Comma) {
+            items.push(Item {
+                value: Expr::Token(self.bump()),
+                comma: None,
+            });
+        }
+        let close = self.expect(TokenKind::RBrace, "closing bracket")?;
+        Ok(Container { open, items, close })
+    }
+
+    fn value(&mut self) -> Result<Expr, SyntaxError> {
+        let first = self.binary(0)?;
+        if self.peek().kind != TokenKind::Pipe {
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        let mut pipes = Vec::new();
+        while self.peek().kind == TokenKind::Pipe {
+            pipes.push(self.bump());
+            items.push(self.binary(0)?);
+        }
+        Ok(Expr::Union(items, pipes))
+    }
+
+    /// Operands joined by operators that bind at least as tightly as
+    /// `min`, grouped to the left. An operator cannot start a line.
+    fn binary(&mut self, min: u8) -> Result<Expr, SyntaxError> {
+        let mut left = self.unary()?;
+        loop {
+            let op = self.peek();
+            let precedence = match precedence(op) {
+                Some(p) if p >= min && !op.starts_line() => p,
+                _ => return Ok(left),
+            };
+            let op = self.bump();
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, SyntaxError> {
+        let t = self.peek();
+        if t.kind == TokenKind::Ellipsis
+            || t.kind == TokenKind::Op
+                && matches!(
+                    t.text.as_str(),
+                    "-" | "*" | "<" | "<=" | ">" | ">=" | "=~" | "!~"
+                )
+        {
+            let minus = self.bump();
+            let operand = self.unary()?;
+            return Ok(Expr::Unary(minus, Box::new(operand)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, SyntaxError> {
+        if self.at_key() {
+            return self.member();
+        }
+        match self.peek().kind {
+            TokenKind::LBrace => Ok(Expr::Object(self.container(TokenKind::RBrace, true)?)),
+            TokenKind::LBracket => {
+                let array = self.container(TokenKind::RBracket, false)?;
+                if self.peek().kind == TokenKind::LBrace && self.peek().leading.is_empty() {
+                    let bounds = self.bounds()?;
+                    return Ok(Expr::List(array, Box::new(bounds)));
+                }
+                Ok(Expr::Array(array))
+            }
+            TokenKind::LParen => Ok(Expr::Group(self.container(TokenKind::RParen, false)?)),
+            TokenKind::Str | TokenKind::Number | TokenKind::Annotation => {
+                Ok(Expr::Token(self.bump()))
+            }
+            TokenKind::Word => {
+                let word = self.bump();
+                if word.text == "String"
+                    && self.peek().kind == TokenKind::LBrace
+                    && self.peek().leading.is_empty()
+                {
+                    let bounds = self.bounds()?;
+                    return Ok(Expr::Sized(word, Box::new(bounds)));
+                }
+                let keyword = KEYWORDS.contains(&word.text.as_str());
+                // `f(a, b)` passes positional arguments.
+                if !keyword
+                    && self.peek().kind == TokenKind::LParen
+                    && self.peek().leading.is_empty()
+                {
+                    let args = self.container(TokenKind::RParen, false)?;
+                    return Ok(Expr::Call(word, Box::new(Expr::Group(args))));
+                }
+                if !keyword && self.at_call_argument() {
+                    let arg = self.value()?;
+                    return Ok(Expr::Call(word, Box::new(arg)));
+                }
+                Ok(Expr::Token(word))
+            }
+            _ => self.error("expected a value"),
+        }
+    }
+
+    /// A reference followed on the same line by a value is a call.
+    fn at_call_argument(&self) -> bool {
+        let t = self.peek();
+        // `f -x` negates the argument; `f - x` subtracts.
+        let negated =
+            t.kind == TokenKind::Op && t.text == "-" && self.peek_at(1).leading.is_empty();
+        !t.leading.is_empty()
+            && !t.starts_line()
+            && (negated
+                || matches!(
+                    t.kind,
+                    TokenKind::Word
+                        | TokenKind::Str
+                        | TokenKind::Number
+                        | TokenKind::Annotation
+                        | TokenKind::LBrace
+                        | TokenKind::LBracket
+                        | TokenKind::LParen
+                ))
+    }
+}
+
+/// How tightly a binary operator binds, if `token` is one.
+fn precedence(token: &Token) -> Option<u8> {
+    if token.kind != TokenKind::Op {
+        return None;
+    }
+    match token.text.as_str() {
+        "&" => Some(0),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => Some(1),
+        "+" | "-" => Some(2),
+        _ => Some(3),
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for t in &self.leading {
+            match t {
+                Trivia::Whitespace(s) | Trivia::Comment(s) => f.write_str(s)?,
+                Trivia::Newline => f.write_str("\n")?,
+            }
+        }
+        f.write_str(&self.text)
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.open)?;
+        for item in &self.items {
+            write!(f, "{}", item)?;
+        }
+        write!(f, "{}", self.close)
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        if let Some(comma) = &self.comma {
+            write!(f, "{}", comma)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Token(t) => write!(f, "{}", t),
+            Expr::Object(c) | Expr::Array(c) | Expr::Group(c) => write!(f, "{}", c),
+            Expr::List(c, bounds) => write!(f, "{}{}", c, bounds),
+            Expr::Sized(t, bounds) => write!(f, "{}{}", t, bounds),
+            Expr::Member(m) => {
+                write!(f, "{}", m.key)?;
+                if let Some(question) = &m.question {
+                    write!(f, "{}", question)?;
+                }
+                write!(f, "{}{}", m.colon, m.value)
+            }
+            Expr::Call(name, arg) => write!(f, "{}{}", name, arg),
+            Expr::Unary(op, operand) => write!(f, "{}{}", op, operand),
+            Expr::Binary(left, op, right) => write!(f, "{}{}{}", left, op, right),
+            Expr::Union(items, pipes) => {
+                write!(f, "{}", items[0])?;
+                for (pipe, item) in pipes.iter().zip(&items[1..]) {
+                    write!(f, "{}{}", pipe, item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Prints the tree back to exactly the source it was parsed from.
+impl fmt::Display for SourceFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for import in &self.imports {
+            write!(
+                f,
+                "{}{}{}{}",
+                import.import, import.path, import.as_, import.alias
+            )?;
+        }
+        match &self.body {
+            Body::Items(items) => {
+                for item in items {
+                    write!(f, "{}", item)?;
+                }
+            }
+            Body::Value(value) => write!(f, "{}", value)?,
+        }
+        write!(f, "{}", self.eof)
+    }
+}
@@ -0,0 +1,253 @@
+use crate::export::{ExportError, ExportErrorKind};
+use crate::loader::LoadError;
+use crate::parser::{INVALID_PATTERN, ParseError};
+use crate::report::SourceMap;
+use crate::types::{SourceId, Span};
+use crate::unify::{Conflict, UnifyError};
+use serde_json::{Value as Json, json};
+
+/// A stable identifier for each kind of problem, for tools that match on
+/// errors rather than messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The source does not parse.
+    Syntax,
+    /// An import could not be loaded.
+    Import,
+    /// Two values for the same field do not unify.
+    Conflict,
+    /// A value does not have the type it is unified with.
+    TypeMismatch,
+    UnresolvedReference,
+    /// A value contains itself.
+    Cycle,
+    UnknownFunction,
+    NotAFunction,
+    /// A function has no `return` member.
+    MissingReturn,
+    /// A call's arguments do not match the function's parameters.
+    Arguments,
+    /// A closed struct is unified with a key it does not have.
+    UnknownField,
+    UnknownOperator,
+    DivisionByZero,
+    /// A native function reported an error.
+    Native,
+    /// A float operation gave a result too large to represent.
+    Overflow,
+    /// Calls and references nest deeper than the limit.
+    DepthLimit,
+    /// Evaluation took more steps than the limit.
+    StepLimit,
+    /// A computed value is larger than the limit.
+    SizeLimit,
+    /// Several files cannot be merged because one is not an object.
+    Merge,
+    /// A value to export is not concrete.
+    Incomplete,
+    /// A value has no representation in the output format.
+    Unrepresentable,
+    /// A `=~` or `!~` regex does not compile.
+    InvalidPattern,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "syntax",
+            ErrorCode::Import => "import",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::TypeMismatch => "type-mismatch",
+            ErrorCode::UnresolvedReference => "unresolved-reference",
+            ErrorCode::Cycle => "cycle",
+            ErrorCode::UnknownFunction => "unknown-function",
+            ErrorCode::NotAFunction => "not-a-function",
+            ErrorCode::MissingReturn => "missing-return",
+            ErrorCode::Arguments => "arguments",
+            ErrorCode::UnknownField => "unknown-field",
+            ErrorCode::UnknownOperator => "unknown-operator",
+            ErrorCode::DivisionByZero => "division-by-zero",
+            ErrorCode::Native => "native",
+            ErrorCode::Overflow => "overflow",
+            ErrorCode::DepthLimit => "depth-limit",
+            ErrorCode::StepLimit => "step-limit",
+            ErrorCode::SizeLimit => "size-limit",
+            ErrorCode::Merge => "merge",
+            ErrorCode::Incomplete => "incomplete",
+            ErrorCode::Unrepresentable => "unrepresentable",
+            ErrorCode::InvalidPattern => "invalid-pattern",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A secondary location that explains a diagnostic, such as the earlier
+/// value in a conflict.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A problem found while loading, unifying or exporting, in a form tools
+/// can place in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    /// The dotted path of the field at fault, if the problem belongs to one.
+    pub path: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            path: None,
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn parse(e: &ParseError) -> Self {
+        let msg = e.to_string();
+        let code = if msg.starts_with(INVALID_PATTERN) {
+            ErrorCode::InvalidPattern
+        } else {
+            ErrorCode::Syntax
+        };
+        let mut d = Diagnostic::error(code, msg, *e.span());
+        for (label, span) in e.contexts() {
+            d = d.with_label(*span, label.to_string());
+        }
+        d
+    }
+
+    pub fn load(err: &LoadError) -> Vec<Self> {
+        match err {
+            LoadError::Parse(errs) => errs.iter().map(Diagnostic::parse).collect(),
+            LoadError::Import { msg, span } => {
+                vec![Diagnostic::error(ErrorCode::Import, msg.clone(), *span)]
+            }
+            LoadError::Alias {
+                alias,
+                span,
+                prev_span,
+            } => vec![
+                Diagnostic::error(
+                    ErrorCode::Import,
+                    format!("import alias {} is already defined", alias),
+                    *span,
+                )
+                .with_label(*prev_span, "previous definition here"),
+            ],
+        }
+    }
+
+    pub fn unify(err: &UnifyError) -> Self {
+        let d = Diagnostic::error(err.code, err.msg.clone(), err.span);
+        if err.prev_span != err.span {
+            d.with_label(err.prev_span, "previous value here")
+        } else {
+            d
+        }
+    }
+
+    pub fn conflict(conflict: &Conflict) -> Self {
+        let d = Diagnostic::unify(&conflict.error);
+        if conflict.path.is_empty() {
+            d
+        } else {
+            d.with_path(conflict.path.clone())
+        }
+    }
+
+    /// A value at `span` that cannot be exported.
+    pub fn export(span: Span, err: &ExportError) -> Self {
+        let code = match err.kind {
+            ExportErrorKind::Unresolved(_) => ErrorCode::Incomplete,
+            ExportErrorKind::NonFinite(_)
+            | ExportErrorKind::Inexact(_)
+            | ExportErrorKind::OutOfRange(_)
+            | ExportErrorKind::Null
+            | ExportErrorKind::TopLevelNotTable
+            | ExportErrorKind::MixedArray => ErrorCode::Unrepresentable,
+        };
+        let d = Diagnostic::error(code, err.to_string(), span);
+        if err.path.is_empty() {
+            d
+        } else {
+            d.with_path(err.path.clone())
+        }
+    }
+
+    /// The diagnostic as JSON. Each span gives its file name and the byte
+    /// offset, 1-based line and 1-based column (in characters) of both ends.
+    pub fn to_json(&self, sources: &SourceMap) -> Json {
+        json!({
+            "severity": self.severity.as_str(),
+            "code": self.code.as_str(),
+            "message": self.message,
+            "path": self.path,
+            "span": span_json(self.span, sources),
+            "labels": self
+                .labels
+                .iter()
+                .map(|l| json!({ "span": span_json(l.span, sources), "message": l.message }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Writes `diagnostics` as a JSON array.
+pub fn to_json_string(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+    Json::Array(diagnostics.iter().map(|d| d.to_json(sources)).collect()).to_string()
+}
+
+fn span_json(span: Span, sources: &SourceMap) -> Json {
+    json!({
+        "file": sources.name(span.context),
+        "start": position_json(sources, span.context, span.start),
+        "end": position_json(sources, span.context, span.end),
+    })
+}
+
+fn position_json(sources: &SourceMap, id: SourceId, offset: usize) -> Json {
+    let before = &sources.text(id)[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    json!({ "offset": offset, "line": line, "column": column })
+}
@@ -0,0 +1,142 @@
+use crate::types::{Annotation, Span, SpannedValue, Value, ValueKind};
+use num_rational::BigRational;
+use std::fmt;
+
+/// How rationals are exported. JSON, YAML and TOML have no exact form for
+/// fractions or for integers beyond 64 bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RationalPolicy {
+    /// Refuse to export them.
+    #[default]
+    Error,
+    /// Write the nearest float.
+    Float,
+    /// Write a string such as `"1/3"`.
+    String,
+}
+
+/// Why a value cannot be exported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportErrorKind {
+    /// A type, reference, call, operation or union was left after
+    /// unification. Holds a description such as `Int` or `call f`.
+    Unresolved(String),
+    /// NaN or an infinity, which JSON has no literal for.
+    NonFinite(f64),
+    /// A rational, under [`RationalPolicy::Error`].
+    Inexact(BigRational),
+    /// A number with no counterpart in the target: a rational beyond the
+    /// range of `f64` under [`RationalPolicy::Float`], or an integer beyond
+    /// 64 bits converted to a `serde_json` value.
+    OutOfRange(BigRational),
+    /// A null, which TOML has no literal for.
+    Null,
+    /// A TOML document whose top level is not an object.
+    TopLevelNotTable,
+    /// A TOML array that mixes tables with other values.
+    MixedArray,
+}
+
+/// A value that cannot be exported, with the path of the offending node in
+/// `a.b[2]` form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportError {
+    pub path: String,
+    pub kind: ExportErrorKind,
+}
+
+impl ExportError {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            ExportErrorKind::Unresolved(what) => format!("value of type {} is unspecified", what),
+            ExportErrorKind::NonFinite(n) => format!("{} is not a finite number", n),
+            ExportErrorKind::Inexact(n) => format!("{} cannot be represented exactly", n),
+            ExportErrorKind::OutOfRange(n) => format!("{} is out of range", n),
+            ExportErrorKind::Null => "null cannot be represented in TOML".into(),
+            ExportErrorKind::TopLevelNotTable => "top-level value must be an object".into(),
+            ExportErrorKind::MixedArray => "array mixes tables and other values".into(),
+        }
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message())
+        } else {
+            write!(f, "{}: {}", self.path, self.message())
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// Describes `value` if it is not concrete enough to export.
+pub(crate) fn unresolved(value: &Value) -> Option<String> {
+    match value {
+        Value::Reference(r) => Some(format!("reference {}", r)),
+        Value::Type(t) => Some(t.to_string()),
+        Value::Call(name, _) => Some(format!("call {}", name)),
+        Value::Arguments(_) => Some("arguments".into()),
+        Value::List(_) => Some("list".into()),
+        Value::OpCall(op, _, _) => Some(format!("op {}", op)),
+        Value::Interpolation(_) => Some("interpolation".into()),
+        Value::Union(_) => Some("union".into()),
+        Value::Default(v) => unresolved(v),
+        _ => None,
+    }
+}
+
+pub(crate) fn member_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Whether a member with `value` and `anns` is exported: it is not marked
+/// `@NoExport` or `@Function`, and if optional it has a concrete value.
+pub fn is_exported(value: &SpannedValue, anns: &[Annotation]) -> bool {
+    !anns.contains(&Annotation::NoExport)
+        && !anns.contains(&Annotation::Function)
+        && (!anns.contains(&Annotation::Optional) || find_unresolved(value).is_none())
+}
+
+/// Finds the first value that would be exported but is not concrete,
+/// skipping members that are not exported. For a union, an unresolved
+/// alternative is reported in preference to the union itself, and likewise
+/// for the parts of an interpolation.
+pub fn find_unresolved(value: &SpannedValue) -> Option<(Span, ExportError)> {
+    find_unresolved_at(value, "")
+}
+
+fn find_unresolved_at(value: &SpannedValue, path: &str) -> Option<(Span, ExportError)> {
+    match &value.kind {
+        ValueKind::Array(items) => items
+            .iter()
+            .enumerate()
+            .find_map(|(i, item)| find_unresolved_at(item, &format!("{}[{}]", path, i))),
+        ValueKind::Object(members) => members
+            .iter()
+            .filter(|(_, v, _, anns)| is_exported(v, anns))
+            .find_map(|(k, v, _, _)| find_unresolved_at(v, &member_path(path, k))),
+        ValueKind::Default(v) => find_unresolved_at(v, path),
+        ValueKind::Union(items) | ValueKind::Interpolation(items) => items
+            .iter()
+            .find_map(|item| find_unresolved_at(item, path))
+            .or_else(|| unresolved_at(value, path)),
+        _ => unresolved_at(value, path),
+    }
+}
+
+fn unresolved_at(value: &SpannedValue, path: &str) -> Option<(Span, ExportError)> {
+    let what = unresolved(&value.to_value())?;
+    Some((
+        value.span,
+        ExportError {
+            path: path.to_string(),
+            kind: ExportErrorKind::Unresolved(what),
+        },
+    ))
+}
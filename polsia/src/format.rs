@@ -0,0 +1,324 @@
+use crate::cst::{self, Body, Container, Expr, Item, Token, Trivia};
+use crate::parser::{ParseError, document, input};
+use crate::types::{SourceId, Span};
+use chumsky::prelude::*;
+
+/// How commas are written between the items of a multi-line object or
+/// array. Items on a single line are always separated by `, `.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommaStyle {
+    /// Keep each comma where it was written.
+    #[default]
+    Preserve,
+    /// No commas between object members; arrays get commas between items.
+    Omit,
+    /// Commas between items, none after the last.
+    Separate,
+    /// A comma after every item, including the last.
+    Trailing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// One level of indentation, e.g. two spaces or a tab.
+    pub indent: String,
+    pub commas: CommaStyle,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: "  ".into(),
+            commas: CommaStyle::default(),
+        }
+    }
+}
+
+/// Formats a Polsia file. Comments, the order of items, a brace-less top
+/// level and `foo: bar: baz` chains are kept. Objects and arrays that were
+/// written on one line stay on one line; the rest get one item per line.
+/// Runs of blank lines become one. Formatting is idempotent.
+pub fn format_source<'a>(
+    src: &'a str,
+    options: &FormatOptions,
+) -> Result<String, Vec<ParseError<'a>>> {
+    document()
+        .parse(input(src, SourceId::default()))
+        .into_result()?;
+    let file = cst::parse(src)
+        .map_err(|e| vec![Rich::custom(Span::new(SourceId::default(), e.span), e.msg)])?;
+    let mut w = Writer {
+        out: String::new(),
+        options,
+        depth: 0,
+        skip_trivia: false,
+        line_has_content: false,
+        at_block_start: true,
+    };
+    for import in &file.imports {
+        w.item_trivia(&import.import.leading, true);
+        w.skip_trivia = true;
+        w.token(&import.import, false);
+        w.token(&import.path, true);
+        w.token(&import.as_, true);
+        w.token(&import.alias, true);
+    }
+    match &file.body {
+        Body::Items(items) => {
+            w.at_block_start = file.imports.is_empty();
+            w.items(items, &file.eof, false);
+        }
+        Body::Value(value) => {
+            w.item_trivia(&value.first_token().leading, true);
+            w.skip_trivia = true;
+            w.expr(value, false);
+            w.item_trivia(&file.eof.leading, false);
+        }
+    }
+    w.newline();
+    Ok(w.out)
+}
+
+struct Writer<'a> {
+    out: String,
+    options: &'a FormatOptions,
+    depth: usize,
+    /// Set when the caller already wrote the next token's trivia.
+    skip_trivia: bool,
+    /// Whether anything but indentation is on the current line.
+    line_has_content: bool,
+    /// Whether nothing has been written since the start of the file or of
+    /// the current multi-line block.
+    at_block_start: bool,
+}
+
+impl Writer<'_> {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.line_has_content = true;
+    }
+
+    /// Ends the current line, if it has anything on it.
+    fn newline(&mut self) {
+        if !self.line_has_content {
+            return;
+        }
+        self.out
+            .truncate(self.out.trim_end_matches([' ', '\t']).len());
+        self.out.push('\n');
+        self.line_has_content = false;
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(&self.options.indent);
+        }
+    }
+
+    /// Writes the comments and blank lines before an item, or before a
+    /// closing bracket if not `before_item`. A comment on the same line as
+    /// the previous item stays there. Blank lines at the start or end of a
+    /// block are dropped.
+    fn item_trivia(&mut self, trivia: &[Trivia], before_item: bool) {
+        let mut newlines = 0;
+        let mut first = std::mem::take(&mut self.at_block_start);
+        for t in trivia {
+            match t {
+                Trivia::Newline => newlines += 1,
+                Trivia::Whitespace(_) => {}
+                Trivia::Comment(c) => {
+                    if newlines == 0 && self.line_has_content {
+                        self.push(" ");
+                    } else {
+                        if newlines > 1 && !first {
+                            self.blank_line();
+                        }
+                        self.newline();
+                        self.indent();
+                    }
+                    self.push(c);
+                    first = false;
+                    newlines = 0;
+                }
+            }
+        }
+        if before_item && newlines > 1 && !first {
+            self.blank_line();
+        }
+        self.newline();
+    }
+
+    /// Writes the comments before a token inside an expression. Returns
+    /// whether the token now starts a new line.
+    fn inline_trivia(&mut self, trivia: &[Trivia]) -> bool {
+        let mut newlines = 0;
+        let mut wrote = false;
+        for t in trivia {
+            match t {
+                Trivia::Newline => newlines += 1,
+                Trivia::Whitespace(_) => {}
+                Trivia::Comment(c) => {
+                    if newlines == 0 && self.line_has_content {
+                        self.push(" ");
+                    } else {
+                        self.newline();
+                        self.indent();
+                        self.out.push_str(&self.options.indent);
+                    }
+                    self.push(c);
+                    wrote = true;
+                    newlines = 0;
+                }
+            }
+        }
+        if wrote {
+            self.newline();
+        }
+        wrote
+    }
+
+    fn token(&mut self, token: &Token, space: bool) {
+        let skip = std::mem::take(&mut self.skip_trivia);
+        let broke = !skip && self.inline_trivia(&token.leading);
+        if space && !broke {
+            self.push(" ");
+        }
+        if !self.line_has_content {
+            self.indent();
+            if broke {
+                // Continuation of an expression interrupted by a comment.
+                self.out.push_str(&self.options.indent);
+            }
+        }
+        self.push(&token.text);
+    }
+
+    /// Length bounds such as `{1,}`, written without spaces.
+    fn bounds(&mut self, bounds: &Container) {
+        self.token(&bounds.open, false);
+        for item in &bounds.items {
+            self.expr(&item.value, false);
+            if item.comma.is_some() {
+                self.push(",");
+            }
+        }
+        self.token(&bounds.close, false);
+    }
+
+    fn expr(&mut self, expr: &Expr, space: bool) {
+        match expr {
+            Expr::Token(t) => self.token(t, space),
+            Expr::Object(c) => self.container(c, space, false),
+            Expr::Array(c) => self.container(c, space, true),
+            Expr::List(c, bounds) => {
+                self.container(c, space, true);
+                self.bounds(bounds);
+            }
+            Expr::Sized(t, bounds) => {
+                self.token(t, space);
+                self.bounds(bounds);
+            }
+            Expr::Group(c) => {
+                self.token(&c.open, space);
+                for item in &c.items {
+                    self.expr(&item.value, false);
+                }
+                self.token(&c.close, false);
+            }
+            Expr::Member(m) => {
+                self.token(&m.key, space);
+                if let Some(question) = &m.question {
+                    self.token(question, false);
+                }
+                self.token(&m.colon, false);
+                self.expr(&m.value, true);
+            }
+            Expr::Call(name, arg) => {
+                self.token(name, space);
+                match arg.as_ref() {
+                    Expr::Group(args) if args.open.leading.is_empty() => {
+                        self.container(args, false, true)
+                    }
+                    _ => self.expr(arg, true),
+                }
+            }
+            Expr::Unary(op, operand) => {
+                self.token(op, space);
+                self.expr(operand, false);
+            }
+            Expr::Binary(left, op, right) => {
+                self.expr(left, space);
+                self.token(op, true);
+                self.expr(right, true);
+            }
+            Expr::Union(items, pipes) => {
+                self.expr(&items[0], space);
+                for (pipe, item) in pipes.iter().zip(&items[1..]) {
+                    self.token(pipe, true);
+                    self.expr(item, true);
+                }
+            }
+        }
+    }
+
+    fn container(&mut self, c: &Container, space: bool, array: bool) {
+        let multiline = c.close.has_comments()
+            || !c.items.is_empty() && c.close.starts_line()
+            || c.items.iter().any(|item| {
+                item.value.first_token().starts_line()
+                    || item.comma.as_ref().is_some_and(Token::starts_line)
+            });
+        self.token(&c.open, space);
+        if multiline {
+            self.at_block_start = true;
+            self.depth += 1;
+            self.items(&c.items, &c.close, array);
+            self.depth -= 1;
+            self.indent();
+            self.push(&c.close.text);
+        } else {
+            let padded = !array && !c.items.is_empty();
+            for (i, item) in c.items.iter().enumerate() {
+                if i > 0 {
+                    self.push(",");
+                }
+                self.expr(&item.value, padded || i > 0);
+            }
+            self.token(&c.close, padded);
+        }
+    }
+
+    /// Writes `items` one per line, followed by the trivia before `close`.
+    fn items(&mut self, items: &[Item], close: &Token, array: bool) {
+        let mut pending: Vec<Trivia> = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            pending.extend(item.value.first_token().leading.iter().cloned());
+            self.item_trivia(&pending, true);
+            pending.clear();
+            self.skip_trivia = true;
+            self.expr(&item.value, false);
+            let last = i + 1 == items.len();
+            let comma = match self.options.commas {
+                CommaStyle::Trailing => true,
+                CommaStyle::Separate => !last,
+                CommaStyle::Omit => array && !last,
+                CommaStyle::Preserve => item.comma.is_some(),
+            };
+            if comma {
+                self.push(",");
+            }
+            if let Some(c) = &item.comma {
+                pending.extend(c.leading.iter().cloned());
+            }
+        }
+        pending.extend(close.leading.iter().cloned());
+        self.item_trivia(&pending, false);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::export::{self, ExportError, ExportErrorKind};
+use crate::types::{Value, to_float};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use serde_json::Number;
+
+/// Renders `value` as compact JSON. Object keys keep their order, and
+/// integers of any size are written digit for digit. Fails on values that
+/// are not concrete.
+pub fn to_string(value: &Value) -> Result<String, ExportError> {
+    let mut out = String::new();
+    write(&mut out, value, None, "")?;
+    Ok(out)
+}
+
+/// Renders `value` as JSON indented by two spaces, in the layout of
+/// `serde_json::to_string_pretty`.
+pub fn to_string_pretty(value: &Value) -> Result<String, ExportError> {
+    let mut out = String::new();
+    write(&mut out, value, Some(0), "")?;
+    Ok(out)
+}
+
+/// Reads the JSON text `s`. Integers of any size are read exactly, as an
+/// `Int` or a `Rational`; other numbers become floats. Of repeated keys the
+/// last one wins.
+pub fn from_str(s: &str) -> Result<Value, serde_json::Error> {
+    // `serde_json` checks the syntax, but reads integers beyond 64 bits as
+    // the nearest float, so the value itself is read from the text.
+    serde_json::from_str::<serde_json::Value>(s)?;
+    let mut reader = Reader { s, pos: 0 };
+    Ok(reader.value())
+}
+
+/// Reads values from JSON text already known to be valid.
+struct Reader<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&mut self) -> u8 {
+        let rest = &self.s.as_bytes()[self.pos..];
+        let ws = rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        self.pos += ws;
+        self.s.as_bytes()[self.pos]
+    }
+
+    /// Skips `n` bytes, returning them.
+    fn take(&mut self, n: usize) -> &str {
+        self.pos += n;
+        &self.s[self.pos - n..self.pos]
+    }
+
+    fn value(&mut self) -> Value {
+        match self.peek() {
+            b'n' => {
+                self.take(4);
+                Value::Null
+            }
+            b't' => {
+                self.take(4);
+                Value::Bool(true)
+            }
+            b'f' => {
+                self.take(5);
+                Value::Bool(false)
+            }
+            b'"' => Value::String(self.string()),
+            b'[' => {
+                self.take(1);
+                let mut items = Vec::new();
+                while self.peek() != b']' {
+                    items.push(self.value());
+                    if self.peek() == b',' {
+                        self.take(1);
+                    }
+                }
+                self.take(1);
+                Value::Array(items)
+            }
+            b'{' => {
+                self.take(1);
+                let mut members: Vec<(String, Value)> = Vec::new();
+                while self.peek() != b'}' {
+                    let key = self.string();
+                    self.peek();
+                    self.take(1);
+                    let value = self.value();
+                    match members.iter_mut().find(|(k, _)| *k == key) {
+                        Some(member) => member.1 = value,
+                        None => members.push((key, value)),
+                    }
+                    if self.peek() == b',' {
+                        self.take(1);
+                    }
+                }
+                self.take(1);
+                Value::Object(members)
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn string(&mut self) -> String {
+        self.peek();
+        let bytes = &self.s.as_bytes()[self.pos..];
+        let mut end = 1;
+        while bytes[end] != b'"' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        serde_json::from_str(self.take(end + 1)).unwrap()
+    }
+
+    fn number(&mut self) -> Value {
+        let len = self.s[self.pos..]
+            .bytes()
+            .take_while(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+            .count();
+        let text = self.take(len);
+        match text.parse::<BigInt>() {
+            Ok(n) => match i64::try_from(&n) {
+                Ok(i) => Value::Int(i),
+                Err(_) => Value::Rational(BigRational::from_integer(n)),
+            },
+            Err(_) => Value::Float(text.parse().unwrap()),
+        }
+    }
+}
+
+/// Writes `value` at `indent`, or on one line if `indent` is `None`.
+fn write(
+    out: &mut String,
+    value: &Value,
+    indent: Option<usize>,
+    path: &str,
+) -> Result<(), ExportError> {
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                separate(out, i, indent);
+                write(
+                    out,
+                    item,
+                    indent.map(|n| n + 1),
+                    &format!("{}[{}]", path, i),
+                )?;
+            }
+            close(out, indent, ']');
+        }
+        Value::Object(members) if !members.is_empty() => {
+            out.push('{');
+            for (i, (k, v)) in members.iter().enumerate() {
+                separate(out, i, indent);
+                out.push_str(&serde_json::to_string(k).unwrap());
+                out.push_str(if indent.is_some() { ": " } else { ":" });
+                write(out, v, indent.map(|n| n + 1), &export::member_path(path, k))?;
+            }
+            close(out, indent, '}');
+        }
+        _ => out.push_str(&scalar(value, path)?),
+    }
+    Ok(())
+}
+
+fn separate(out: &mut String, i: usize, indent: Option<usize>) {
+    if i > 0 {
+        out.push(',');
+    }
+    if let Some(n) = indent {
+        out.push('\n');
+        out.push_str(&"  ".repeat(n + 1));
+    }
+}
+
+fn close(out: &mut String, indent: Option<usize>, bracket: char) {
+    if let Some(n) = indent {
+        out.push('\n');
+        out.push_str(&"  ".repeat(n));
+    }
+    out.push(bracket);
+}
+
+fn scalar(value: &Value, path: &str) -> Result<String, ExportError> {
+    let error = |kind| ExportError {
+        path: path.to_string(),
+        kind,
+    };
+    Ok(match value {
+        Value::Null => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Float(n) => match Number::from_f64(*n) {
+            Some(n) => n.to_string(),
+            None => return Err(error(ExportErrorKind::NonFinite(*n))),
+        },
+        Value::Decimal(n) => return scalar(&Value::Float(to_float(n)), path),
+        Value::Rational(n) if n.is_integer() => n.numer().to_string(),
+        Value::Rational(n) => return Err(error(ExportErrorKind::Inexact(n.clone()))),
+        Value::String(s) => serde_json::to_string(s).unwrap(),
+        Value::Array(_) => "[]".into(),
+        Value::Object(_) => "{}".into(),
+        other => {
+            return Err(error(ExportErrorKind::Unresolved(
+                export::unresolved(other).unwrap_or_default(),
+            )));
+        }
+    })
+}
@@ -1,148 +1,114 @@
+pub mod cst;
+pub mod diagnostic;
+pub mod export;
+pub mod format;
+pub mod json;
+pub mod loader;
+pub mod lsp;
+pub mod native;
 pub mod parser;
+pub mod printer;
+pub mod report;
+mod stdlib;
+pub mod toml;
 pub mod types;
 pub mod unify;
+pub mod yaml;
 
-pub use parser::{document, parser};
-pub use types::{Annotation, Document, SpannedValue, ValType, Value, ValueKind};
-pub use unify::{UnifyError, unify_spanned, unify_tree};
+pub use diagnostic::Diagnostic;
+pub use loader::{FsLoader, LoadError, Loader, MemoryLoader, resolve_imports};
+pub use native::{NativeFunction, Natives};
+pub use parser::{document, input, parser};
+pub use report::SourceMap;
+pub use types::{Annotation, Document, SourceId, SpannedValue, ValType, Value, ValueKind};
+pub use unify::{
+    Conflict, Limits, UnifyError, merge_documents, unify_spanned, unify_tree,
+    unify_tree_collecting, unify_tree_collecting_with_limits, unify_tree_collecting_with_natives,
+    unify_tree_with_limits, unify_tree_with_natives,
+};
 
-use crate::types::Span;
-use ariadne::{Color, Config, Label, Report, ReportKind, sources};
+use crate::export::find_unresolved;
+use ariadne::{Config, Report};
 use chumsky::prelude::*;
 
 pub fn parse_to_json(src: &str) -> Result<String, String> {
-    let filename = "input".to_string();
-    let parse_result = document().parse(src).into_result();
-    match parse_result {
-        Ok(doc) => match unify_tree(&doc.value) {
-            Ok(value) => match find_unresolved(&value) {
-                Some((span, t)) => {
-                    let msg = format!("value of type {} is unspecified", t);
-                    let span_range = span.into_range();
-                    let mut buf = Vec::new();
-                    Report::build(ReportKind::Error, (filename.clone(), span_range.clone()))
-                        .with_config(Config::default().with_color(false))
-                        .with_message(&msg)
-                        .with_label(
-                            Label::new((filename.clone(), span_range))
-                                .with_message(&msg)
-                                .with_color(Color::Red),
-                        )
-                        .finish()
-                        .write(sources([(filename.clone(), src)]), &mut buf)
-                        .unwrap();
-                    Err(String::from_utf8(buf).unwrap())
-                }
-                None => Ok(value.to_value().to_pretty_string()),
-            },
-            Err(err) => {
-                use chumsky::error::LabelError;
-                let mut e = Rich::custom(err.span, err.msg.clone());
-                <Rich<_> as LabelError<&str, _>>::in_context(
-                    &mut e,
-                    "previous value here",
-                    err.prev_span,
-                );
-                let span = (*e.span()).into_range();
-                let msg = e.to_string();
-                let mut buf = Vec::new();
-                Report::build(ReportKind::Error, (filename.clone(), span.clone()))
-                    .with_config(Config::default().with_color(false))
-                    .with_message(&msg)
-                    .with_label(
-                        Label::new((filename.clone(), span.clone()))
-                            .with_message(&msg)
-                            .with_color(Color::Red),
-                    )
-                    .with_labels(e.contexts().map(|(label, span)| {
-                        Label::new((filename.clone(), span.into_range()))
-                            .with_message(label.to_string())
-                            .with_color(Color::Yellow)
-                    }))
-                    .finish()
-                    .write(sources([(filename.clone(), src)]), &mut buf)
-                    .unwrap();
-                Err(String::from_utf8(buf).unwrap())
-            }
-        },
-        Err(errs) => {
-            let mut out = String::new();
-            for e in errs {
-                let span = (*e.span()).into_range();
-                let msg = e.to_string();
-                let mut buf = Vec::new();
-                Report::build(ReportKind::Error, (filename.clone(), span.clone()))
-                    .with_config(Config::default().with_color(false))
-                    .with_message(&msg)
-                    .with_label(
-                        Label::new((filename.clone(), span.clone()))
-                            .with_message(&msg)
-                            .with_color(Color::Red),
-                    )
-                    .with_labels(e.contexts().map(|(label, span)| {
-                        Label::new((filename.clone(), span.into_range()))
-                            .with_message(label.to_string())
-                            .with_color(Color::Yellow)
-                    }))
-                    .finish()
-                    .write(sources([(filename.clone(), src)]), &mut buf)
-                    .unwrap();
-                out.push_str(&String::from_utf8(buf).unwrap());
-            }
-            Err(out)
-        }
+    parse_to_json_with_loader(src, &MemoryLoader::new())
+}
+
+/// Like [`parse_to_json`], resolving imports through `loader`. Import paths
+/// are relative to a file named `input`.
+pub fn parse_to_json_with_loader(src: &str, loader: &dyn Loader) -> Result<String, String> {
+    let mut sources = SourceMap::new();
+    let config = Config::default().with_color(false);
+    parse_to_json_with_diagnostics(src, loader, &mut sources).map_err(|diagnostics| {
+        render(
+            &sources,
+            diagnostics
+                .iter()
+                .map(|d| report::diagnostic_report(d, &sources, config)),
+        )
+    })
+}
+
+/// Like [`parse_to_json_with_loader`], but reports problems as structured
+/// [`Diagnostic`]s. `src` is added to `sources` as `input`, followed by the
+/// files it imports, so the diagnostics' spans can be looked up there.
+pub fn parse_to_json_with_diagnostics(
+    src: &str,
+    loader: &dyn Loader,
+    sources: &mut SourceMap,
+) -> Result<String, Vec<Diagnostic>> {
+    let id = sources.add("input", src);
+    let doc = document()
+        .parse(input(sources.text(id), id))
+        .into_result()
+        .map_err(|errs| errs.iter().map(Diagnostic::parse).collect::<Vec<_>>())?;
+    let doc =
+        resolve_imports(doc, "input", loader, sources).map_err(|err| Diagnostic::load(&err))?;
+    let (value, conflicts) = unify_tree_collecting(&doc.value);
+    if !conflicts.is_empty() {
+        return Err(conflicts
+            .iter()
+            .filter(|c| !c.consequential)
+            .map(Diagnostic::conflict)
+            .collect());
     }
+    if let Some((span, err)) = find_unresolved(&value) {
+        return Err(vec![Diagnostic::export(span, &err)]);
+    }
+    value.to_value().to_pretty_string().map_err(|err| {
+        let span = value.select(&err.path).map_or(value.span, |v| v.span);
+        vec![Diagnostic::export(span, &err)]
+    })
 }
 
-fn find_unresolved(value: &SpannedValue) -> Option<(Span, String)> {
-    match &value.kind {
-        ValueKind::Reference(p) => Some((value.span, format!("reference {}", p))),
-        ValueKind::Type(t) => Some((value.span, format!("{:?}", t))),
-        ValueKind::Call(name, _) => Some((value.span, format!("call {}", name))),
-        ValueKind::OpCall(op, _, _) => Some((value.span, format!("op {}", op))),
-        ValueKind::Union(items) => {
-            for item in items {
-                if let Some(res) = find_unresolved(item) {
-                    return Some(res);
-                }
-            }
-            Some((value.span, "union".into()))
-        }
-        ValueKind::Array(items) => {
-            for item in items {
-                if let Some(res) = find_unresolved(item) {
-                    return Some(res);
-                }
-            }
-            None
-        }
-        ValueKind::Object(members) => {
-            for (_, v, _, anns) in members {
-                if anns.contains(&Annotation::NoExport) || anns.contains(&Annotation::Function) {
-                    continue;
-                }
-                if let Some(res) = find_unresolved(v) {
-                    return Some(res);
-                }
-            }
-            None
-        }
-        _ => None,
+fn render(
+    sources: &SourceMap,
+    reports: impl IntoIterator<Item = Report<'static, report::ReportSpan>>,
+) -> String {
+    let mut buf = Vec::new();
+    for report in reports {
+        report.write(sources.cache(), &mut buf).unwrap();
     }
+    String::from_utf8(buf).unwrap()
 }
 
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
 #[cfg(feature = "wasm")]
-pub use wasm::polsia_to_json;
+pub use wasm::{polsia_to_json, polsia_to_json_with_diagnostics, polsia_to_json_with_files};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::Span;
 
     fn parse_unify(src: &str) -> Result<SpannedValue, UnifyError> {
-        let parsed = parser().parse(src).into_result().unwrap();
+        let parsed = parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         unify_tree(&parsed)
     }
 
@@ -156,7 +122,7 @@ mod tests {
 
     fn span_value(value: Value) -> SpannedValue {
         use Value::*;
-        let span = SimpleSpan::new((), 0..0);
+        let span = Span::new(SourceId::default(), 0..0);
         SpannedValue {
             span,
             kind: match value {
@@ -164,6 +130,8 @@ mod tests {
                 Bool(b) => ValueKind::Bool(b),
                 Int(n) => ValueKind::Int(n),
                 Float(n) => ValueKind::Float(n),
+                Rational(n) => ValueKind::Rational(n),
+                Decimal(n) => ValueKind::Decimal(n),
                 String(s) => ValueKind::String(s),
                 Array(items) => ValueKind::Array(items.into_iter().map(span_value).collect()),
                 Object(members) => ValueKind::Object(
@@ -175,12 +143,20 @@ mod tests {
                 Reference(r) => ValueKind::Reference(r),
                 Type(t) => ValueKind::Type(t),
                 Call(name, arg) => ValueKind::Call(name, Box::new(span_value(*arg))),
+                Arguments(items) => {
+                    ValueKind::Arguments(items.into_iter().map(span_value).collect())
+                }
+                List(list) => ValueKind::List(list.map(|v| span_value(v.clone()))),
                 OpCall(op, left, right) => ValueKind::OpCall(
                     op,
                     Box::new(span_value(*left)),
                     Box::new(span_value(*right)),
                 ),
+                Interpolation(parts) => {
+                    ValueKind::Interpolation(parts.into_iter().map(span_value).collect())
+                }
                 Union(items) => ValueKind::Union(items.into_iter().map(span_value).collect()),
+                Default(v) => ValueKind::Default(Box::new(span_value(*v))),
             },
         }
     }
@@ -347,7 +323,7 @@ mod tests {
             Err(err) => {
                 use chumsky::error::LabelError;
                 let mut e = Rich::custom(err.span, err.msg.clone());
-                <Rich<_> as LabelError<&str, _>>::in_context(
+                <Rich<_, _> as LabelError<parser::ParserInput, _>>::in_context(
                     &mut e,
                     "previous value here",
                     err.prev_span,
@@ -377,7 +353,7 @@ mod tests {
             Err(err) => {
                 use chumsky::error::LabelError;
                 let mut e = Rich::custom(err.span, err.msg.clone());
-                <Rich<_> as LabelError<&str, _>>::in_context(
+                <Rich<_, _> as LabelError<parser::ParserInput, _>>::in_context(
                     &mut e,
                     "previous value here",
                     err.prev_span,
@@ -427,7 +403,7 @@ mod tests {
     fn single_key_chain_without_braces() {
         let src = "foo: bar: baz: 1";
         let expected = parser()
-            .parse("foo: { bar: { baz: 1 } }")
+            .parse(input("foo: { bar: { baz: 1 } }", SourceId::default()))
             .into_result()
             .unwrap();
         let expected = unify_tree(&expected).unwrap();
@@ -736,6 +712,7 @@ foo: baz: "hello"
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn parse_int_and_float_values() {
         let src = "my_int: 1\nmy_float: 3.1415";
         let unified = must_unify(src);
@@ -775,7 +752,7 @@ my_float: 3.1415";
     fn int_exports_without_decimal() {
         let src = "1";
         let unified = must_unify(src);
-        assert_eq!(unified.to_value().to_pretty_string(), "1");
+        assert_eq!(unified.to_value().to_pretty_string().unwrap(), "1");
     }
 
     #[test]
@@ -786,14 +763,17 @@ my_float: 3.1415";
                 continue;
             }
             let src = std::fs::read_to_string(&path).unwrap();
-            let json = parse_to_json(&src).unwrap();
+            parse_to_json(&src).unwrap();
         }
     }
 
     #[test]
     fn noexport_removes_field() {
         let src = "foo: 1\nbar: 2\nbar: @NoExport";
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         assert_eq!(
             unified.to_value(),
@@ -815,7 +795,10 @@ my_float: 3.1415";
             forest: name: "forest"
             forest: age: 4
         "#;
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         assert_eq!(
             unified.to_value(),
@@ -843,7 +826,10 @@ my_float: 3.1415";
             baz: FooOrBar
             baz: bar: ""
         "#;
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         assert_eq!(
             unified.to_value(),
@@ -863,7 +849,10 @@ credentials: {
   password: Nothing
 }
 "#;
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         assert_eq!(
             unified.to_value(),
@@ -883,7 +872,10 @@ credentials: {
   password: Nothing
 }
 "#;
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         assert_eq!(
             unified.to_value(),
@@ -1093,7 +1085,10 @@ Pet: {
 
 pet: Pet
 "#;
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         match &unified.kind {
             ValueKind::Object(members) => {
@@ -1135,7 +1130,10 @@ pet: Pet
 pet: species: "cat"
 pet: says: "meow"
 "#;
-        let doc = document().parse(src).into_result().unwrap();
+        let doc = document()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
         let unified = unify_tree(&doc.value).unwrap();
         assert_eq!(
             unified.to_value(),
@@ -1218,6 +1216,2695 @@ foo: bar: baz: "hello, world"
         assert!(err.contains("Int"));
     }
 
+    fn parse_sources(files: &[(&str, &str)]) -> (SourceMap, Vec<Document>) {
+        let mut sources = SourceMap::new();
+        for (name, src) in files {
+            sources.add(*name, *src);
+        }
+        let docs = sources
+            .iter()
+            .map(|(id, _, src)| document().parse(input(src, id)).into_result().unwrap())
+            .collect();
+        (sources, docs)
+    }
+
+    #[test]
+    fn multiple_files_merge_top_level_objects() {
+        let (_, docs) = parse_sources(&[
+            (
+                "base.pls",
+                "Service: @NoExport\nService: { name: String, port: Int }\nweb: Service",
+            ),
+            ("env.pls", "web: port: 8080"),
+            ("team.pls", "web: name: \"frontend\""),
+        ]);
+        let unified = unify_tree(&merge_documents(&docs).unwrap()).unwrap();
+        assert_eq!(
+            unified.to_value(),
+            Value::Object(vec![(
+                "web".into(),
+                Value::Object(vec![
+                    ("name".into(), Value::String("frontend".into())),
+                    ("port".into(), Value::Int(8080)),
+                ]),
+            )])
+        );
+    }
+
+    #[test]
+    fn multiple_files_error_names_both_files() {
+        let (sources, docs) = parse_sources(&[("a.pls", "port: 80"), ("b.pls", "\nport: 443")]);
+        let err = unify_tree(&merge_documents(&docs).unwrap()).unwrap_err();
+        assert_eq!(sources.name(err.span.context), "b.pls");
+        assert_eq!(sources.name(err.prev_span.context), "a.pls");
+        let mut buf = Vec::new();
+        report::unify_error_report(&err, &sources, ariadne::Config::default().with_color(false))
+            .write(sources.cache(), &mut buf)
+            .unwrap();
+        let rendered = String::from_utf8(buf).unwrap();
+        assert!(rendered.contains("a.pls"));
+        assert!(rendered.contains("b.pls"));
+    }
+
+    #[test]
+    fn multiple_files_require_objects() {
+        let (sources, docs) = parse_sources(&[("a.pls", "port: 80"), ("b.pls", "[1, 2]")]);
+        let err = merge_documents(&docs).unwrap_err();
+        assert_eq!(sources.name(err.span.context), "b.pls");
+    }
+
+    fn load_with(src: &str, loader: &MemoryLoader) -> Result<Document, LoadError> {
+        let mut sources = SourceMap::new();
+        let id = sources.add("input", src);
+        let doc = document().parse(input(src, id)).into_result().unwrap();
+        resolve_imports(doc, "input", loader, &mut sources)
+    }
+
+    #[test]
+    fn import_binds_other_file() {
+        let loader: MemoryLoader = [(
+            "lib/animals.pls",
+            "Dog: @NoExport\nDog: { species: \"dog\", says: \"bark\" }",
+        )]
+        .into_iter()
+        .collect();
+        let src = "import \"lib/animals.pls\" as lib\npluto: lib.Dog\npluto: planet: false";
+        let doc = load_with(src, &loader).unwrap();
+        let unified = unify_tree(&doc.value).unwrap();
+        assert_eq!(
+            unified.to_value(),
+            Value::Object(vec![(
+                "pluto".into(),
+                Value::Object(vec![
+                    ("planet".into(), Value::Bool(false)),
+                    ("says".into(), Value::String("bark".into())),
+                    ("species".into(), Value::String("dog".into())),
+                ]),
+            )])
+        );
+    }
+
+    #[test]
+    fn import_paths_are_relative_to_importer() {
+        let loader: MemoryLoader = [
+            (
+                "lib/pets.pls",
+                "import \"../base/types.pls\" as base\nPet: base.Named",
+            ),
+            ("base/types.pls", "Named: name: String"),
+        ]
+        .into_iter()
+        .collect();
+        let src = "import \"./lib/pets.pls\" as pets\ncat: pets.Pet\ncat: name: \"forest\"";
+        let json = parse_to_json_with_loader(src, &loader).unwrap();
+        assert!(json.contains("forest"));
+        assert!(!json.contains("Named"));
+    }
+
+    #[test]
+    fn import_cycle_reports_chain() {
+        let loader: MemoryLoader = [
+            ("a.pls", "import \"b.pls\" as b\nfoo: 1"),
+            ("b.pls", "import \"a.pls\" as a\nbar: 2"),
+        ]
+        .into_iter()
+        .collect();
+        match load_with("import \"a.pls\" as a\nbaz: 3", &loader) {
+            Err(LoadError::Import { msg, .. }) => {
+                assert_eq!(msg, "import cycle: a.pls -> b.pls -> a.pls")
+            }
+            other => panic!("expected import cycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn import_alias_must_not_collide() {
+        let loader: MemoryLoader = [("lib.pls", "Dog: 1"), ("other.pls", "Cat: 2")]
+            .into_iter()
+            .collect();
+        let src = "import \"lib.pls\" as lib\nlib: { Dog: 2 }";
+        match load_with(src, &loader) {
+            Err(LoadError::Alias {
+                alias,
+                span,
+                prev_span,
+            }) => {
+                assert_eq!(alias, "lib");
+                assert_eq!(&src[span.into_range()], "import \"lib.pls\" as lib");
+                assert!(src[prev_span.into_range()].starts_with("lib"));
+            }
+            other => panic!("expected an alias collision, got {:?}", other),
+        }
+        let src = "import \"lib.pls\" as lib\nimport \"other.pls\" as lib\na: 1";
+        let Err(err) = load_with(src, &loader) else {
+            panic!("expected an alias collision");
+        };
+        let d = &crate::diagnostic::Diagnostic::load(&err)[0];
+        assert_eq!(d.message, "import alias lib is already defined");
+        assert_eq!(&src[d.span.into_range()], "import \"other.pls\" as lib");
+        assert_eq!(
+            &src[d.labels[0].span.into_range()],
+            "import \"lib.pls\" as lib"
+        );
+    }
+
+    #[test]
+    fn import_missing_file_fails() {
+        let err = parse_to_json("import \"nope.pls\" as nope\nfoo: 1").unwrap_err();
+        assert!(err.contains("cannot find nope.pls"));
+    }
+
+    #[test]
+    fn import_keyword_still_usable_as_key() {
+        let unified = must_unify("import: 1");
+        assert_eq!(
+            unified.to_value(),
+            Value::Object(vec![("import".into(), Value::Int(1))])
+        );
+    }
+
+    #[test]
+    fn select_nested_path() {
+        let unified = must_unify("a: b: [1, { c: \"hi\" }]");
+        assert_eq!(
+            unified.select("a.b[1].c").unwrap().to_value(),
+            Value::String("hi".into())
+        );
+        assert_eq!(unified.select("").unwrap().to_value(), unified.to_value());
+        assert!(unified.select("a.b[2]").is_none());
+        assert!(unified.select("a.c").is_none());
+    }
+
+    fn fmt(src: &str) -> String {
+        format::format_source(src, &format::FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn format_source_reindents() {
+        let src = "a: {\n      b: \"{\" # {\n}\n\n\n c: [\n1,\n  ]  \n";
+        assert_eq!(fmt(src), "a: {\n  b: \"{\" # {\n}\n\nc: [\n  1,\n]\n");
+    }
+
+    #[test]
+    fn format_keeps_comments_chains_and_layout() {
+        let src = "# header\n\n\nfoo:   bar: baz   # trailing\nobj: {a: 1,b: 2}\nlist: [ # first\n  1,\n\n\n  # before two\n  2, ]\n\n\n\nt: Int|String\nsum: 1  +  2\n";
+        assert_eq!(
+            fmt(src),
+            "# header\n\nfoo: bar: baz # trailing\nobj: { a: 1, b: 2 }\nlist: [ # first\n  1,\n\n  # before two\n  2,\n]\n\nt: Int | String\nsum: 1 + 2\n"
+        );
+    }
+
+    #[test]
+    fn format_options_change_indent_and_commas() {
+        let src = "a: {\nb: 1\nc: [\n1,\n2,\n]\n}\n";
+        let options = format::FormatOptions {
+            indent: "\t".into(),
+            commas: format::CommaStyle::Trailing,
+        };
+        assert_eq!(
+            format::format_source(src, &options).unwrap(),
+            "a: {\n\tb: 1,\n\tc: [\n\t\t1,\n\t\t2,\n\t],\n},\n"
+        );
+        let options = format::FormatOptions {
+            commas: format::CommaStyle::Omit,
+            ..Default::default()
+        };
+        assert_eq!(
+            format::format_source(src, &options).unwrap(),
+            "a: {\n  b: 1\n  c: [\n    1,\n    2\n  ]\n}\n"
+        );
+        let options = format::FormatOptions {
+            commas: format::CommaStyle::Separate,
+            ..Default::default()
+        };
+        assert_eq!(
+            format::format_source(src, &options).unwrap(),
+            "a: {\n  b: 1,\n  c: [\n    1,\n    2\n  ]\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_rejects_invalid_source() {
+        assert!(format::format_source("a: {", &format::FormatOptions::default()).is_err());
+    }
+
+    #[test]
+    fn cst_is_lossless() {
+        let src = "import \"x.pls\" as x\r\n# c\na:  { b: [1 ,2]|  Int }\n\tc: f (1 + 2) # t\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+    }
+
+    #[test]
+    fn format_examples_is_stable() {
+        for entry in std::fs::read_dir("../examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|s| s.to_str()) != Some("pls") {
+                continue;
+            }
+            let src = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(cst::parse(&src).unwrap().to_string(), src);
+            let once = fmt(&src);
+            assert_eq!(fmt(&once), once, "{}", path.display());
+            assert_eq!(
+                without_spans(&must_unify(&once)),
+                without_spans(&must_unify(&src)),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn yaml_keeps_key_order_and_nests() {
+        let unified = must_unify(
+            "zeta: 1\nalpha: { list: [1, { a: true, b: null }, []], empty: {} }\nmid: 2.5",
+        );
+        assert_eq!(
+            unified.to_value().to_yaml_string().unwrap(),
+            "zeta: 1\nalpha:\n  list:\n    - 1\n    - a: true\n      b: null\n    - []\n  empty: {}\nmid: 2.5\n"
+        );
+    }
+
+    #[test]
+    fn yaml_quotes_ambiguous_strings() {
+        let unified = must_unify(
+            r#"a: ["true", "No", "1.5", "0x1F", "1_000", "~", "", " x", "- x", "a: b", "2024-01-01", "plain text", "multi\nline"]"#,
+        );
+        assert_eq!(
+            unified.to_value().to_yaml_string().unwrap(),
+            "a:\n  - \"true\"\n  - \"No\"\n  - \"1.5\"\n  - \"0x1F\"\n  - \"1_000\"\n  - \"~\"\n  - \"\"\n  - \" x\"\n  - \"- x\"\n  - \"a: b\"\n  - \"2024-01-01\"\n  - plain text\n  - \"multi\\nline\"\n"
+        );
+    }
+
+    #[test]
+    fn yaml_floats_read_back_as_the_same_floats() {
+        // The YAML 1.1 float pattern, which also needs a point and a signed
+        // exponent; YAML 1.2 accepts a superset.
+        let yaml_float =
+            regex::Regex::new(r"^[-+]?([0-9][0-9_]*)?\.[0-9.]*([eE][-+][0-9]+)?$").unwrap();
+        for n in [1e19, 1.5e-7, -2.5e300, 5e-324, 0.1, 1.0, -3.0, 123456.789] {
+            let yaml = yaml::to_string(&Value::Float(n)).unwrap();
+            let scalar = yaml.trim_end();
+            assert!(yaml_float.is_match(scalar), "{}", scalar);
+            assert_eq!(scalar.parse::<f64>().unwrap(), n, "{}", scalar);
+        }
+        assert_eq!(yaml::to_string(&Value::Float(1e19)).unwrap(), "1.0e+19\n");
+    }
+
+    #[test]
+    fn yaml_top_level_array_is_multi_document() {
+        let unified = must_unify("[{ kind: \"Service\" }, { kind: \"Deployment\" }, [1, [2]]]");
+        assert_eq!(
+            yaml::to_documents(&unified.to_value()).unwrap(),
+            "kind: Service\n---\nkind: Deployment\n---\n- 1\n- - 2\n"
+        );
+        assert_eq!(yaml::to_documents(&Value::Int(3)).unwrap(), "3\n");
+    }
+
+    #[test]
+    fn toml_writes_tables_after_values() {
+        let unified = must_unify(
+            r#"package: { name: "demo", version: "0.1.0" }
+            deps: { serde: { version: "1", features: ["derive"] } }
+            "my key": 1.5
+            bin: [{ name: "a" }, { name: "b" }]"#,
+        );
+        assert_eq!(
+            unified.to_value().to_toml_string().unwrap(),
+            r#""my key" = 1.5
+
+[package]
+name = "demo"
+version = "0.1.0"
+
+[deps.serde]
+version = "1"
+features = ["derive"]
+
+[[bin]]
+name = "a"
+
+[[bin]]
+name = "b"
+"#
+        );
+    }
+
+    #[test]
+    fn toml_rejects_unrepresentable_values() {
+        let err = must_unify("a: { b: [1, 2, null] }")
+            .to_value()
+            .to_toml_string()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.b[2]: null cannot be represented in TOML"
+        );
+
+        let err = must_unify("a: [{ x: 1 }, 2]")
+            .to_value()
+            .to_toml_string()
+            .unwrap_err();
+        assert_eq!(err.path, "a[1]");
+        assert_eq!(err.kind, export::ExportErrorKind::MixedArray);
+
+        let err = must_unify("[1, 2]")
+            .to_value()
+            .to_toml_string()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "top-level value must be an object");
+    }
+
+    /// `value` with every span reset, for comparing trees from different
+    /// sources.
+    fn without_spans(value: &SpannedValue) -> SpannedValue {
+        let span = Span::new(SourceId::default(), 0..0);
+        let kind = match &value.kind {
+            ValueKind::Array(items) => ValueKind::Array(items.iter().map(without_spans).collect()),
+            ValueKind::Object(members) => ValueKind::Object(
+                members
+                    .iter()
+                    .map(|(k, v, _, anns)| (k.clone(), without_spans(v), span, anns.clone()))
+                    .collect(),
+            ),
+            ValueKind::Call(name, arg) => {
+                ValueKind::Call(name.clone(), Box::new(without_spans(arg)))
+            }
+            ValueKind::OpCall(op, left, right) => ValueKind::OpCall(
+                op.clone(),
+                Box::new(without_spans(left)),
+                Box::new(without_spans(right)),
+            ),
+            ValueKind::Union(items) => ValueKind::Union(items.iter().map(without_spans).collect()),
+            ValueKind::Interpolation(parts) => {
+                ValueKind::Interpolation(parts.iter().map(without_spans).collect())
+            }
+            ValueKind::Arguments(items) => {
+                ValueKind::Arguments(items.iter().map(without_spans).collect())
+            }
+            ValueKind::List(list) => ValueKind::List(list.map(without_spans)),
+            ValueKind::Default(v) => ValueKind::Default(Box::new(without_spans(v))),
+            other => other.clone(),
+        };
+        SpannedValue { span, kind }
+    }
+
+    fn assert_round_trips(unified: &SpannedValue) {
+        let printed = printer::to_string(unified);
+        let reparsed = parse_unify(&printed)
+            .unwrap_or_else(|e| panic!("{}\nin printed source:\n{}", e.msg, printed));
+        assert_eq!(
+            without_spans(&reparsed),
+            without_spans(unified),
+            "{}",
+            printed
+        );
+    }
+
+    #[test]
+    fn printer_writes_canonical_source() {
+        let unified = must_unify(
+            r#"port: Int | String
+            Port: @NoExport
+            Port: Int
+            n: Int
+            m: n + 1
+            xs: [{ a: 1 }, [1, 2], "x\ny"]
+            "my key": 1.5
+            inc: @Function
+            inc: {
+              arg: Int
+              return: native ["increment", arg]
+            }"#,
+        );
+        assert_eq!(
+            printer::to_string(&unified),
+            r#"port: Int | String
+Port: @NoExport
+Port: Int
+n: Int
+m: Int + 1
+xs: [
+  {
+    a: 1
+  },
+  [1, 2],
+  """
+    x
+    y
+    """,
+]
+"my key": 1.5
+inc: @Function
+inc: {
+  arg: Int
+  return: native ["increment", inc.arg]
+}
+"#
+        );
+        assert_round_trips(&unified);
+    }
+
+    #[test]
+    fn printer_parenthesizes_nested_operators() {
+        let left_nested = span_value(Value::OpCall(
+            "-".into(),
+            Box::new(Value::OpCall(
+                "+".into(),
+                Box::new(Value::Type(ValType::Int)),
+                Box::new(Value::Int(1)),
+            )),
+            Box::new(Value::Int(2)),
+        ));
+        assert_eq!(printer::to_string(&left_nested), "Int + 1 - 2\n");
+        assert_round_trips(&left_nested);
+
+        let right_nested = span_value(Value::OpCall(
+            "*".into(),
+            Box::new(Value::Int(2)),
+            Box::new(Value::OpCall(
+                "-".into(),
+                Box::new(Value::Type(ValType::Int)),
+                Box::new(Value::Int(1)),
+            )),
+        ));
+        assert_eq!(printer::to_string(&right_nested), "2 * (Int - 1)\n");
+        assert_round_trips(&right_nested);
+
+        let union_of_calls = span_value(Value::Union(vec![
+            Value::Call("f".into(), Box::new(Value::Int(1))),
+            Value::Type(ValType::String),
+        ]));
+        assert_eq!(printer::to_string(&union_of_calls), "(f 1) | String\n");
+    }
+
+    #[test]
+    fn printer_round_trips_examples() {
+        for entry in std::fs::read_dir("../examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|s| s.to_str()) != Some("pls") {
+                continue;
+            }
+            let src = std::fs::read_to_string(&path).unwrap();
+            assert_round_trips(&must_unify(&src));
+        }
+    }
+
+    fn lsp_notify(
+        server: &mut lsp::Server,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Vec<serde_json::Value> {
+        server.handle(&serde_json::json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn lsp_request(
+        server: &mut lsp::Server,
+        method: &str,
+        params: serde_json::Value,
+    ) -> serde_json::Value {
+        let replies = server.handle(
+            &serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }),
+        );
+        assert_eq!(replies.len(), 1);
+        replies[0]["result"].clone()
+    }
+
+    fn lsp_open(server: &mut lsp::Server, uri: &str, text: &str) -> Vec<serde_json::Value> {
+        lsp_notify(
+            server,
+            "textDocument/didOpen",
+            serde_json::json!({ "textDocument": { "uri": uri, "languageId": "polsia", "version": 1, "text": text } }),
+        )
+    }
+
+    fn lsp_at(uri: &str, line: u64, character: u64) -> serde_json::Value {
+        serde_json::json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn lsp_publishes_parse_and_unify_diagnostics() {
+        let uri = "file:///work/a.pls";
+        let mut server = lsp::Server::new();
+        let init = lsp_request(&mut server, "initialize", serde_json::json!({}));
+        assert_eq!(init["capabilities"]["hoverProvider"], true);
+
+        let published = lsp_open(&mut server, uri, "a: 1\na: 2");
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostic = &published[0]["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["message"], "a: values do not unify");
+        assert_eq!(
+            diagnostic["range"]["start"],
+            serde_json::json!({ "line": 1, "character": 0 })
+        );
+        let related = &diagnostic["relatedInformation"][0];
+        assert_eq!(related["message"], "previous value here");
+        assert_eq!(related["location"]["uri"], uri);
+        assert_eq!(related["location"]["range"]["start"]["line"], 0);
+
+        let published = lsp_notify(
+            &mut server,
+            "textDocument/didChange",
+            serde_json::json!({ "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": "a: {" }] }),
+        );
+        let diagnostics = published[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 4);
+
+        let published = lsp_notify(
+            &mut server,
+            "textDocument/didChange",
+            serde_json::json!({ "textDocument": { "uri": uri, "version": 3 }, "contentChanges": [{ "text": "a: 1" }] }),
+        );
+        assert_eq!(published[0]["params"]["diagnostics"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn lsp_hover_shows_unified_value() {
+        let uri = "file:///work/a.pls";
+        let mut server = lsp::Server::new();
+        lsp_open(
+            &mut server,
+            uri,
+            "person: { name: String }\nperson: name: \"Jane\"\nme: person.name",
+        );
+        let hover = lsp_request(&mut server, "textDocument/hover", lsp_at(uri, 0, 2));
+        assert_eq!(
+            hover["contents"]["value"],
+            "```polsia\nperson: {\n  name: \"Jane\"\n}\n```"
+        );
+        assert_eq!(
+            hover["range"]["end"],
+            serde_json::json!({ "line": 0, "character": 6 })
+        );
+
+        let hover = lsp_request(&mut server, "textDocument/hover", lsp_at(uri, 2, 8));
+        assert_eq!(hover["contents"]["value"], "```polsia\nname: \"Jane\"\n```");
+        assert_eq!(
+            lsp_request(&mut server, "textDocument/hover", lsp_at(uri, 0, 7)),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn lsp_definition_finds_every_definition() {
+        let uri = "file:///work/a.pls";
+        let mut server = lsp::Server::new();
+        lsp_open(
+            &mut server,
+            uri,
+            "numbers: { one: 1 }\nnumbers: one: Int\nx: numbers.one\ny: { one: 2, z: one }",
+        );
+        let locations = lsp_request(&mut server, "textDocument/definition", lsp_at(uri, 2, 5));
+        let starts: Vec<_> = locations
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["range"]["start"].clone())
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                serde_json::json!({ "line": 0, "character": 11 }),
+                serde_json::json!({ "line": 1, "character": 9 })
+            ]
+        );
+
+        // `one` inside `y` refers to `y.one`, not the top-level key.
+        let locations = lsp_request(&mut server, "textDocument/definition", lsp_at(uri, 3, 17));
+        assert_eq!(
+            locations[0]["range"]["start"],
+            serde_json::json!({ "line": 3, "character": 5 })
+        );
+        assert_eq!(locations.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn lsp_definition_follows_imports() {
+        let mut server = lsp::Server::new();
+        lsp_open(
+            &mut server,
+            "file:///work/lib/animals.pls",
+            "Dog: @NoExport\nDog: { says: \"bark\" }",
+        );
+        let published = lsp_open(
+            &mut server,
+            "file:///work/main.pls",
+            "import \"lib/animals.pls\" as animals\npet: animals.Dog",
+        );
+        assert!(
+            published
+                .iter()
+                .all(|p| p["params"]["diagnostics"] == serde_json::json!([]))
+        );
+        let locations = lsp_request(
+            &mut server,
+            "textDocument/definition",
+            lsp_at("file:///work/main.pls", 1, 14),
+        );
+        let uris: Vec<_> = locations
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["uri"].clone())
+            .collect();
+        assert_eq!(
+            uris,
+            [
+                "file:///work/lib/animals.pls",
+                "file:///work/lib/animals.pls"
+            ]
+        );
+    }
+
+    #[test]
+    fn lsp_run_speaks_base_protocol() {
+        let mut input = Vec::new();
+        for msg in [
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ] {
+            lsp::write_message(&mut input, &msg).unwrap();
+        }
+        let mut output = Vec::new();
+        lsp::run(input.as_slice(), &mut output).unwrap();
+        let mut replies = output.as_slice();
+        let first = lsp::read_message(&mut replies).unwrap().unwrap();
+        assert_eq!(first["id"], 1);
+        assert_eq!(first["result"]["capabilities"]["definitionProvider"], true);
+        let second = lsp::read_message(&mut replies).unwrap().unwrap();
+        assert_eq!(
+            second,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "result": null })
+        );
+        assert!(lsp::read_message(&mut replies).unwrap().is_none());
+    }
+
+    fn collect(src: &str) -> (SpannedValue, Vec<Conflict>) {
+        let parsed = parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
+        unify_tree_collecting(&parsed)
+    }
+
+    #[test]
+    fn collecting_reports_every_conflict() {
+        let src = "a: 1\na: \"x\"\nb: 2\nc: Int\nc: true\nd: missing\n";
+        let (value, conflicts) = collect(src);
+        let paths: Vec<&str> = conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["a", "c", "d"]);
+        assert!(conflicts.iter().all(|c| !c.consequential));
+        let a = &conflicts[0].error;
+        assert_eq!(&src[a.span.into_range()], "a: \"x\"");
+        assert_eq!(&src[a.prev_span.into_range()], "a: 1");
+        assert!(
+            conflicts[2]
+                .error
+                .msg
+                .contains("unresolved reference missing")
+        );
+        assert_eq!(
+            value.select("a").unwrap().kind,
+            ValueKind::Type(ValType::Nothing)
+        );
+        assert_eq!(value.select("b").unwrap().kind, ValueKind::Int(2));
+    }
+
+    #[test]
+    fn collecting_flags_consequential_conflicts() {
+        let src = "a: 1\na: \"x\"\nb: a\nb: 2\nc: b\nc: 3\nd: 4\nd: 5\n";
+        let (_, conflicts) = collect(src);
+        let flagged: Vec<(&str, bool)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.consequential))
+            .collect();
+        assert_eq!(
+            flagged,
+            [("a", false), ("b", true), ("c", true), ("d", false)]
+        );
+    }
+
+    #[test]
+    fn collecting_fails_only_conflicting_members() {
+        let src = "c: { x: 1, y: 2 }\nc: { x: 3, y: 2 }\nd: c.y\ne: c.x\ne: 4\n";
+        let (value, conflicts) = collect(src);
+        let flagged: Vec<(&str, bool)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.consequential))
+            .collect();
+        assert_eq!(flagged, [("c.x", false), ("e", true)]);
+        assert_eq!(value.select("d").unwrap().kind, ValueKind::Int(2));
+    }
+
+    #[test]
+    fn collecting_matches_unify_tree_without_conflicts() {
+        let src = "a: 1\nb: a\nc: { x: b }\nc: { y: 2 }\n";
+        let (value, conflicts) = collect(src);
+        assert!(conflicts.is_empty());
+        assert_eq!(value.to_value(), must_unify(src).to_value());
+    }
+
+    #[test]
+    fn parse_to_json_reports_all_conflicts() {
+        let err = parse_to_json("a: 1\na: 2\nb: a\nb: 3\nc: true\nc: 1\n").unwrap_err();
+        assert!(err.contains("a: values do not unify"));
+        assert!(err.contains("c: values do not unify"));
+        assert!(!err.contains("b: "));
+    }
+
+    fn diagnose(src: &str) -> (SourceMap, Vec<Diagnostic>) {
+        let mut sources = SourceMap::new();
+        let diagnostics =
+            parse_to_json_with_diagnostics(src, &MemoryLoader::new(), &mut sources).unwrap_err();
+        (sources, diagnostics)
+    }
+
+    #[test]
+    fn diagnostics_describe_conflicts() {
+        let src = "a: 1\nb: { c: true }\nb: { c: \"x\" }\n";
+        let (sources, diagnostics) = diagnose(src);
+        assert_eq!(diagnostics.len(), 1);
+        let d = &diagnostics[0];
+        assert_eq!(d.severity, diagnostic::Severity::Error);
+        assert_eq!(d.code, diagnostic::ErrorCode::Conflict);
+        assert_eq!(d.path.as_deref(), Some("b.c"));
+        assert_eq!(&src[d.span.into_range()], "c: \"x\"");
+        assert_eq!(d.labels.len(), 1);
+        assert_eq!(&src[d.labels[0].span.into_range()], "c: true");
+        assert_eq!(d.labels[0].message, "previous value here");
+        let json = d.to_json(&sources);
+        assert_eq!(json["code"], "conflict");
+        assert_eq!(json["path"], "b.c");
+        assert_eq!(
+            json["span"],
+            serde_json::json!({
+                "file": "input",
+                "start": { "offset": 25, "line": 3, "column": 6 },
+                "end": { "offset": 31, "line": 3, "column": 12 },
+            })
+        );
+        assert_eq!(json["labels"][0]["span"]["start"]["line"], 2);
+    }
+
+    #[test]
+    fn diagnostics_have_stable_codes() {
+        let code = |src: &str| diagnose(src).1[0].code.as_str();
+        assert_eq!(code("a: {"), "syntax");
+        assert_eq!(code("import \"x.pls\" as x\na: x.b\n"), "import");
+        assert_eq!(code("a: Int\na: true"), "type-mismatch");
+        assert_eq!(code("a: b"), "unresolved-reference");
+        assert_eq!(code("a: nope 1"), "unknown-function");
+        let (_, diagnostics) = diagnose("a: { b: [1, Int] }");
+        assert_eq!(diagnostics[0].code.as_str(), "incomplete");
+        assert_eq!(diagnostics[0].path.as_deref(), Some("a.b[1]"));
+    }
+
+    #[test]
+    fn diagnostics_render_like_reports() {
+        let err = parse_to_json("a: 1\na: 2\n").unwrap_err();
+        assert!(err.contains("[conflict] Error: a: values do not unify"));
+        assert!(err.contains("previous value here"));
+    }
+
+    #[test]
+    fn export_errors_name_the_offending_path() {
+        use export::{ExportError, ExportErrorKind};
+        let value = Value::Object(vec![(
+            "a".into(),
+            Value::Array(vec![Value::Int(1), Value::Type(ValType::Int)]),
+        )]);
+        let expected = ExportError {
+            path: "a[1]".into(),
+            kind: ExportErrorKind::Unresolved("Int".into()),
+        };
+        assert_eq!(value.to_value(), Err(expected.clone()));
+        assert_eq!(value.to_yaml_string(), Err(expected));
+
+        let err = must_unify("a: { b: 1e999 }")
+            .to_value()
+            .to_pretty_string()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "a.b: inf is not a finite number");
+        assert_eq!(
+            must_unify("a: -1e999").to_value().to_yaml_string().unwrap(),
+            "a: -.inf\n"
+        );
+    }
+
+    #[test]
+    fn parse_to_json_reports_non_finite_floats() {
+        let err = parse_to_json("a: 1\nb: 1e999\n").unwrap_err();
+        assert!(err.contains("[unrepresentable] Error: b: inf is not a finite number"));
+        assert!(err.contains("b: 1e999"));
+    }
+
+    #[test]
+    fn find_unresolved_skips_hidden_members() {
+        let src = "hidden: @NoExport\nhidden: Int\nf: @Function\nf: { arg: Int, return: arg }\nshown: { x: [1, f 1 + y] }\ny: Int\n";
+        let (span, err) = export::find_unresolved(&must_unify(src)).unwrap();
+        assert_eq!(err.path, "shown.x[1]");
+        assert_eq!(
+            err.to_string(),
+            "shown.x[1]: value of type op + is unspecified"
+        );
+        assert_eq!(&src[span.into_range()], "1 + y");
+    }
+
+    fn parse_errors(src: &str) -> Vec<(String, std::ops::Range<usize>)> {
+        parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap_err()
+            .iter()
+            .map(|e| (e.to_string(), e.span().into_range()))
+            .collect()
+    }
+
+    fn ratio(n: i64, d: i64) -> num_rational::BigRational {
+        num_rational::BigRational::new(n.into(), d.into())
+    }
+
+    #[test]
+    fn large_integers_are_exact() {
+        let unified = must_unify(
+            "a: 99999999999999999999\nb: 9223372036854775807 + 1\nc: Int\nc: a\nd: b - 1\n",
+        );
+        let big: num_bigint::BigInt = "99999999999999999999".parse().unwrap();
+        assert_eq!(
+            unified.select("c").unwrap().kind,
+            ValueKind::Rational(big.into())
+        );
+        assert_eq!(
+            unified.select("b").unwrap().to_value(),
+            Value::Rational(num_bigint::BigInt::from(i64::MAX as u64 + 1).into())
+        );
+        assert_eq!(unified.select("d").unwrap().kind, ValueKind::Int(i64::MAX));
+        let min = must_unify("a: -9223372036854775808");
+        assert_eq!(min.select("a").unwrap().kind, ValueKind::Int(i64::MIN));
+    }
+
+    #[test]
+    fn rationals_are_exact() {
+        let unified = must_unify("a: 1/3\nb: a + 1/6\nc: -2/4\nd: 4/2\ne: b - 1/2\n");
+        assert_eq!(
+            unified.select("a").unwrap().kind,
+            ValueKind::Rational(ratio(1, 3))
+        );
+        assert_eq!(
+            unified.select("b").unwrap().kind,
+            ValueKind::Rational(ratio(1, 2))
+        );
+        assert_eq!(
+            unified.select("c").unwrap().kind,
+            ValueKind::Rational(ratio(-1, 2))
+        );
+        assert_eq!(unified.select("d").unwrap().kind, ValueKind::Int(2));
+        assert_eq!(unified.select("e").unwrap().kind, ValueKind::Int(0));
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: 1/3\nb: 1/2\nc: -1/2\nd: 2\ne: 0\n"
+        );
+    }
+
+    #[test]
+    fn rational_type_reads_decimals_exactly() {
+        let unified =
+            must_unify("a: Rational\na: 0.1\nb: Rational\nb: 0.2\nd: a + b\nc: Int\nc: 1/1");
+        assert_eq!(
+            unified.select("a").unwrap().kind,
+            ValueKind::Rational(ratio(1, 10))
+        );
+        assert_eq!(
+            unified.select("d").unwrap().kind,
+            ValueKind::Rational(ratio(3, 10))
+        );
+        assert_eq!(unified.select("c").unwrap().kind, ValueKind::Int(1));
+        assert!(parse_unify("a: Int\na: 1/2").is_err());
+    }
+
+    #[test]
+    fn decimal_literals_keep_their_digits() {
+        use num_bigint::BigInt;
+        let power = |n: u32| BigInt::from(10).pow(n);
+        let unified = must_unify(
+            "a: Rational & 1e-400\nb: Rational & 0.10000000000000000001\nc: Rational & 1e999\nd: Rational & 2.5e1",
+        );
+        assert_eq!(
+            unified.select("a").unwrap().kind,
+            ValueKind::Rational(num_rational::BigRational::new(1.into(), power(400)))
+        );
+        assert_eq!(
+            unified.select("b").unwrap().kind,
+            ValueKind::Rational(num_rational::BigRational::new(power(19) + 1, power(20)))
+        );
+        assert_eq!(
+            unified.select("c").unwrap().kind,
+            ValueKind::Rational(num_rational::BigRational::from_integer(power(999)))
+        );
+        assert_eq!(unified.select("d").unwrap().kind, ValueKind::Int(25));
+    }
+
+    #[test]
+    fn decimal_arithmetic_is_exact() {
+        let unified = must_unify("a: 0.1 + 0.2\nb: Rational & (0.1 + 0.2)\nc: 0.1 + 0.2 == 0.3");
+        assert_eq!(unified.select("a").unwrap().kind, ValueKind::Float(0.3));
+        assert_eq!(
+            unified.select("b").unwrap().kind,
+            ValueKind::Rational(ratio(3, 10))
+        );
+        assert_eq!(unified.select("c").unwrap().kind, ValueKind::Bool(true));
+        assert_eq!(parse_to_json("a: 0.1 + 0.2").unwrap(), "{\n  \"a\": 0.3\n}");
+    }
+
+    #[test]
+    fn decimals_equal_exact_numbers_of_the_same_value() {
+        let unified =
+            must_unify("a: 0.5\na: 1/2\nb: 2\nb: 2.0\nc: Float\nc: 1/3\nd: Float\nd: 4/2");
+        assert_eq!(
+            unified.select("a").unwrap().kind,
+            ValueKind::Rational(ratio(1, 2))
+        );
+        assert_eq!(unified.select("b").unwrap().kind, ValueKind::Int(2));
+        assert_eq!(
+            unified.select("c").unwrap().kind,
+            ValueKind::Float(1.0 / 3.0)
+        );
+        assert_eq!(unified.select("d").unwrap().kind, ValueKind::Int(2));
+        assert!(parse_unify("a: 0.3333\na: 1/3").is_err());
+    }
+
+    #[test]
+    fn decimal_exponents_are_bounded() {
+        assert_eq!(
+            parse_errors("a: 1e10001"),
+            vec![(
+                "exponent of 1e10001 is out of range; at most 10000 is supported".to_string(),
+                3..10
+            )]
+        );
+        assert_eq!(
+            must_unify("a: 1e-10000 > 0").select("a").unwrap().kind,
+            ValueKind::Bool(true)
+        );
+    }
+
+    #[test]
+    fn rationals_export_by_policy() {
+        use crate::export::{ExportErrorKind, RationalPolicy};
+        let value = must_unify("a: [1, 1/4]\nb: 10000000000000000000").to_value();
+        let err = value.to_pretty_string().unwrap_err();
+        assert_eq!(err.path, "a[1]");
+        assert_eq!(err.kind, ExportErrorKind::Inexact(ratio(1, 4)));
+        assert_eq!(
+            value
+                .with_rationals(RationalPolicy::Float)
+                .unwrap()
+                .to_value()
+                .unwrap(),
+            serde_json::json!({ "a": [1, 0.25], "b": 10000000000000000000u64 })
+        );
+        assert_eq!(
+            value
+                .with_rationals(RationalPolicy::String)
+                .unwrap()
+                .to_value()
+                .unwrap(),
+            serde_json::json!({ "a": [1, "1/4"], "b": 10000000000000000000u64 })
+        );
+        assert_eq!(
+            value
+                .with_rationals(RationalPolicy::String)
+                .unwrap()
+                .to_yaml_string()
+                .unwrap(),
+            "a:\n  - 1\n  - 1/4\nb: 10000000000000000000\n"
+        );
+        // Integers of any size are exact under every policy.
+        assert_eq!(
+            parse_to_json(
+                "a: 18446744073709551615\nb: -99999999999999999999999\nc: jsonEncode [a, b]"
+            )
+            .unwrap(),
+            r#"{
+  "a": 18446744073709551615,
+  "b": -99999999999999999999999,
+  "c": "[18446744073709551615,-99999999999999999999999]"
+}"#
+        );
+        assert_eq!(
+            stdlib_json("a: jsonDecode (jsonEncode 18446744073709551615)"),
+            serde_json::json!({ "a": 18446744073709551615u64 })
+        );
+        let big: num_bigint::BigInt = "99999999999999999999999".parse().unwrap();
+        let big = num_rational::BigRational::from_integer(big);
+        assert_eq!(
+            Value::Rational(big.clone()).to_value().unwrap_err().kind,
+            ExportErrorKind::OutOfRange(big)
+        );
+        let huge = num_rational::BigRational::new(num_bigint::BigInt::from(10).pow(400), 3.into());
+        let err = Value::Array(vec![Value::Int(0), Value::Rational(huge.clone())])
+            .with_rationals(RationalPolicy::Float)
+            .unwrap_err();
+        assert_eq!(err.path, "[1]");
+        assert_eq!(err.kind, ExportErrorKind::OutOfRange(huge));
+        let err = parse_to_json("a: 1/3").unwrap_err();
+        assert!(
+            err.contains("a: 1/3 cannot be represented exactly"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn surrogate_pairs_decode_to_one_character() {
+        let unified = must_unify(r#"{"\ud83d\ude00": "x\ud83d\ude00y\u00e9"}"#);
+        assert_eq!(
+            unified.to_value(),
+            Value::Object(vec![("😀".into(), Value::String("x😀yé".into()))])
+        );
+    }
+
+    #[test]
+    fn unpaired_surrogates_are_parse_errors() {
+        let src = r#"a: "\ud800"
+b: "\udc00\ud800x"
+"#;
+        assert_eq!(
+            parse_errors(src),
+            [
+                ("unpaired surrogate \\ud800 in string".to_string(), 4..10),
+                ("unpaired surrogate \\udc00 in string".to_string(), 16..22),
+                ("unpaired surrogate \\ud800 in string".to_string(), 22..28),
+            ]
+        );
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        let unified = must_unify(
+            "x: 5\na: 1 + 2 * 3\nb: (1 + 2) * 3\nc: 10 - 4 - 3\nd: 2*3+1\ne: -x * 2\nf: 7 / 2 * 2\n",
+        );
+        for (path, n) in [("a", 7), ("b", 9), ("c", 3), ("d", 7), ("e", -10), ("f", 7)] {
+            assert_eq!(
+                unified.select(path).unwrap().kind,
+                ValueKind::Int(n),
+                "{}",
+                path
+            );
+        }
+        let unified = must_unify("a: 7 // 2\nb: -7 // 2\nc: -7 % 3\nd: 7 % -3\ne: 1 / 3");
+        for (path, n) in [("a", 3), ("b", -4), ("c", 2), ("d", -2)] {
+            assert_eq!(
+                unified.select(path).unwrap().kind,
+                ValueKind::Int(n),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            unified.select("e").unwrap().kind,
+            ValueKind::Rational(ratio(1, 3))
+        );
+    }
+
+    #[test]
+    fn operators_accept_floats_and_mixed_operands() {
+        let unified =
+            must_unify("a: 1.5 * 2\nb: 1 / 4.0\nc: 7.5 // 2\nd: -x\nx: 0.5\ne: 1/2 + 0.25");
+        for (path, n) in [
+            ("a", 3.0),
+            ("b", 0.25),
+            ("c", 3.0),
+            ("d", -0.5),
+            ("e", 0.75),
+        ] {
+            assert_eq!(
+                unified.select(path).unwrap().kind,
+                ValueKind::Float(n),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons_produce_booleans() {
+        let unified = must_unify(
+            "a: 1 < 2\nb: 2.5 >= 3\nc: \"a\" < \"b\"\nd: [1, 2] == [1, 2]\ne: 1 + 1 == 2\nf: 1 == 1.0\ng: null != {}\nh: 1/3 > 0.3",
+        );
+        for (path, b) in [
+            ("a", true),
+            ("b", false),
+            ("c", true),
+            ("d", true),
+            ("e", true),
+            ("f", true),
+            ("g", true),
+            ("h", true),
+        ] {
+            assert_eq!(
+                unified.select(path).unwrap().kind,
+                ValueKind::Bool(b),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn operator_errors_are_unify_errors() {
+        use crate::diagnostic::ErrorCode;
+        for (src, code, msg) in [
+            ("a: 1 / 0", ErrorCode::DivisionByZero, "a: division by zero"),
+            (
+                "a: 1.5 % 0",
+                ErrorCode::DivisionByZero,
+                "a: division by zero",
+            ),
+            (
+                "a: 1e308 * 10",
+                ErrorCode::Overflow,
+                "a: result of * is too large for a float",
+            ),
+            (
+                "a: \"x\" * 2",
+                ErrorCode::TypeMismatch,
+                "a: cannot apply * to String and Int",
+            ),
+            (
+                "a: true < false",
+                ErrorCode::TypeMismatch,
+                "a: cannot apply < to Boolean and Boolean",
+            ),
+        ] {
+            let err = parse_unify(src).unwrap_err();
+            assert_eq!((err.code, err.msg.as_str()), (code, msg), "{}", src);
+            assert_eq!(err.span.into_range(), 0..src.len(), "{}", src);
+        }
+        // Operands that are not concrete yet wait.
+        let unified = must_unify("a: Int * 0\nb: Int < 1");
+        assert!(matches!(
+            unified.select("a").unwrap().kind,
+            ValueKind::OpCall(..)
+        ));
+    }
+
+    #[test]
+    fn format_spaces_operators() {
+        assert_eq!(
+            fmt("a: 1+2*-x\nb: f -x\nc: f - x\nd: 1 -1\ne: [-1, 2>=1]\n"),
+            "a: 1 + 2 * -x\nb: f -x\nc: f - x\nd: 1 - 1\ne: [-1, 2 >= 1]\n"
+        );
+        let src = "a: -(1)//2 % 3 !=4\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+    }
+
+    #[test]
+    fn strings_concatenate_with_plus() {
+        let unified = must_unify("name: \"b\"\na: \"a\" + name + \"c\"\nb: \"x\" < \"y\"");
+        assert_eq!(
+            unified.select("a").unwrap().kind,
+            ValueKind::String("abc".into())
+        );
+        assert_eq!(unified.select("b").unwrap().kind, ValueKind::Bool(true));
+    }
+
+    #[test]
+    fn interpolation_joins_values() {
+        let unified = must_unify(
+            "name: \"world\"\nn: 3\na: \"hi \\(name), \\(n + 1) \\(1 / 2) \\(1.5) \\(true) \\(null)\"\nb: \"<\\(\"in \\(name)\")>\"\nc: \"\\\\(name)\"\nd: \"\\(n)\"",
+        );
+        for (path, s) in [
+            ("a", "hi world, 4 1/2 1.5 true null"),
+            ("b", "<in world>"),
+            ("c", "\\(name)"),
+            ("d", "3"),
+        ] {
+            assert_eq!(
+                unified.select(path).unwrap().kind,
+                ValueKind::String(s.into()),
+                "{}",
+                path
+            );
+        }
+        // A concrete interpolation unifies with an equal string.
+        must_unify("n: 3\na: \"n\\(n)\"\na: \"n3\"");
+        assert!(parse_unify("n: 3\na: \"n\\(n)\"\na: \"n4\"").is_err());
+    }
+
+    #[test]
+    fn interpolation_waits_for_concrete_values() {
+        let unified = must_unify("a: Int\nb: \"v\\(a + 1)!\"\n");
+        assert!(matches!(
+            unified.select("b").unwrap().kind,
+            ValueKind::Interpolation(_)
+        ));
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: Int\nb: \"v\\(Int + 1)!\"\n"
+        );
+        assert_round_trips(&unified);
+        let src = "b: \"v\\(Int)\"";
+        let (span, err) = export::find_unresolved(&must_unify(src)).unwrap();
+        assert_eq!(err.path, "b");
+        assert_eq!(&src[span.into_range()], "Int");
+        // Filled in later, it exports as a string.
+        let unified = must_unify("a: Int\nb: \"v\\(a + 1)\"\na: 1");
+        assert_eq!(
+            unified.select("b").unwrap().kind,
+            ValueKind::String("v2".into())
+        );
+    }
+
+    #[test]
+    fn interpolating_structures_is_an_error() {
+        use crate::diagnostic::ErrorCode;
+        let src = "x: [1]\na: \"v\\(x)\"";
+        let err = parse_unify(src).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TypeMismatch);
+        assert_eq!(err.msg, "a: cannot interpolate an array");
+    }
+
+    #[test]
+    fn format_keeps_interpolations() {
+        let src = "a: \"x\\( (1 + 2) * (f \"y)\")) \\(b)\" + \"z\"\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(fmt("a:\"\\(b)\"+ c\n"), "a: \"\\(b)\" + c\n");
+        assert!(cst::parse("a: \"\\(b\"\n").is_err());
+    }
+
+    #[test]
+    fn multi_line_strings_strip_common_indentation() {
+        let unified = must_unify(
+            r#"name: "sh"
+            script: """
+              #!/bin/\(name)
+              echo "a" \\
+                "b"
+
+              done\n"""
+            closing: """
+                a
+              b
+              """
+            crlf: """
+  x
+  """
+            empty: """
+            """
+            "#
+            .replace("x\n", "x\r\n")
+            .as_str(),
+        );
+        for (path, s) in [
+            ("script", "#!/bin/sh\necho \"a\" \\\n  \"b\"\n\ndone\n"),
+            ("closing", "  a\nb"),
+            ("crlf", "x"),
+            ("empty", ""),
+        ] {
+            assert_eq!(
+                unified.select(path).unwrap().kind,
+                ValueKind::String(s.into()),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn raw_strings_keep_backslashes() {
+        let unified = must_unify(
+            r##"#"C:\dir"#: #"\d+\(x) "quoted"
+line"#  # a comment
+            a: 1 #no space
+            """
+              multi
+              """: 2"##,
+        );
+        assert_eq!(
+            unified.to_value(),
+            Value::Object(vec![
+                (
+                    "C:\\dir".into(),
+                    Value::String("\\d+\\(x) \"quoted\"\nline".into())
+                ),
+                ("a".into(), Value::Int(1)),
+                ("multi".into(), Value::Int(2)),
+            ])
+        );
+    }
+
+    #[test]
+    fn raw_strings_close_with_as_many_hashes_as_they_open() {
+        let unified = must_unify(
+            r####"a: ##"a "# b"##
+b: ###"x"##"###  ## a comment"####,
+        );
+        assert_eq!(
+            unified.to_value(),
+            Value::Object(vec![
+                ("a".into(), Value::String("a \"# b".into())),
+                ("b".into(), Value::String("x\"##".into())),
+            ])
+        );
+        assert!(!parse_errors(r###"a: ##"x"#"###).is_empty());
+        let src = "a: ##\"a \"# b\"## # note\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(fmt(src), src);
+        assert!(cst::parse("a: ##\"x\"#\n").is_err());
+    }
+
+    #[test]
+    fn string_forms_keep_spans() {
+        let src = "a: \"\"\"\n  x\\ud800\n  \"\"\"\nb: #\"1\"#\nb: 2\n";
+        assert_eq!(
+            parse_errors(src),
+            [("unpaired surrogate \\ud800 in string".to_string(), 10..16)]
+        );
+        let src = "b: #\"1\"#\nb: \"\"\"\n  2\n  \"\"\"\n";
+        let err = parse_unify(src).unwrap_err();
+        assert_eq!(&src[err.prev_span.into_range()], "b: #\"1\"#");
+        assert_eq!(&src[err.span.into_range()], "b: \"\"\"\n  2\n  \"\"\"");
+    }
+
+    #[test]
+    fn printer_round_trips_multi_line_strings() {
+        for s in [
+            "a\nb",
+            "a\n",
+            "\n",
+            "  lead\n\ttab\n  \n",
+            "back\\slash \\(x)\n\"q\"",
+            "three \"\"\" quotes\n",
+            "cr\r\nlf",
+        ] {
+            let unified = {
+                let quoted = serde_json::to_string(s).unwrap();
+                must_unify(&format!("a: [{}]\nb: {{ c: {} }}", quoted, quoted))
+            };
+            assert_round_trips(&unified);
+            let printed = printer::to_string(&unified);
+            assert_eq!(
+                must_unify(&printed).select("b.c").unwrap().kind,
+                ValueKind::String(s.into()),
+                "{}",
+                printed
+            );
+        }
+        let unified = must_unify("a: \"x\\ny\"");
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: \"\"\"\n  x\n  y\n  \"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_string_forms() {
+        let src = "a: \"\"\"\n    x \\(\")\")\n  \"\"\"\n\"\"\"\n  k\n  \"\"\": #\"\\\"#\n# a #\"x\" comment\n";
+        let formatted = fmt(src);
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert!(formatted.contains("#\"\\\"#"));
+        assert!(cst::parse("a: #\"x\n").is_err());
+        assert!(cst::parse("a: \"\"\"\nx\"\n").is_err());
+    }
+
+    /// Repeats `s` `times` times; waits for both to be concrete.
+    struct Repeat;
+
+    impl NativeFunction for Repeat {
+        fn arg(&self) -> Value {
+            Value::Object(vec![
+                ("s".into(), Value::Type(ValType::String)),
+                ("times".into(), Value::Type(ValType::Int)),
+            ])
+        }
+
+        fn call(&self, arg: &Value) -> Result<Option<Value>, String> {
+            let Value::Object(members) = arg else {
+                return Ok(None);
+            };
+            match (&members[0].1, &members[1].1) {
+                (_, Value::Int(n)) if *n < 0 => Err(format!("cannot repeat {} times", n)),
+                (Value::String(s), Value::Int(n)) => Ok(Some(Value::String(s.repeat(*n as usize)))),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    fn unify_with_repeat(src: &str) -> Result<SpannedValue, UnifyError> {
+        let parsed = parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
+        let mut natives = Natives::builtin();
+        natives.insert("repeat", Repeat);
+        unify_tree_with_natives(&parsed, &natives)
+    }
+
+    #[test]
+    fn call_registered_native() {
+        let unified =
+            unify_with_repeat("n: 2\na: native [\"repeat\", { s: \"ab\", times: n + 1 }]").unwrap();
+        assert_eq!(
+            unified.select("a").unwrap().to_value(),
+            Value::String("ababab".into())
+        );
+        // The builtins are still there.
+        let unified = unify_with_repeat("a: native [\"increment\", 1]").unwrap();
+        assert_eq!(unified.select("a").unwrap().to_value(), Value::Int(2));
+    }
+
+    #[test]
+    fn call_native_waits_for_concrete_argument() {
+        let unified =
+            unify_with_repeat("a: native [\"repeat\", { s: \"ab\", times: Int }]").unwrap();
+        assert!(matches!(
+            unified.select("a").unwrap().kind,
+            ValueKind::Call(..)
+        ));
+        assert!(export::find_unresolved(&unified).is_some());
+        let unified =
+            unify_with_repeat("t: Int\na: native [\"repeat\", { s: \"x\", times: t }]\nt: 2")
+                .unwrap();
+        assert_eq!(
+            unified.select("a").unwrap().to_value(),
+            Value::String("xx".into())
+        );
+    }
+
+    #[test]
+    fn call_native_checks_argument_shape() {
+        use crate::diagnostic::ErrorCode;
+        let src = "a: native [\"repeat\", { s: 1, times: 2 }]";
+        let err = unify_with_repeat(src).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TypeMismatch);
+        assert_eq!(err.msg, "a.s: expected string");
+        assert_eq!(&src[err.span.into_range()], "s: 1");
+        let err = unify_with_repeat("a: native [\"repeat\", { s: \"x\", times: -1 }]").unwrap_err();
+        assert_eq!(
+            (err.code, err.msg.as_str()),
+            (ErrorCode::Native, "a: repeat: cannot repeat -1 times")
+        );
+        let err = unify_tree_with_natives(
+            &parser()
+                .parse(input("a: native [\"increment\", 1]", SourceId::default()))
+                .into_result()
+                .unwrap(),
+            &Natives::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            (err.code, err.msg.as_str()),
+            (
+                ErrorCode::UnknownFunction,
+                "a: unknown native function increment"
+            )
+        );
+    }
+
+    fn stdlib_json(src: &str) -> serde_json::Value {
+        serde_json::from_str(&parse_to_json(src).unwrap()).unwrap()
+    }
+
+    /// Evaluates `call` as the member `a` and returns its JSON.
+    fn stdlib_call(call: &str) -> serde_json::Value {
+        stdlib_json(&format!("a: {}", call))["a"].take()
+    }
+
+    /// Evaluates `call` as the member `a`, which must fail with `code`, and
+    /// returns the message.
+    fn stdlib_rejects(call: &str, code: crate::diagnostic::ErrorCode) -> String {
+        let err = parse_unify(&format!("a: {}", call)).unwrap_err();
+        assert_eq!(err.code, code, "{}", err.msg);
+        err.msg
+    }
+
+    const DOUBLE: &str = "double: @Function\ndouble: { arg: Int, return: arg * 2 }\n";
+
+    #[test]
+    fn stdlib_upper() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"upper "abc""#), "ABC");
+        assert_eq!(
+            stdlib_rejects("upper 1", ErrorCode::TypeMismatch),
+            "a: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_lower() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"lower "ÀbC""#), "àbc");
+        assert_eq!(
+            stdlib_rejects("lower 1", ErrorCode::TypeMismatch),
+            "a: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_trim() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"trim "  x ""#), "x");
+        assert_eq!(
+            stdlib_rejects("trim 1", ErrorCode::TypeMismatch),
+            "a: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_split() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"split { s: "a,b,c", sep: "," }"#),
+            serde_json::json!(["a", "b", "c"])
+        );
+        assert_eq!(
+            stdlib_rejects(r#"split { s: 1, sep: "," }"#, ErrorCode::TypeMismatch),
+            "a.s: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_join() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"join { list: ["a", 1, true], sep: "-" }"#),
+            "a-1-true"
+        );
+        assert_eq!(
+            stdlib_rejects(r#"join { list: [[1]], sep: "-" }"#, ErrorCode::Native),
+            "a: join: cannot join a list"
+        );
+    }
+
+    #[test]
+    fn stdlib_replace() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"replace { s: "aXbX", from: "X", to: "." }"#),
+            "a.b."
+        );
+        assert_eq!(
+            stdlib_rejects(
+                r#"replace { s: "a", from: "", to: "b" }"#,
+                ErrorCode::Native
+            ),
+            "a: replace: cannot replace an empty string"
+        );
+    }
+
+    #[test]
+    fn stdlib_contains() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"contains { s: "hello", part: "ell" }"#), true);
+        assert_eq!(
+            stdlib_rejects(r#"contains { s: "a", part: 1 }"#, ErrorCode::TypeMismatch),
+            "a.part: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_starts_with() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"startsWith { s: "hello", prefix: "he" }"#),
+            true
+        );
+        assert_eq!(
+            stdlib_call(r#"startsWith { s: "hello", prefix: "lo" }"#),
+            false
+        );
+        assert_eq!(
+            stdlib_rejects(
+                r#"startsWith { s: 1, prefix: "a" }"#,
+                ErrorCode::TypeMismatch
+            ),
+            "a.s: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_format() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"format { template: "{} + {} = {} {{}}", args: [1, 2, 1 + 2] }"#),
+            "1 + 2 = 3 {}"
+        );
+        assert_eq!(
+            stdlib_rejects(r#"format { template: "{}", args: [] }"#, ErrorCode::Native),
+            "a: format: too few arguments for format: 0"
+        );
+        assert_eq!(
+            stdlib_rejects(r#"format { template: "}", args: [] }"#, ErrorCode::Native),
+            "a: format: unmatched } in format"
+        );
+    }
+
+    #[test]
+    fn stdlib_len() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"len "héllo""#), 5);
+        assert_eq!(stdlib_call("len [1, 2]"), 2);
+        assert_eq!(
+            stdlib_rejects("len 1", ErrorCode::Native),
+            "a: len: Int has no length"
+        );
+    }
+
+    #[test]
+    fn stdlib_concat() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call("concat [[1], [2, 3]]"),
+            serde_json::json!([1, 2, 3])
+        );
+        assert_eq!(
+            stdlib_rejects("concat [1, [2]]", ErrorCode::Native),
+            "a: concat: expected a list, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_flatten() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call("flatten [[1, [2]], [3]]"),
+            serde_json::json!([1, 2, 3])
+        );
+        assert_eq!(
+            stdlib_rejects("flatten 1", ErrorCode::Native),
+            "a: flatten: expected a list, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_map() {
+        use crate::diagnostic::ErrorCode;
+        let json = stdlib_json(&format!(
+            "{}a: map {{ list: [1, 2, 3], f: \"double\" }}\nb: map {{ list: [\"x\"], f: \"upper\" }}",
+            DOUBLE
+        ));
+        assert_eq!(json["a"], serde_json::json!([2, 4, 6]));
+        assert_eq!(json["b"], serde_json::json!(["X"]));
+        assert_eq!(
+            stdlib_rejects(
+                r#"map { list: [1], f: "nope" }"#,
+                ErrorCode::UnknownFunction
+            ),
+            "a: unknown function nope"
+        );
+    }
+
+    #[test]
+    fn stdlib_filter() {
+        let json = stdlib_json(
+            "big: @Function\nbig: { arg: Int, return: arg > 2 }\na: filter { list: [1, 2, 3, 4], f: \"big\" }",
+        );
+        assert_eq!(json["a"], serde_json::json!([3, 4]));
+        let err = parse_unify(&format!(
+            "{}a: filter {{ list: [1], f: \"double\" }}",
+            DOUBLE
+        ))
+        .unwrap_err();
+        assert_eq!(err.code, crate::diagnostic::ErrorCode::Native);
+        assert_eq!(err.msg, "a: filter: expected a Boolean, got Int");
+    }
+
+    #[test]
+    fn filter_helper_is_not_callable() {
+        let err = parse_to_json("x: where { list: [1], keep: [true] }").unwrap_err();
+        assert!(err.contains("unknown function where"), "{}", err);
+        let err = parse_to_json("x: native [\"where\", { list: [1], keep: [true] }]").unwrap_err();
+        assert!(err.contains("unknown native function where"), "{}", err);
+    }
+
+    #[test]
+    fn stdlib_sort() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call("sort [3, 1.5, 2]"),
+            serde_json::json!([1.5, 2, 3])
+        );
+        assert_eq!(
+            stdlib_rejects(r#"sort [1, "a"]"#, ErrorCode::Native),
+            "a: sort: cannot compare String and Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_unique() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("unique [1, 2, 1]"), serde_json::json!([1, 2]));
+        assert_eq!(
+            stdlib_rejects("unique 1", ErrorCode::Native),
+            "a: unique: expected a list, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_range() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("range 3"), serde_json::json!([0, 1, 2]));
+        assert_eq!(
+            stdlib_call("range { from: 2, to: 4 }"),
+            serde_json::json!([2, 3])
+        );
+        assert_eq!(
+            stdlib_call("range { from: 0, to: 3 }"),
+            stdlib_call("range 3")
+        );
+        let err = parse_to_json("a: range { to: 3 }").unwrap_err();
+        assert!(err.contains("a: value of type call range is unspecified"));
+        assert_eq!(
+            stdlib_rejects(r#"range "a""#, ErrorCode::Conflict),
+            "a: values do not unify"
+        );
+    }
+
+    #[test]
+    fn stdlib_keys() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call("keys { x: 1, y: 2 }"),
+            serde_json::json!(["x", "y"])
+        );
+        assert_eq!(
+            stdlib_rejects("keys [1]", ErrorCode::Native),
+            "a: keys: expected an object, got a list"
+        );
+    }
+
+    #[test]
+    fn stdlib_values() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call("values { x: 1, y: 2 }"),
+            serde_json::json!([1, 2])
+        );
+        assert_eq!(
+            stdlib_rejects("values 1", ErrorCode::Native),
+            "a: values: expected an object, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_merge() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call("merge [{ a: 1, b: 2 }, { b: 3 }]"),
+            serde_json::json!({ "a": 1, "b": 3 })
+        );
+        assert_eq!(
+            stdlib_rejects("merge [1]", ErrorCode::Native),
+            "a: merge: expected an object, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_pick() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"pick { object: { a: 1, b: 2 }, keys: ["b"] }"#),
+            serde_json::json!({ "b": 2 })
+        );
+        assert_eq!(
+            stdlib_rejects("pick { object: { a: 1 }, keys: [1] }", ErrorCode::Native),
+            "a: pick: expected a String key, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_omit() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"omit { object: { a: 1, b: 2 }, keys: ["b"] }"#),
+            serde_json::json!({ "a": 1 })
+        );
+        assert_eq!(
+            stdlib_rejects(r#"omit { object: 1, keys: ["a"] }"#, ErrorCode::Native),
+            "a: omit: expected an object, got Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_min() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("min [3, 1, 2]"), 1);
+        assert_eq!(
+            stdlib_rejects("min []", ErrorCode::Native),
+            "a: min: empty list"
+        );
+    }
+
+    #[test]
+    fn stdlib_max() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"max ["b", "c", "a"]"#), "c");
+        assert_eq!(
+            stdlib_rejects(r#"max [1, "a"]"#, ErrorCode::Native),
+            "a: max: cannot compare String and Int"
+        );
+    }
+
+    #[test]
+    fn stdlib_abs() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("abs (0 - 4)"), 4);
+        assert_eq!(
+            stdlib_rejects(r#"abs "a""#, ErrorCode::TypeMismatch),
+            "a: expected number"
+        );
+    }
+
+    #[test]
+    fn stdlib_floor() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("floor (7 / 2)"), 3);
+        assert_eq!(
+            stdlib_rejects("floor 1e400", ErrorCode::Native),
+            "a: floor: cannot floor inf"
+        );
+    }
+
+    #[test]
+    fn stdlib_ceil() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("ceil 2.1"), 3);
+        assert_eq!(
+            stdlib_rejects(r#"ceil "a""#, ErrorCode::TypeMismatch),
+            "a: expected number"
+        );
+    }
+
+    #[test]
+    fn stdlib_round() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call("round 2.5"), 3);
+        assert_eq!(
+            stdlib_rejects(r#"round "a""#, ErrorCode::TypeMismatch),
+            "a: expected number"
+        );
+    }
+
+    #[test]
+    fn stdlib_base64_encode() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"base64Encode "hi""#), "aGk=");
+        assert_eq!(
+            stdlib_rejects("base64Encode 1", ErrorCode::TypeMismatch),
+            "a: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_base64_decode() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(stdlib_call(r#"base64Decode "aGk=""#), "hi");
+        assert_eq!(
+            stdlib_rejects(r#"base64Decode "!""#, ErrorCode::Native),
+            "a: base64Decode: invalid base64: Invalid symbol 33, offset 0."
+        );
+    }
+
+    #[test]
+    fn stdlib_json_encode() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"jsonEncode { x: [1, "y"] }"#),
+            "{\"x\":[1,\"y\"]}"
+        );
+        assert_eq!(
+            stdlib_rejects("jsonEncode { x: 1/3 }", ErrorCode::Native),
+            "a: jsonEncode: x: 1/3 cannot be represented exactly"
+        );
+    }
+
+    #[test]
+    fn stdlib_json_decode() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"jsonDecode "{\"x\": [1, 2.5, null]}""#),
+            serde_json::json!({ "x": [1, 2.5, null] })
+        );
+        // Integers beyond 64 bits keep every digit.
+        let unified = must_unify(
+            "a: jsonDecode \"[18446744073709551616, -99999999999999999999999]\"\na: [18446744073709551616, -99999999999999999999999]\nb: jsonEncode (jsonDecode \"{ \\\"x\\\": 99999999999999999999999 }\")",
+        );
+        assert_eq!(
+            unified.select("b").unwrap().kind,
+            ValueKind::String(r#"{"x":99999999999999999999999}"#.into())
+        );
+        assert_eq!(
+            stdlib_rejects(r#"jsonDecode "{""#, ErrorCode::Native),
+            "a: jsonDecode: invalid JSON: EOF while parsing an object at line 1 column 1"
+        );
+    }
+
+    #[test]
+    fn stdlib_sha256() {
+        use crate::diagnostic::ErrorCode;
+        assert_eq!(
+            stdlib_call(r#"sha256 "abc""#),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            stdlib_rejects("sha256 1", ErrorCode::TypeMismatch),
+            "a: expected string"
+        );
+    }
+
+    #[test]
+    fn stdlib_calls_wait_for_concrete_arguments() {
+        let unified = must_unify("a: upper t\nt: String");
+        assert!(export::find_unresolved(&unified).is_some());
+        let unified = must_unify("a: upper t\nt: String\nt: \"x\"");
+        assert_eq!(
+            unified.select("a").unwrap().to_value(),
+            Value::String("X".into())
+        );
+    }
+
+    const GREET: &str = r#"greet: @Function
+greet: {
+  arg: { name: String, greeting: String }
+  default: { greeting: "hello" }
+  return: "\(arg.greeting), \(arg.name)"
+}
+"#;
+
+    #[test]
+    fn call_with_named_parameters_and_defaults() {
+        let json: serde_json::Value = serde_json::from_str(
+            &parse_to_json(&format!(
+                "{}a: greet {{ name: \"ann\" }}\nb: greet {{ name: \"bob\", greeting: \"hi\" }}",
+                GREET
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "a": "hello, ann", "b": "hi, bob" })
+        );
+    }
+
+    #[test]
+    fn call_with_positional_arguments() {
+        let json: serde_json::Value = serde_json::from_str(
+            &parse_to_json(&format!(
+                "{}a: greet(\"ann\")\nb: greet(\"bob\", \"hi\",)\nc: split(\"a,b\", \",\")\nd: upper(\"x\")\nf: @Function\nf: {{ arg: Int, return: arg + 1 }}\ne: f(1)",
+                GREET
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "a": "hello, ann", "b": "hi, bob", "c": ["a", "b"], "d": "X", "e": 2
+            })
+        );
+        // A call waiting for its arguments prints them the same way.
+        let unified = must_unify("a: upper(t)\nt: String");
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: upper(String)\nt: String\n"
+        );
+        assert_round_trips(&unified);
+    }
+
+    #[test]
+    fn call_argument_errors_point_at_the_call() {
+        use crate::diagnostic::ErrorCode;
+        let check = |call: &str, code: ErrorCode, msg: &str, at: &str| {
+            let src = format!("{}x: {}", GREET, call);
+            let err = parse_unify(&src).unwrap_err();
+            assert_eq!((err.code, err.msg.as_str()), (code, msg));
+            assert_eq!(&src[err.span.into_range()], at);
+        };
+        check(
+            "greet(\"a\", \"b\", \"c\")",
+            ErrorCode::Arguments,
+            "x: greet: expected at most 2 arguments, got 3",
+            "\"c\"",
+        );
+        check(
+            "greet { nam: \"a\" }",
+            ErrorCode::Arguments,
+            "x: greet: unknown argument nam",
+            "nam: \"a\"",
+        );
+        check(
+            "greet()",
+            ErrorCode::Arguments,
+            "x: greet: missing argument name",
+            "x: greet()",
+        );
+        check(
+            "greet(1)",
+            ErrorCode::TypeMismatch,
+            "x.name: expected string",
+            "1",
+        );
+        check(
+            "upper(\"a\", \"b\")",
+            ErrorCode::Arguments,
+            "x: upper: expected 1 argument, got 2",
+            "(\"a\", \"b\")",
+        );
+    }
+
+    #[test]
+    fn format_keeps_positional_calls() {
+        assert_eq!(fmt("a: f( 1,2 )\nb: f (1)\n"), "a: f(1, 2)\nb: f (1)\n");
+        let src = "a: f(\n  1,\n  2,\n)\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(fmt(src), src);
+    }
+
+    fn unify_with_limits(src: &str, limits: &Limits) -> Result<SpannedValue, UnifyError> {
+        let parsed = parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
+        unify_tree_with_limits(&parsed, &Natives::builtin(), limits)
+    }
+
+    #[test]
+    fn limits_stop_runaway_recursion() {
+        use crate::diagnostic::ErrorCode;
+        let err =
+            parse_unify("f: @Function\nf: { arg: Int, return: f(arg) }\na: f(1)").unwrap_err();
+        assert_eq!(err.code, ErrorCode::DepthLimit);
+        assert_eq!(err.msg, "a: function calls nest more than 50 deep");
+        let (_, conflicts) = unify_tree_collecting(
+            &parser()
+                .parse(input(
+                    "f: @Function\nf: { arg: Int, return: f(arg) }\na: f(1)\nb: 2",
+                    SourceId::default(),
+                ))
+                .into_result()
+                .unwrap(),
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].error.code, ErrorCode::DepthLimit);
+    }
+
+    #[test]
+    fn recursive_calls_check_each_argument_against_the_spec() {
+        use crate::diagnostic::ErrorCode;
+        let err =
+            parse_unify("f: @Function\nf: { arg: Int, return: f(arg + 1) }\nx: f(1)").unwrap_err();
+        assert_eq!(err.code, ErrorCode::DepthLimit);
+        assert_eq!(err.msg, "x: function calls nest more than 50 deep");
+    }
+
+    #[test]
+    fn limits_are_configurable() {
+        use crate::diagnostic::ErrorCode;
+        let chain = |n: usize| {
+            let mut src = "a0: 1\n".to_string();
+            for i in 1..n {
+                src.push_str(&format!("a{}: a{}\n", i, i - 1));
+            }
+            src
+        };
+        let calls = |n: usize| {
+            let mut src = "f0: @Function\nf0: { arg: Int, return: arg }\n".to_string();
+            for i in 1..n {
+                src.push_str(&format!(
+                    "f{i}: @Function\nf{i}: {{ arg: Int, return: f{}(arg) }}\n",
+                    i - 1
+                ));
+            }
+            src + &format!("a: f{}(1)", n - 1)
+        };
+        let limits = Limits {
+            max_depth: 10,
+            ..Limits::default()
+        };
+        assert!(unify_with_limits(&calls(10), &limits).is_ok());
+        let err = unify_with_limits(&calls(20), &limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::DepthLimit);
+        // References are not calls, so long chains of them do not count.
+        assert!(unify_with_limits(&chain(1000), &limits).is_ok());
+
+        let limits = Limits {
+            max_steps: 1_000,
+            ..Limits::default()
+        };
+        let err = unify_with_limits(&chain(2000), &limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StepLimit);
+        assert!(err.msg.ends_with("evaluation took more than 1000 steps"));
+
+        let limits = Limits {
+            max_output: 50,
+            ..Limits::default()
+        };
+        assert!(unify_with_limits("a: range 10", &limits).is_ok());
+        let err = unify_with_limits("a: range 100", &limits).unwrap_err();
+        assert_eq!(
+            (err.code, err.msg.as_str()),
+            (ErrorCode::SizeLimit, "a: value is larger than 50")
+        );
+        // Natives that can build large values check the limit first.
+        let huge = [
+            "a: len (range 300000000)",
+            "a: replace { s: \"xxxxxxxxxxxxxxxxxxxx\", from: \"x\", to: \"yyy\" }",
+            "a: join { list: range 6, sep: \"0123456789\" }",
+            "a: split { s: \"0123456789012345678901234567890123456789\", sep: \"\" }",
+            "a: concat [range 30, range 30]",
+        ];
+        for src in huge {
+            let err = unify_with_limits(src, &limits).unwrap_err();
+            assert_eq!(
+                (err.code, err.msg.as_str()),
+                (ErrorCode::SizeLimit, "a: value is larger than 50"),
+                "{}",
+                src
+            );
+        }
+        assert!(unify_with_limits("a: concat [range 20, range 20]", &limits).is_ok());
+        assert!(unify_with_limits("a: split { s: \"a-b-c\", sep: \"-\" }", &limits).is_ok());
+        let err = unify_with_limits("a: len (range 300000000)", &Limits::default()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::SizeLimit);
+
+        let src = "a: \"0123456789\"\nb: a + a + a + a + a + a";
+        let err = unify_with_limits(src, &limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::SizeLimit);
+        assert_eq!(&src[err.span.into_range()], "b: a + a + a + a + a + a");
+        let err = unify_with_limits(
+            "a: \"\\(b)\\(b)\"\nb: \"0123456789012345678901234567\"",
+            &limits,
+        )
+        .unwrap_err();
+        assert_eq!(err.code, ErrorCode::SizeLimit);
+    }
+
+    #[test]
+    fn deep_expressions_fail_instead_of_overflowing_the_stack() {
+        use crate::diagnostic::ErrorCode;
+        let sum = |n: usize| format!("a: 1{}", " + 1".repeat(n - 1));
+        let deep = sum(10_000);
+        let err = parse_unify(&deep).unwrap_err();
+        assert_eq!(err.code, ErrorCode::DepthLimit);
+        assert_eq!(err.msg, "a: values nest more than 100 deep");
+        let (_, conflicts) = unify_tree_collecting(
+            &parser()
+                .parse(input(&deep, SourceId::default()))
+                .into_result()
+                .unwrap(),
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "a");
+        assert_eq!(conflicts[0].error.code, ErrorCode::DepthLimit);
+        let doc = document()
+            .parse(input(&deep, SourceId::default()))
+            .into_result()
+            .unwrap();
+        let err = merge_documents(&[doc]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::DepthLimit);
+        assert_eq!(
+            must_unify(&sum(100)).to_value().to_value().unwrap()["a"],
+            100
+        );
+    }
+
+    #[test]
+    fn long_reference_chains_take_steps_in_proportion_to_their_length() {
+        use crate::diagnostic::ErrorCode;
+        let parse = |src: &str| {
+            parser()
+                .parse(input(src, SourceId::default()))
+                .into_result()
+                .unwrap()
+        };
+        let mut src = String::new();
+        for i in 0..3000 {
+            src.push_str(&format!("a{}: a{} + 1\nb{}: a{}\n", i, i + 1, i, i));
+        }
+        // Each field is resolved once however many others refer to it, so
+        // ten steps a field is plenty.
+        let limits = Limits {
+            max_steps: 30_000,
+            ..Limits::default()
+        };
+        let unified = unify_with_limits(&format!("{}a3000: 1", src), &limits).unwrap();
+        let json = unified.to_value().to_value().unwrap();
+        assert_eq!((&json["a0"], &json["b0"]), (&3001.into(), &3001.into()));
+
+        // Running out of steps part way fails the rest without blowing up.
+        let limits = Limits {
+            max_steps: 5_000,
+            ..Limits::default()
+        };
+        let parsed = parse(&format!("{}a3000: 1", src));
+        let (_, conflicts) =
+            unify_tree_collecting_with_limits(&parsed, &Natives::builtin(), &limits);
+        assert!(!conflicts.is_empty());
+        assert!(
+            conflicts
+                .iter()
+                .all(|c| c.error.code == ErrorCode::StepLimit)
+        );
+
+        // A failure at the end of the chain is reported once.
+        let (_, conflicts) = unify_tree_collecting(&parse(&format!("{}a3000: \"x\"", src)));
+        let reported: Vec<_> = conflicts.iter().filter(|c| !c.consequential).collect();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].path, "a2999");
+    }
+
+    #[test]
+    fn list_types_unify_with_arrays_of_any_length() {
+        let json: serde_json::Value = serde_json::from_str(
+            &parse_to_json(
+                r#"ports: [...Int]
+ports: [80, 443]
+empty: [...Int]
+empty: []
+pair: [String, ...Int]
+pair: ["a", 1, 2]
+tags: [...String]{1,3}
+tags: ["x"]
+people: [...{ name: String }]
+people: [{ name: "a" }, { name: "b" }]"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "ports": [80, 443], "empty": [], "pair": ["a", 1, 2], "tags": ["x"],
+                "people": [{ "name": "a" }, { "name": "b" }]
+            })
+        );
+    }
+
+    #[test]
+    fn list_type_errors_name_the_index() {
+        use crate::diagnostic::ErrorCode;
+        let src = "a: [...Int]\na: [1, \"x\"]";
+        let err = parse_unify(src).unwrap_err();
+        assert_eq!(err.msg, "a[1]: expected integer");
+        assert_eq!(&src[err.span.into_range()], "\"x\"");
+        let err = parse_unify("a: [...{ n: Int }]\na: [{ n: 1 }, { n: \"x\" }]").unwrap_err();
+        assert_eq!(err.msg, "a[1].n: expected integer");
+        let err = parse_unify("a: [String, ...Int]\na: []").unwrap_err();
+        assert_eq!(
+            (err.code, err.msg.as_str()),
+            (ErrorCode::Conflict, "a: expected at least 1 item, got 0")
+        );
+        let err = parse_unify("a: [...Int]{1,2}\na: [1, 2, 3]").unwrap_err();
+        assert_eq!(err.msg, "a: expected between 1 and 2 items, got 3");
+    }
+
+    #[test]
+    fn list_types_unify_with_each_other() {
+        let unified =
+            must_unify("a: [...Int]{2,}\na: [Int, ...Int]{0,4}\nb: [...Any]\nb: [...String]");
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: [Int, ...Int]{2,4}\nb: [...String]\n"
+        );
+        assert_round_trips(&unified);
+        let (_, err) = export::find_unresolved(&unified).unwrap();
+        assert_eq!(err.to_string(), "a: value of type list is unspecified");
+        let err = parse_unify("a: [...Int]{3,}\na: [...Int]{0,2}").unwrap_err();
+        assert_eq!(
+            err.msg,
+            "a: list lengths do not overlap: at least 3 items and at most 2 items"
+        );
+        let err = parse_unify("a: [...Int]\na: [...String]").unwrap_err();
+        assert_eq!(err.msg, "a[...]: Int cannot be unified with String");
+        let err = parse_unify("a: [Int, ...Int]\na: [Int, String, ...String]").unwrap_err();
+        assert_eq!(err.msg, "a[1]: Int cannot be unified with String");
+    }
+
+    #[test]
+    fn list_type_bounds_are_checked_when_parsed() {
+        let errors = |src| {
+            parser()
+                .parse(input(src, SourceId::default()))
+                .into_errors()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            errors("a: [1]{1}"),
+            ["length bounds need a rest type such as `...Int`"]
+        );
+        assert_eq!(
+            errors("a: [...Int]{3,1}"),
+            ["minimum length 3 is more than maximum 1"]
+        );
+        assert!(errors("a: [...Int]{3}\nb: [Int,\n  ...String,\n]").is_empty());
+    }
+
+    #[test]
+    fn list_types_take_a_maximum_alone() {
+        let unified = must_unify("a: [...Int]{,2}\na: [1, 2]\nb: [...Int]{ , 2}");
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: [1, 2]\nb: [...Int]{0,2}\n"
+        );
+        let err = parse_unify("a: [...Int]{,2}\na: [1, 2, 3]").unwrap_err();
+        assert_eq!(err.msg, "a: expected at most 2 items, got 3");
+        assert_eq!(fmt("a: [...Int]{ ,2 }\n"), "a: [...Int]{,2}\n");
+        let errors = parser()
+            .parse(input("a: [...Int]{,}", SourceId::default()))
+            .into_errors();
+        assert_eq!(
+            errors[0].to_string(),
+            "length bounds need a minimum or maximum"
+        );
+    }
+
+    #[test]
+    fn string_types_take_a_maximum_alone() {
+        let unified = must_unify("a: String{,3}\na: \"abc\"\nb: String{,3}");
+        assert_eq!(printer::to_string(&unified), "a: \"abc\"\nb: String{0,3}\n");
+        let err = parse_unify("a: String{,3}\na: \"abcd\"").unwrap_err();
+        assert_eq!(err.msg, "a: expected String{0,3}, got \"abcd\"");
+        assert_eq!(fmt("a: String{ , 3}\n"), "a: String{,3}\n");
+    }
+
+    #[test]
+    fn format_keeps_list_types() {
+        let src = "a: [1, ...Int]{1,5}\nb: [\n  ...String, # names\n]{2,}\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(fmt(src), src);
+        assert_eq!(fmt("a: [ 1,...Int ]{ 1 , 5 }\n"), "a: [1, ...Int]{1,5}\n");
+    }
+
+    const SERVER: &str =
+        "Server: @NoExport\nServer: close { host: String, port?: Int, tls?: { cert: String } }\n";
+
+    #[test]
+    fn optional_fields_are_exported_only_with_a_value() {
+        let json = stdlib_json(&format!(
+            "{}a: Server\na: host: \"x\"\nb: Server\nb: {{ host: \"y\", port: 8080 }}\n",
+            SERVER
+        ));
+        assert_eq!(
+            json,
+            serde_json::json!({ "a": { "host": "x" }, "b": { "host": "y", "port": 8080 } })
+        );
+        let err = parse_unify(&format!("{}c: Server\nc: {{ port: \"80\" }}", SERVER)).unwrap_err();
+        assert_eq!(err.msg, "c.port: expected integer");
+        // Left out of the shortcut for equal values, so the type still applies.
+        let err = parse_unify("e: { port?: Int }\ne: {}\ne: { port: \"x\" }").unwrap_err();
+        assert_eq!(err.msg, "e.port: expected integer");
+        // A member is optional only if every occurrence is.
+        assert!(parse_to_json("d: { port?: Int }\nd: { port: Int }").is_err());
+        assert_eq!(
+            stdlib_json("d: { port?: Int }\nd: port?: Int"),
+            serde_json::json!({ "d": {} })
+        );
+    }
+
+    #[test]
+    fn closed_structs_reject_unknown_fields() {
+        use crate::diagnostic::ErrorCode;
+        let src = format!("{}web: Server\nweb: {{ host: \"x\", extra: 1 }}", SERVER);
+        let err = parse_unify(&src).unwrap_err();
+        assert_eq!(
+            (err.code, err.msg.as_str()),
+            (
+                ErrorCode::UnknownField,
+                "web: field extra is not allowed by a closed struct"
+            )
+        );
+        assert_eq!(&src[err.span.into_range()], "extra: 1");
+        assert!(src[err.prev_span.into_range()].starts_with("Server: close {"));
+        // Still closed after unifying with `Any` or an open struct. Nested
+        // structs stay open.
+        let src = format!(
+            "{}web: Any\nweb: Server\nweb: {{ host: \"x\" }}\nweb: {{ tls: {{ key: 1 }}, more: 2 }}",
+            SERVER
+        );
+        let err = parse_unify(&src).unwrap_err();
+        assert_eq!(err.msg, "web: field more is not allowed by a closed struct");
+        let err = parse_unify("a: close {}").unwrap_err();
+        assert_eq!(
+            err.msg,
+            "a: close expects an object with at least one member, got an empty object"
+        );
+    }
+
+    #[test]
+    fn a_function_named_close_replaces_the_builtin() {
+        let json = stdlib_json(
+            "close: @Function\nclose: { arg: { x: Int }, return: arg.x + 1 }\na: close { x: 1 }",
+        );
+        assert_eq!(json["a"], 2);
+    }
+
+    #[test]
+    fn printer_writes_optional_fields_and_closed_structs() {
+        let unified = must_unify("a: close { x?: Int, y: 1 }\nb?: @NoExport\nb?: { c?: String }");
+        assert_eq!(
+            printer::to_string(&unified),
+            "a: close {\n  x?: Int\n  y: 1\n}\nb?: @NoExport\nb?: {\n  c?: String\n}\n"
+        );
+        assert_round_trips(&unified);
+    }
+
+    #[test]
+    fn format_keeps_optional_fields() {
+        let src = "a?: Int\nb: { c?: String, \"d e\"?: close { f: 1 } }\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(fmt(src), src);
+        assert_eq!(fmt("a?:Int\n"), "a?: Int\n");
+    }
+
+    #[test]
+    fn defaults_apply_when_nothing_narrows_the_value() {
+        let json = stdlib_json(
+            r#"Log: @NoExport
+Log: { level: *"info" | "debug" | "warn", port: Int | *8080 }
+a: Log
+b: Log
+b: { level: "debug", port: 9000 }
+c: Log
+c: level: String
+d: "level \(c.level)"
+e: upper c.level
+"#,
+        );
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "a": { "level": "info", "port": 8080 },
+                "b": { "level": "debug", "port": 9000 },
+                "c": { "level": "info", "port": 8080 },
+                "d": "level info",
+                "e": "INFO",
+            })
+        );
+        // Narrowing away the default leaves the other alternatives.
+        let err = parse_to_json("x: *1 | 2 | 3\nx: 2 | 3").unwrap_err();
+        assert!(
+            err.contains("value of type union is unspecified"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            stdlib_json("x: *1 | 2 | 3\nx: 1 | 3"),
+            serde_json::json!({ "x": 1 })
+        );
+        assert_eq!(
+            stdlib_json("x: *Int | String\nx: *3 | \"a\""),
+            serde_json::json!({ "x": 3 })
+        );
+    }
+
+    #[test]
+    fn clashing_defaults_point_at_both() {
+        let src = "x: *\"a\" | \"b\"\nx: *\"b\" | \"a\"";
+        let err = parse_unify(src).unwrap_err();
+        assert_eq!(err.msg, "x: defaults \"a\" and \"b\" do not unify");
+        assert_eq!(&src[err.span.into_range()], "*\"b\"");
+        assert_eq!(&src[err.prev_span.into_range()], "*\"a\"");
+        let err = parser()
+            .parse(input("x: *1", SourceId::default()))
+            .into_result()
+            .unwrap_err();
+        assert_eq!(
+            err[0].to_string(),
+            "a default needs other alternatives, as in `*1 | Int`"
+        );
+    }
+
+    #[test]
+    fn printer_and_formatter_keep_defaults() {
+        let parsed = parser()
+            .parse(input(
+                "x: *\"a\" | String\ny: Int | *(1 + 2)",
+                SourceId::default(),
+            ))
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            printer::to_string(&parsed),
+            "x: *\"a\" | String\ny: Int | *1 + 2\n"
+        );
+        let src = "x: *\"a\" | String\ny: Int | *1 + 2\n";
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(fmt("x: *\"a\"|String\n"), "x: *\"a\" | String\n");
+    }
+
+    #[test]
+    fn bounds_narrow_and_check_numbers() {
+        let json = stdlib_json(
+            r#"Port: @NoExport
+Port: Int & >=1 & <=65535
+a: Port
+a: 8080
+replicas: >0
+replicas: 3
+half: >=1/2
+half: 0.5
+"#,
+        );
+        assert_eq!(
+            json,
+            serde_json::json!({ "a": 8080, "replicas": 3, "half": 0.5 })
+        );
+        let parsed = parse_unify("x: >=1\nx: <=10\nx: Int").unwrap();
+        assert_eq!(printer::to_string(&parsed), "x: Int & >=1 & <=10\n");
+        let src = "port: Int & >=1 & <=65535\nport: 0";
+        let err = parse_unify(src).unwrap_err();
+        assert_eq!(err.msg, "port: expected >=1, got 0");
+        assert_eq!(&src[err.span.into_range()], "port: 0");
+        let err = parse_unify("x: <2.5\nx: 3.5").unwrap_err();
+        assert_eq!(err.msg, "x: expected <5/2, got 3.5");
+        let err = parse_unify("x: Int & >1\nx: 1.5").unwrap_err();
+        assert_eq!(err.msg, "x: expected integer");
+    }
+
+    #[test]
+    fn contradictory_bounds_fail_without_a_value() {
+        let err = parse_unify("x: >5 & <3").unwrap_err();
+        assert_eq!(err.msg, "x: no number is >5 and <3");
+        let err = parse_unify("x: >1\nx: Int & <2").unwrap_err();
+        assert_eq!(err.msg, "x: no integer is >1 and <2");
+        let err = parse_unify("x: >=1\nx: String").unwrap_err();
+        assert_eq!(err.msg, "x: >=1 cannot be unified with String");
+        assert!(parse_unify("x: >=2 & <=2\nx: Int").is_ok());
+    }
+
+    #[test]
+    fn printer_and_formatter_keep_bounds() {
+        let src = "port: Int & >=1 & <=65535\nreplicas: >0\n";
+        let parsed = parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
+        assert_eq!(printer::to_string(&parsed), src);
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(
+            fmt("port: Int&>=1 &<=65535\n"),
+            "port: Int & >=1 & <=65535\n"
+        );
+    }
+
+    #[test]
+    fn string_patterns_and_lengths_narrow_and_check_strings() {
+        let json = stdlib_json(
+            r#"Label: @NoExport
+Label: String{1,63} & =~"^[a-z0-9-]+$" & !~"^-"
+a: Label
+a: "web-1"
+b: String{3}
+b: "héé"
+"#,
+        );
+        assert_eq!(json, serde_json::json!({ "a": "web-1", "b": "héé" }));
+        let parsed = parse_unify("x: =~\"^a\"\nx: String{1,}\nx: String{0,5} & =~\"^a\"").unwrap();
+        assert_eq!(printer::to_string(&parsed), "x: String{1,5} & =~\"^a\"\n");
+        let label = "Label: @NoExport\nLabel: String{1,63} & =~\"^[a-z0-9-]+$\" & !~\"^-\"\n";
+        let err = parse_unify(&format!("{}hosts: [Label]\nhosts: [\"Web\"]", label)).unwrap_err();
+        assert_eq!(
+            err.msg,
+            "hosts[0]: expected =~\"^[a-z0-9-]+$\", got \"Web\""
+        );
+        let err = parse_unify(&format!("{}a: Label\na: \"-web\"", label)).unwrap_err();
+        assert_eq!(err.msg, "a: expected !~\"^-\", got \"-web\"");
+        let err = parse_unify(&format!("{}a: Label\na: \"\"", label)).unwrap_err();
+        assert_eq!(err.msg, "a: expected String{1,63}, got \"\"");
+        let err = parse_unify("a: =~\"x\"\na: 1").unwrap_err();
+        assert_eq!(err.msg, "a: expected string");
+    }
+
+    #[test]
+    fn contradictory_string_lengths_fail_without_a_value() {
+        let err = parse_unify("x: String{5,}\nx: String{0,3} & =~\"a\"").unwrap_err();
+        assert_eq!(
+            err.msg,
+            "x: no string has at least 5 and at most 3 characters"
+        );
+        let err = parse_unify("x: =~\"a\" & Int").unwrap_err();
+        assert_eq!(err.msg, "x: =~\"a\" cannot be unified with Int");
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported_on_the_pattern() {
+        use crate::diagnostic::{Diagnostic, ErrorCode};
+        let err = parser()
+            .parse(input("x: =~\"(\"", SourceId::default()))
+            .into_result()
+            .unwrap_err();
+        let d = Diagnostic::parse(&err[0]);
+        assert_eq!(d.code, ErrorCode::InvalidPattern);
+        assert_eq!(d.message, "invalid pattern \"(\": unclosed group");
+        assert_eq!(d.span.into_range(), 5..8);
+    }
+
+    #[test]
+    fn printer_and_formatter_keep_string_constraints() {
+        let src = "name: String{1,63} & =~\"^[a-z]+$\" & !~\"^x\"\ncode: String{3}\n";
+        let parsed = parser()
+            .parse(input(src, SourceId::default()))
+            .into_result()
+            .unwrap();
+        assert_eq!(printer::to_string(&parsed), src);
+        assert_eq!(cst::parse(src).unwrap().to_string(), src);
+        assert_eq!(
+            fmt("name: String{1, 63}&=~\"^[a-z]+$\"\n"),
+            "name: String{1,63} & =~\"^[a-z]+$\"\n"
+        );
+    }
+
+    #[test]
+    fn imports_of_imports_keep_their_values() {
+        let loader: MemoryLoader = [
+            (
+                "lib/animals.pls",
+                "Dog: @NoExport\nDog: { name: String, sound: \"woof\" }",
+            ),
+            (
+                "lib/inner.pls",
+                "import \"animals.pls\" as an\ncat: an.Dog\ncat: name: \"c\"",
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let src = "import \"lib/inner.pls\" as i\nq: i.cat";
+        let json: serde_json::Value =
+            serde_json::from_str(&parse_to_json_with_loader(src, &loader).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"q": {"name": "c", "sound": "woof"}})
+        );
+    }
+
     #[test]
     fn call_increment_literal() {
         let src = r#"increment: @Function
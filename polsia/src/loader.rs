@@ -0,0 +1,199 @@
+use crate::parser::{ParseError, document, input};
+use crate::report::SourceMap;
+use crate::types::{Annotation, Document, Span, SpannedValue, ValueKind};
+use chumsky::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Supplies the source text of imported files.
+pub trait Loader {
+    /// Turns `path`, as written in an import inside `importer`, into the name
+    /// the file is loaded and reported under. Two imports of the same file
+    /// must resolve to the same name for cycle detection to work.
+    fn resolve(&self, importer: &str, path: &str) -> String {
+        join_relative(importer, path)
+    }
+
+    fn load(&self, name: &str) -> Result<String, String>;
+}
+
+/// Reads imports from disk, relative to the importing file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsLoader;
+
+impl Loader for FsLoader {
+    fn load(&self, name: &str) -> Result<String, String> {
+        std::fs::read_to_string(name).map_err(|e| format!("cannot read {}: {}", name, e))
+    }
+}
+
+/// Serves imports from an in-memory map of file names to sources.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    files: BTreeMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, src: impl Into<String>) {
+        self.files.insert(normalize_path(&name.into()), src.into());
+    }
+}
+
+impl<N: Into<String>, S: Into<String>> FromIterator<(N, S)> for MemoryLoader {
+    fn from_iter<I: IntoIterator<Item = (N, S)>>(iter: I) -> Self {
+        let mut loader = MemoryLoader::new();
+        for (name, src) in iter {
+            loader.insert(name, src);
+        }
+        loader
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<String, String> {
+        self.files
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("cannot find {}", name))
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// An imported file failed to parse.
+    Parse(Vec<ParseError<'static>>),
+    /// An import could not be resolved; `span` covers the import statement.
+    Import { msg: String, span: Span },
+    /// An import alias names a top-level key the document already has, or
+    /// another import's alias. `span` covers the import statement and
+    /// `prev_span` the earlier definition.
+    Alias {
+        alias: String,
+        span: Span,
+        prev_span: Span,
+    },
+}
+
+/// Resolves the imports of an already parsed document named `importer`,
+/// binding each imported file's value under its alias.
+pub fn resolve_imports(
+    doc: Document,
+    importer: &str,
+    loader: &dyn Loader,
+    sources: &mut SourceMap,
+) -> Result<Document, LoadError> {
+    let mut session = Session::new(loader, sources);
+    session.stack.push(importer.to_string());
+    session.link(doc, importer)
+}
+
+struct Session<'a> {
+    loader: &'a dyn Loader,
+    sources: &'a mut SourceMap,
+    stack: Vec<String>,
+    loaded: HashMap<String, SpannedValue>,
+}
+
+impl<'a> Session<'a> {
+    fn new(loader: &'a dyn Loader, sources: &'a mut SourceMap) -> Self {
+        Session {
+            loader,
+            sources,
+            stack: Vec::new(),
+            loaded: HashMap::new(),
+        }
+    }
+
+    fn load_source(&mut self, name: &str, src: String) -> Result<Document, LoadError> {
+        let id = self.sources.add(name, src);
+        let doc = document()
+            .parse(input(self.sources.text(id), id))
+            .into_result()
+            .map_err(|errs| LoadError::Parse(errs.into_iter().map(|e| e.into_owned()).collect()))?;
+        self.stack.push(name.to_string());
+        let linked = self.link(doc, name);
+        self.stack.pop();
+        linked
+    }
+
+    fn link(&mut self, mut doc: Document, importer: &str) -> Result<Document, LoadError> {
+        for import in &doc.imports {
+            let name = self.loader.resolve(importer, &import.path);
+            if let Some(start) = self.stack.iter().position(|n| *n == name) {
+                let mut cycle = self.stack[start..].to_vec();
+                cycle.push(name);
+                return Err(LoadError::Import {
+                    msg: format!("import cycle: {}", cycle.join(" -> ")),
+                    span: import.span,
+                });
+            }
+            let value = match self.loaded.get(&name) {
+                Some(value) => value.clone(),
+                None => {
+                    let src = self.loader.load(&name).map_err(|msg| LoadError::Import {
+                        msg,
+                        span: import.span,
+                    })?;
+                    let value = self.load_source(&name, src)?.value;
+                    self.loaded.insert(name, value.clone());
+                    value
+                }
+            };
+            match &mut doc.value.kind {
+                ValueKind::Object(members) => {
+                    if let Some((_, _, prev_span, _)) =
+                        members.iter().find(|(k, _, _, _)| *k == import.alias)
+                    {
+                        return Err(LoadError::Alias {
+                            alias: import.alias.clone(),
+                            span: import.span,
+                            prev_span: *prev_span,
+                        });
+                    }
+                    members.push((
+                        import.alias.clone(),
+                        value,
+                        import.span,
+                        vec![Annotation::NoExport],
+                    ))
+                }
+                _ => {
+                    return Err(LoadError::Import {
+                        msg: "imports require a top-level object".into(),
+                        span: import.span,
+                    });
+                }
+            }
+        }
+        Ok(doc)
+    }
+}
+
+fn join_relative(importer: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        return normalize_path(path);
+    }
+    match importer.rfind('/') {
+        Some(i) => normalize_path(&format!("{}/{}", &importer[..i], path)),
+        None => normalize_path(path),
+    }
+}
+
+/// Lexically removes `.` and `..` segments so the same file always gets the
+/// same name.
+fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." if !parts.is_empty() || seg == "." => {}
+            ".." if parts.last().is_some_and(|p| *p != ".." && !p.is_empty()) => {
+                parts.pop();
+            }
+            _ => parts.push(seg),
+        }
+    }
+    parts.join("/")
+}
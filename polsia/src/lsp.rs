@@ -0,0 +1,558 @@
+//! A Language Server Protocol server for Polsia files.
+//!
+//! [`Server`] handles decoded JSON-RPC messages and knows nothing about the
+//! transport, so it can be driven in-process; [`run`] connects it to a pair
+//! of streams using the LSP base protocol. Documents are synced in full.
+
+use crate::cst::{self, Body, Expr, Item, TokenKind};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::loader::{Loader, resolve_imports};
+use crate::parser::{document, input};
+use crate::printer;
+use crate::report::SourceMap;
+use crate::types::{SourceId, SpannedValue, ValueKind};
+use crate::unify::unify_tree_collecting;
+use chumsky::prelude::*;
+use serde_json::{Value as Json, json};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+/// Reads messages from `input` and writes replies to `output` until the
+/// client sends `exit`.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(msg) = read_message(&mut input)? {
+        for reply in server.handle(&msg) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads one `Content-Length` framed message. Returns `None` at the end of
+/// the stream.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[derive(Debug, Default)]
+pub struct Server {
+    /// Open documents by URI.
+    documents: BTreeMap<String, String>,
+    /// Set once the client has sent `exit`.
+    pub exited: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles one request or notification and returns the messages to send
+    /// back: the response to a request, and any diagnostics to publish.
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        let Some(id) = msg.get("id") else {
+            return self.notification(method, params);
+        };
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "polsia-lsp" },
+            }),
+            "shutdown" => Json::Null,
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            _ => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("unknown method {}", method) },
+                })];
+            }
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish(uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        // A change can fix or break files that import this one, so every open
+        // document is checked again.
+        self.documents
+            .keys()
+            .map(|uri| publish(uri, self.analyze(uri).diagnostics))
+            .collect()
+    }
+
+    fn analyze(&self, uri: &str) -> Analysis {
+        Analysis::new(uri, &self.documents[uri], &DocumentLoader(&self.documents))
+    }
+
+    /// The open document and byte offset a position request points at.
+    fn locate(&self, params: &Json) -> Option<(Analysis, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((self.analyze(uri), offset(text, line, character)))
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((analysis, at)) = self.locate(params) else {
+            return Json::Null;
+        };
+        let Some((path, range)) = analysis.path_at(at) else {
+            return Json::Null;
+        };
+        let Some(value) = analysis.unified.as_ref().and_then(|v| v.select(&path)) else {
+            return Json::Null;
+        };
+        let key = path.rsplit('.').next().unwrap_or_default().to_string();
+        let member = SpannedValue {
+            span: value.span,
+            kind: ValueKind::Object(vec![(key, value.clone(), value.span, Vec::new())]),
+        };
+        json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!("```polsia\n{}```", printer::to_string(&member)),
+            },
+            "range": analysis.range(analysis.main, range),
+        })
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((analysis, at)) = self.locate(params) else {
+            return Json::Null;
+        };
+        let Some(path) = analysis
+            .references
+            .iter()
+            .find(|r| contains(&r.span, at))
+            .and_then(|r| analysis.resolve(r))
+        else {
+            return Json::Null;
+        };
+        Json::Array(
+            analysis
+                .definitions
+                .iter()
+                .filter(|d| d.path == path)
+                .map(|d| analysis.location(d.source, d.span.clone()))
+                .collect(),
+        )
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Serves open documents from the editor and everything else from disk.
+struct DocumentLoader<'a>(&'a BTreeMap<String, String>);
+
+impl Loader for DocumentLoader<'_> {
+    fn load(&self, name: &str) -> Result<String, String> {
+        match self.0.get(&path_to_uri(name)) {
+            Some(text) => Ok(text.clone()),
+            None => {
+                std::fs::read_to_string(name).map_err(|e| format!("cannot read {}: {}", name, e))
+            }
+        }
+    }
+}
+
+/// A key defined somewhere, by its path from the top of its file's value.
+struct Definition {
+    path: String,
+    source: SourceId,
+    span: Range<usize>,
+}
+
+/// A reference in the main document and the path of the member it is in.
+struct Reference {
+    name: String,
+    scope: Vec<String>,
+    span: Range<usize>,
+}
+
+/// Everything known about one document after parsing and unifying it.
+struct Analysis {
+    sources: SourceMap,
+    main: SourceId,
+    diagnostics: Vec<Json>,
+    unified: Option<SpannedValue>,
+    definitions: Vec<Definition>,
+    references: Vec<Reference>,
+    /// Import aliases and the files they name.
+    imports: BTreeMap<String, String>,
+}
+
+impl Analysis {
+    fn new(uri: &str, text: &str, loader: &dyn Loader) -> Self {
+        let mut sources = SourceMap::new();
+        let name = uri_to_path(uri);
+        let main = sources.add(name.clone(), text);
+        let mut analysis = Analysis {
+            sources: SourceMap::new(),
+            main,
+            diagnostics: Vec::new(),
+            unified: None,
+            definitions: Vec::new(),
+            references: Vec::new(),
+            imports: BTreeMap::new(),
+        };
+        if let Ok(file) = cst::parse(text) {
+            for import in &file.imports {
+                let path = unquote(&import.path.text);
+                analysis
+                    .imports
+                    .insert(import.alias.text.clone(), loader.resolve(&name, &path));
+            }
+            analysis.collect(&file, main, "", true);
+        }
+        let parsed = document().parse(input(text, main)).into_result();
+        let diagnostics: Vec<Diagnostic> = match parsed {
+            Err(errs) => errs.iter().map(Diagnostic::parse).collect(),
+            Ok(doc) => match resolve_imports(doc, &name, loader, &mut sources) {
+                Err(err) => Diagnostic::load(&err),
+                Ok(doc) => {
+                    let (unified, conflicts) = unify_tree_collecting(&doc.value);
+                    analysis.unified = Some(unified);
+                    conflicts
+                        .iter()
+                        .filter(|c| !c.consequential)
+                        .map(Diagnostic::conflict)
+                        .collect()
+                }
+            },
+        };
+        analysis.sources = sources;
+        analysis.diagnostics = diagnostics.iter().map(|d| analysis.diagnostic(d)).collect();
+        let imported: Vec<(String, String)> = analysis
+            .imports
+            .iter()
+            .map(|(alias, name)| (alias.clone(), name.clone()))
+            .collect();
+        for (alias, name) in imported {
+            let found = analysis.sources.iter().find(|(_, n, _)| *n == name);
+            let Some((id, _, text)) = found else {
+                continue;
+            };
+            if let Ok(file) = cst::parse(text) {
+                analysis.collect(&file, id, &alias, false);
+            }
+        }
+        analysis
+    }
+
+    /// `d` as an LSP diagnostic for the main document. Problems located in
+    /// another file are shown at the top of this one and point to the real
+    /// location.
+    fn diagnostic(&self, d: &Diagnostic) -> Json {
+        let mut related = Vec::new();
+        let range = if d.span.context == self.main {
+            self.range(self.main, d.span.into_range())
+        } else {
+            related.push(json!({
+                "location": self.location(d.span.context, d.span.into_range()),
+                "message": d.message,
+            }));
+            self.range(self.main, 0..0)
+        };
+        for label in &d.labels {
+            related.push(json!({
+                "location": self.location(label.span.context, label.span.into_range()),
+                "message": label.message,
+            }));
+        }
+        let severity = match d.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        };
+        let mut diagnostic = json!({
+            "range": range,
+            "severity": severity,
+            "code": d.code.as_str(),
+            "source": "polsia",
+            "message": d.message,
+        });
+        if !related.is_empty() {
+            diagnostic["relatedInformation"] = Json::Array(related);
+        }
+        diagnostic
+    }
+
+    fn range(&self, source: SourceId, span: Range<usize>) -> Json {
+        let text = self.sources.text(source);
+        let (start_line, start_char) = position(text, span.start);
+        let (end_line, end_char) = position(text, span.end);
+        json!({
+            "start": { "line": start_line, "character": start_char },
+            "end": { "line": end_line, "character": end_char },
+        })
+    }
+
+    fn location(&self, source: SourceId, span: Range<usize>) -> Json {
+        json!({
+            "uri": path_to_uri(self.sources.name(source)),
+            "range": self.range(source, span),
+        })
+    }
+
+    /// Records the keys defined in `file`, under `prefix`, and if `main` the
+    /// references it makes.
+    fn collect(&mut self, file: &cst::SourceFile, source: SourceId, prefix: &str, main: bool) {
+        let mut walker = Walker {
+            source,
+            main,
+            analysis: self,
+        };
+        let path: Vec<String> = prefix
+            .split('.')
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        match &file.body {
+            Body::Items(items) => walker.items(items, &path),
+            Body::Value(value) => walker.expr(value, &path),
+        }
+    }
+
+    /// The path of the key or reference at `at`, with the token's span.
+    fn path_at(&self, at: usize) -> Option<(String, Range<usize>)> {
+        if let Some(d) = self
+            .definitions
+            .iter()
+            .find(|d| d.source == self.main && contains(&d.span, at))
+        {
+            return Some((d.path.clone(), d.span.clone()));
+        }
+        let r = self.references.iter().find(|r| contains(&r.span, at))?;
+        Some((self.resolve(r)?, r.span.clone()))
+    }
+
+    /// Resolves a reference the way unification does: relative to the member
+    /// it appears in first, then to each enclosing object in turn.
+    fn resolve(&self, r: &Reference) -> Option<String> {
+        (0..=r.scope.len()).rev().find_map(|i| {
+            let mut path = r.scope[..i].to_vec();
+            path.push(r.name.clone());
+            let path = path.join(".");
+            self.definitions
+                .iter()
+                .any(|d| d.path == path)
+                .then_some(path)
+        })
+    }
+}
+
+struct Walker<'a> {
+    source: SourceId,
+    main: bool,
+    analysis: &'a mut Analysis,
+}
+
+impl Walker<'_> {
+    fn items(&mut self, items: &[Item], path: &[String]) {
+        for item in items {
+            self.expr(&item.value, path);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, path: &[String]) {
+        match expr {
+            Expr::Token(t) => {
+                if t.kind == TokenKind::Word && !cst::KEYWORDS.contains(&t.text.as_str()) {
+                    self.reference(&t.text, path, t.span.clone());
+                }
+            }
+            Expr::Object(c) | Expr::Group(c) => self.items(&c.items, path),
+            Expr::Array(c) | Expr::List(c, _) => {
+                for (i, item) in c.items.iter().enumerate() {
+                    let mut path = path.to_vec();
+                    match path.last_mut() {
+                        Some(last) => last.push_str(&format!("[{}]", i)),
+                        None => path.push(format!("[{}]", i)),
+                    }
+                    self.expr(&item.value, &path);
+                }
+            }
+            Expr::Member(m) => {
+                let mut path = path.to_vec();
+                path.push(unquote(&m.key.text));
+                self.analysis.definitions.push(Definition {
+                    path: path.join("."),
+                    source: self.source,
+                    span: m.key.span.clone(),
+                });
+                self.expr(&m.value, &path);
+            }
+            Expr::Call(name, arg) => {
+                self.reference(&name.text, path, name.span.clone());
+                self.expr(arg, path);
+            }
+            Expr::Unary(_, operand) => self.expr(operand, path),
+            Expr::Binary(left, _, right) => {
+                self.expr(left, path);
+                self.expr(right, path);
+            }
+            Expr::Union(items, _) => {
+                for item in items {
+                    self.expr(item, path);
+                }
+            }
+            Expr::Sized(..) => {}
+        }
+    }
+
+    fn reference(&mut self, name: &str, scope: &[String], span: Range<usize>) {
+        if self.main {
+            self.analysis.references.push(Reference {
+                name: name.to_string(),
+                scope: scope.to_vec(),
+                span,
+            });
+        }
+    }
+}
+
+/// Whether a cursor at `at` is on the token covering `span`, including just
+/// after its last character.
+fn contains(span: &Range<usize>, at: usize) -> bool {
+    span.start <= at && at <= span.end
+}
+
+fn unquote(key: &str) -> String {
+    if key.starts_with(['"', '#']) {
+        crate::parser::unquote(key).unwrap_or_else(|| key.trim_matches(['"', '#']).to_string())
+    } else {
+        key.to_string()
+    }
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return uri.to_string();
+    };
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(decoded) if b == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn path_to_uri(path: &str) -> String {
+    if !path.starts_with('/') {
+        return path.to_string();
+    }
+    let mut uri = String::from("file://");
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+/// The LSP position of a byte offset: a line and a UTF-16 column.
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    (line, before[line_start..].encode_utf16().count())
+}
+
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = if line == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        }
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
@@ -1,112 +1,574 @@
-use ariadne::{Color, Label, Report, ReportKind, sources};
+//! The `polsia` command line tool.
+//!
+//! ```text
+//! polsia export [--out FORMAT] [--rationals POLICY] FILE...
+//!                                               print the unified files
+//! polsia vet FILE...                            only check that the files unify and export
+//! polsia eval -e PATH [--out FORMAT] [--rationals POLICY] FILE...
+//!                                               print the value at PATH (e.g. `a.b[2]`)
+//! polsia fmt [--check] [--indent N|tab] [--commas STYLE] FILE...
+//!                                               reformat files in place
+//! ```
+//!
+//! `polsia FILE...` without a subcommand behaves like `polsia export`.
+//! `polsia --help` prints the usage to stdout.
+//!
+//! `FORMAT` is `json` (the default), `yaml`, `toml` or `polsia`. YAML output of a
+//! top-level array is written as one document per element. TOML output
+//! requires a top-level object and fails on values TOML cannot hold, such as
+//! `null`. `polsia` writes the unified value back as Polsia source and, unlike
+//! the other formats, accepts values that are not fully resolved.
+//!
+//! JSON, YAML and TOML cannot hold rationals such as `1/3` exactly. `POLICY`
+//! is `error` (the default) to refuse them, `float` to write the nearest
+//! float or `string` to write them as strings like `"1/3"`.
+//!
+//! Every command accepts `--diagnostics=human|json`. With `json`, problems in
+//! the input are written to stderr as one JSON array of objects with
+//! `severity`, `code`, `message`, `path`, `span` and `labels` fields.
+//!
+//! `fmt` indents by two spaces unless given `--indent`. `STYLE` is `preserve`
+//! (the default, keep commas as written), `omit`, `separate` or `trailing`.
+//!
+//! Exit codes:
+//!
+//! - `0`: success
+//! - `1`: the input failed to parse, unify or export
+//! - `2`: invalid command line arguments
+//! - `3`: a file could not be read or written
+//! - `4`: `eval` found no value at the requested path
+//! - `5`: `fmt --check` found files that are not formatted
+
+use ariadne::Config;
 use chumsky::prelude::*;
-use polsia::types::Span;
-use polsia::{SpannedValue, ValueKind, document, unify_tree};
-use std::{env, fs};
-
-fn find_unresolved(value: &SpannedValue) -> Option<(Span, String)> {
-    match &value.kind {
-        ValueKind::Reference(p) => Some((value.span, format!("reference {}", p))),
-        ValueKind::Type(t) => Some((value.span, format!("{:?}", t))),
-        ValueKind::Union(items) => {
-            for item in items {
-                if let Some(res) = find_unresolved(item) {
-                    return Some(res);
+use polsia::export::{RationalPolicy, find_unresolved};
+use polsia::format::{CommaStyle, FormatOptions};
+use polsia::{
+    Diagnostic, FsLoader, SourceMap, SpannedValue, diagnostic, document, format, input,
+    merge_documents, printer, report, resolve_imports, unify_tree_collecting, yaml,
+};
+use std::{env, fs, process};
+
+const EXIT_INVALID: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_NO_VALUE: i32 = 4;
+const EXIT_UNFORMATTED: i32 = 5;
+
+const USAGE: &str = "usage:
+  polsia export [--out json|yaml|toml|polsia] [--rationals error|float|string] FILE...
+  polsia vet FILE...
+  polsia eval -e PATH [--out json|yaml|toml|polsia] [--rationals error|float|string] FILE...
+  polsia fmt [--check] [--indent N|tab] [--commas preserve|omit|separate|trailing] FILE...
+
+options:
+  --diagnostics human|json    how to report problems in the input (default human)";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Export,
+    Vet,
+    Eval(String),
+    Fmt { check: bool, options: FormatOptions },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Output {
+    Json,
+    Yaml,
+    Toml,
+    Polsia,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiagnosticFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug)]
+struct Args {
+    command: Command,
+    out: Output,
+    rationals: RationalPolicy,
+    diagnostics: DiagnosticFormat,
+    files: Vec<String>,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args, String> {
+    let help = Args {
+        command: Command::Help,
+        out: Output::Json,
+        rationals: RationalPolicy::default(),
+        diagnostics: DiagnosticFormat::Human,
+        files: Vec::new(),
+    };
+    let (mut command, skip) = match args.first().map(String::as_str) {
+        Some("export") => (Command::Export, 1),
+        Some("vet") => (Command::Vet, 1),
+        Some("eval") => (Command::Eval(String::new()), 1),
+        Some("fmt") => (
+            Command::Fmt {
+                check: false,
+                options: FormatOptions::default(),
+            },
+            1,
+        ),
+        Some("help" | "-h" | "--help") => return Ok(help),
+        _ => (Command::Export, 0),
+    };
+    let mut out = Output::Json;
+    let mut rationals = RationalPolicy::default();
+    let mut diagnostics = DiagnosticFormat::Human;
+    let mut files = Vec::new();
+    let mut expression = false;
+    let mut rest = args.into_iter().skip(skip);
+    while let Some(arg) = rest.next() {
+        if let Some(format) = arg.strip_prefix("--diagnostics=") {
+            diagnostics = diagnostic_format(Some(format))?;
+            continue;
+        }
+        match (&mut command, arg.as_str()) {
+            (_, "-h" | "--help") => return Ok(help),
+            (_, "--diagnostics") => diagnostics = diagnostic_format(rest.next().as_deref())?,
+            (Command::Eval(path), "-e" | "--expression") => match rest.next() {
+                Some(p) => {
+                    *path = p;
+                    expression = true;
+                }
+                None => return Err(format!("{} requires a path", arg)),
+            },
+            (Command::Export | Command::Eval(_), "--out") => {
+                out = match rest.next().as_deref() {
+                    Some("json") => Output::Json,
+                    Some("yaml") => Output::Yaml,
+                    Some("toml") => Output::Toml,
+                    Some("polsia") => Output::Polsia,
+                    Some(other) => return Err(format!("unknown output format {}", other)),
+                    None => return Err("--out requires a format".into()),
                 }
             }
-            Some((value.span, "union".into()))
-        }
-        ValueKind::Array(items) => {
-            for item in items {
-                if let Some(res) = find_unresolved(item) {
-                    return Some(res);
+            (Command::Export | Command::Eval(_), "--rationals") => {
+                rationals = match rest.next().as_deref() {
+                    Some("error") => RationalPolicy::Error,
+                    Some("float") => RationalPolicy::Float,
+                    Some("string") => RationalPolicy::String,
+                    Some(other) => return Err(format!("unknown rational policy {}", other)),
+                    None => return Err("--rationals requires a policy".into()),
                 }
             }
-            None
-        }
-        ValueKind::Object(members) => {
-            for (_, v, _, _) in members {
-                if let Some(res) = find_unresolved(v) {
-                    return Some(res);
+            (Command::Fmt { check, .. }, "--check") => *check = true,
+            (Command::Fmt { options, .. }, "--indent") => {
+                options.indent = match rest.next().as_deref() {
+                    Some("tab") => "\t".into(),
+                    Some(n) => match n.parse() {
+                        Ok(n) => " ".repeat(n),
+                        Err(_) => return Err(format!("invalid indent {}", n)),
+                    },
+                    None => return Err("--indent requires a width".into()),
                 }
             }
-            None
+            (Command::Fmt { options, .. }, "--commas") => {
+                options.commas = match rest.next().as_deref() {
+                    Some("preserve") => CommaStyle::Preserve,
+                    Some("omit") => CommaStyle::Omit,
+                    Some("separate") => CommaStyle::Separate,
+                    Some("trailing") => CommaStyle::Trailing,
+                    Some(other) => return Err(format!("unknown comma style {}", other)),
+                    None => return Err("--commas requires a style".into()),
+                }
+            }
+            (_, flag) if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option {}", flag));
+            }
+            _ => files.push(arg),
         }
-        _ => None,
+    }
+    if matches!(command, Command::Eval(_)) && !expression {
+        return Err("eval requires -e PATH".into());
+    }
+    if files.is_empty() {
+        return Err("expected file argument".into());
+    }
+    Ok(Args {
+        command,
+        out,
+        rationals,
+        diagnostics,
+        files,
+    })
+}
+
+fn diagnostic_format(arg: Option<&str>) -> Result<DiagnosticFormat, String> {
+    match arg {
+        Some("human") => Ok(DiagnosticFormat::Human),
+        Some("json") => Ok(DiagnosticFormat::Json),
+        Some(other) => Err(format!("unknown diagnostics format {}", other)),
+        None => Err("--diagnostics requires a format".into()),
     }
 }
 
 fn main() {
-    let filename = env::args().nth(1).expect("expected file argument");
-    let src = fs::read_to_string(&filename).expect("failed to read file");
-    let parse_result = document().parse(&src).into_result();
-    match parse_result {
-        Ok(doc) => match unify_tree(&doc.value) {
-            Ok(value) => {
-                if let Some((span, t)) = find_unresolved(&value) {
-                    let msg = format!("value of type {} is unspecified", t);
-                    let span_range = span.into_range();
-                    Report::build(ReportKind::Error, (filename.clone(), span_range.clone()))
-                        .with_message(&msg)
-                        .with_label(
-                            Label::new((filename.clone(), span_range))
-                                .with_message(&msg)
-                                .with_color(Color::Red),
-                        )
-                        .finish()
-                        .print(sources([(filename.clone(), src.as_str())]))
-                        .unwrap();
-                } else {
-                    println!("{}", value.to_value().to_pretty_string());
+    process::exit(run(env::args().skip(1).collect()));
+}
+
+fn run(args: Vec<String>) -> i32 {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let format = args.diagnostics;
+    match args.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            0
+        }
+        Command::Export if matches!(args.out, Output::Polsia) => {
+            match unify_files(&args.files, format) {
+                Ok((sources, value)) => {
+                    print_value(&value, "", args.out, args.rationals, &sources, format)
                 }
+                Err(code) => code,
             }
-            Err(err) => {
-                use chumsky::error::LabelError;
-                let mut e = Rich::custom(err.span, err.msg.clone());
-                <Rich<_> as LabelError<&str, _>>::in_context(
-                    &mut e,
-                    "previous value here",
-                    err.prev_span,
-                );
-                let span = (*e.span()).into_range();
-                let msg = e.to_string();
-                Report::build(ReportKind::Error, (filename.clone(), span.clone()))
-                    .with_message(&msg)
-                    .with_label(
-                        Label::new((filename.clone(), span.clone()))
-                            .with_message(&msg)
-                            .with_color(Color::Red),
-                    )
-                    .with_labels(e.contexts().map(|(label, span)| {
-                        Label::new((filename.clone(), span.into_range()))
-                            .with_message(label.to_string())
-                            .with_color(Color::Yellow)
-                    }))
-                    .finish()
-                    .print(sources([(filename.clone(), src.as_str())]))
-                    .unwrap();
+        }
+        Command::Export => match evaluate(&args.files, format) {
+            Ok((sources, value)) => {
+                print_value(&value, "", args.out, args.rationals, &sources, format)
             }
+            Err(code) => code,
+        },
+        Command::Vet => match evaluate(&args.files, format) {
+            Ok(_) => 0,
+            Err(code) => code,
         },
-        Err(errs) => {
-            for e in errs {
-                let span = (*e.span()).into_range();
-                let msg = e.to_string();
-                Report::build(ReportKind::Error, (filename.clone(), span.clone()))
-                    .with_message(&msg)
-                    .with_label(
-                        Label::new((filename.clone(), span.clone()))
-                            .with_message(&msg)
-                            .with_color(Color::Red),
-                    )
-                    .with_labels(e.contexts().map(|(label, span)| {
-                        Label::new((filename.clone(), span.into_range()))
-                            .with_message(label.to_string())
-                            .with_color(Color::Yellow)
-                    }))
-                    .finish()
-                    .print(sources([(filename.clone(), src.as_str())]))
+        Command::Eval(path) => eval(&args.files, &path, args.out, args.rationals, format),
+        Command::Fmt { check, options } => fmt(&args.files, check, &options, format),
+    }
+}
+
+/// Prints problems found in the input to stderr.
+fn emit(diagnostics: &[Diagnostic], sources: &SourceMap, format: DiagnosticFormat) {
+    match format {
+        DiagnosticFormat::Human => {
+            for d in diagnostics {
+                report::diagnostic_report(d, sources, Config::default())
+                    .eprint(sources.cache())
                     .unwrap();
             }
         }
+        DiagnosticFormat::Json => {
+            eprintln!("{}", diagnostic::to_json_string(diagnostics, sources))
+        }
+    }
+}
+
+/// Loads, unifies and checks `files` for export, printing any diagnostics.
+/// Returns the exit code to use on failure.
+fn evaluate(files: &[String], format: DiagnosticFormat) -> Result<(SourceMap, SpannedValue), i32> {
+    let (sources, value) = unify_files(files, format)?;
+    if let Some((span, err)) = find_unresolved(&value) {
+        emit(&[Diagnostic::export(span, &err)], &sources, format);
+        return Err(EXIT_INVALID);
+    }
+    Ok((sources, value))
+}
+
+fn unify_files(
+    files: &[String],
+    format: DiagnosticFormat,
+) -> Result<(SourceMap, SpannedValue), i32> {
+    let mut sources = SourceMap::new();
+    let mut docs = Vec::new();
+    let mut diagnostics = Vec::new();
+    for filename in files {
+        let src = read_file(filename)?;
+        let id = sources.add(filename.clone(), src);
+        let doc = match document().parse(input(sources.text(id), id)).into_result() {
+            Ok(doc) => doc,
+            Err(errs) => {
+                diagnostics.extend(errs.iter().map(Diagnostic::parse));
+                continue;
+            }
+        };
+        match resolve_imports(doc, filename, &FsLoader, &mut sources) {
+            Ok(doc) => docs.push(doc),
+            Err(err) => diagnostics.extend(Diagnostic::load(&err)),
+        }
+    }
+    if !diagnostics.is_empty() {
+        emit(&diagnostics, &sources, format);
+        return Err(EXIT_INVALID);
+    }
+
+    let merged = match merge_documents(&docs) {
+        Ok(merged) => merged,
+        Err(err) => {
+            emit(&[Diagnostic::unify(&err)], &sources, format);
+            return Err(EXIT_INVALID);
+        }
+    };
+    let (value, conflicts) = unify_tree_collecting(&merged);
+    if conflicts.is_empty() {
+        return Ok((sources, value));
+    }
+    let diagnostics: Vec<Diagnostic> = conflicts
+        .iter()
+        .filter(|c| !c.consequential)
+        .map(Diagnostic::conflict)
+        .collect();
+    emit(&diagnostics, &sources, format);
+    Err(EXIT_INVALID)
+}
+
+/// Prints `value`, found at `path`, in `out`, reporting what cannot be
+/// exported as a diagnostic.
+fn print_value(
+    value: &SpannedValue,
+    path: &str,
+    out: Output,
+    rationals: RationalPolicy,
+    sources: &SourceMap,
+    format: DiagnosticFormat,
+) -> i32 {
+    match render(value, path, out, rationals) {
+        Ok(s) => {
+            print!("{}", s);
+            0
+        }
+        Err(d) => {
+            emit(&[d], sources, format);
+            EXIT_INVALID
+        }
+    }
+}
+
+/// Renders `value`, found at `path`, in `out`.
+fn render(
+    value: &SpannedValue,
+    path: &str,
+    out: Output,
+    rationals: RationalPolicy,
+) -> Result<String, Diagnostic> {
+    let rendered = value
+        .to_value()
+        .with_rationals(rationals)
+        .and_then(|exported| match out {
+            Output::Json => exported.to_pretty_string().map(|s| s + "\n"),
+            Output::Yaml => yaml::to_documents(&exported),
+            Output::Polsia => Ok(printer::to_string(value)),
+            Output::Toml => exported.to_toml_string(),
+        });
+    rendered.map_err(|mut err| {
+        let span = value.select(&err.path).map_or(value.span, |v| v.span);
+        err.path = full_path(path, &err.path);
+        Diagnostic::export(span, &err)
+    })
+}
+
+/// The path from the top of the file of `inner`, a path within the value
+/// at `path`.
+fn full_path(path: &str, inner: &str) -> String {
+    if path.is_empty() {
+        inner.to_string()
+    } else if inner.is_empty() || inner.starts_with('[') {
+        format!("{}{}", path, inner)
+    } else {
+        format!("{}.{}", path, inner)
+    }
+}
+
+fn eval(
+    files: &[String],
+    path: &str,
+    out: Output,
+    rationals: RationalPolicy,
+    format: DiagnosticFormat,
+) -> i32 {
+    let (sources, value) = match unify_files(files, format) {
+        Ok(res) => res,
+        Err(code) => return code,
+    };
+    let Some(selected) = value.select(path) else {
+        eprintln!("error: no value at {}", path);
+        return EXIT_NO_VALUE;
+    };
+    if !matches!(out, Output::Polsia)
+        && let Some((span, mut err)) = find_unresolved(selected)
+    {
+        // Report the path from the top of the file, not from `path`.
+        err.path = full_path(path, &err.path);
+        emit(&[Diagnostic::export(span, &err)], &sources, format);
+        return EXIT_INVALID;
+    }
+    print_value(selected, path, out, rationals, &sources, format)
+}
+
+fn fmt(files: &[String], check: bool, options: &FormatOptions, format: DiagnosticFormat) -> i32 {
+    let mut unformatted = false;
+    for filename in files {
+        let src = match read_file(filename) {
+            Ok(src) => src,
+            Err(code) => return code,
+        };
+        let formatted = match format::format_source(&src, options) {
+            Ok(formatted) => formatted,
+            Err(errs) => {
+                let mut sources = SourceMap::new();
+                sources.add(filename.clone(), src.clone());
+                let diagnostics: Vec<Diagnostic> = errs.iter().map(Diagnostic::parse).collect();
+                emit(&diagnostics, &sources, format);
+                return EXIT_INVALID;
+            }
+        };
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("{}", filename);
+            unformatted = true;
+        } else if let Err(e) = fs::write(filename, formatted) {
+            eprintln!("error: cannot write {}: {}", filename, e);
+            return EXIT_IO;
+        }
+    }
+    if unformatted { EXIT_UNFORMATTED } else { 0 }
+}
+
+fn read_file(filename: &str) -> Result<String, i32> {
+    fs::read_to_string(filename).map_err(|e| {
+        eprintln!("error: cannot read {}: {}", filename, e);
+        EXIT_IO
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn export_is_the_default_command() {
+        let args = parse(&["a.pls", "b.pls"]).unwrap();
+        assert_eq!(args.command, Command::Export);
+        assert_eq!(args.out, Output::Json);
+        assert_eq!(args.rationals, RationalPolicy::Error);
+        assert_eq!(args.diagnostics, DiagnosticFormat::Human);
+        assert_eq!(args.files, ["a.pls", "b.pls"]);
+    }
+
+    #[test]
+    fn export_takes_output_and_rationals() {
+        let args = parse(&[
+            "export",
+            "--out",
+            "yaml",
+            "--rationals",
+            "string",
+            "--diagnostics=json",
+            "a.pls",
+        ])
+        .unwrap();
+        assert_eq!(args.command, Command::Export);
+        assert_eq!(args.out, Output::Yaml);
+        assert_eq!(args.rationals, RationalPolicy::String);
+        assert_eq!(args.diagnostics, DiagnosticFormat::Json);
+        assert_eq!(args.files, ["a.pls"]);
+        assert_eq!(
+            parse(&["export", "--out", "xml", "a.pls"]).unwrap_err(),
+            "unknown output format xml"
+        );
+    }
+
+    #[test]
+    fn vet_takes_files_only() {
+        let args = parse(&["vet", "a.pls"]).unwrap();
+        assert_eq!(args.command, Command::Vet);
+        assert_eq!(
+            parse(&["vet", "--out", "yaml", "a.pls"]).unwrap_err(),
+            "unknown option --out"
+        );
+        assert_eq!(parse(&["vet"]).unwrap_err(), "expected file argument");
+    }
+
+    #[test]
+    fn eval_requires_a_path() {
+        let args = parse(&["eval", "-e", "a.b[2]", "--out", "toml", "a.pls"]).unwrap();
+        assert_eq!(args.command, Command::Eval("a.b[2]".into()));
+        assert_eq!(args.out, Output::Toml);
+        assert_eq!(
+            parse(&["eval", "a.pls"]).unwrap_err(),
+            "eval requires -e PATH"
+        );
+        assert_eq!(
+            parse(&["eval", "a.pls", "-e"]).unwrap_err(),
+            "-e requires a path"
+        );
+    }
+
+    #[test]
+    fn fmt_takes_format_options() {
+        let args = parse(&[
+            "fmt", "--check", "--indent", "tab", "--commas", "omit", "a.pls",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.command,
+            Command::Fmt {
+                check: true,
+                options: FormatOptions {
+                    indent: "\t".into(),
+                    commas: CommaStyle::Omit,
+                },
+            }
+        );
+        assert_eq!(
+            parse(&["fmt", "--indent", "x", "a.pls"]).unwrap_err(),
+            "invalid indent x"
+        );
+    }
+
+    #[test]
+    fn export_errors_are_diagnostics() {
+        let mut sources = SourceMap::new();
+        let id = sources.add("a.pls", "a: { b: 1/3 }\n");
+        let doc = document()
+            .parse(input(sources.text(id), id))
+            .into_result()
+            .unwrap();
+        let (value, _) = unify_tree_collecting(&doc.value);
+        let d = render(&value, "", Output::Json, RationalPolicy::Error).unwrap_err();
+        let json: serde_json::Value =
+            serde_json::from_str(&diagnostic::to_json_string(&[d], &sources)).unwrap();
+        assert_eq!(json[0]["code"], "unrepresentable");
+        assert_eq!(json[0]["path"], "a.b");
+        assert_eq!(json[0]["message"], "a.b: 1/3 cannot be represented exactly");
+        assert_eq!(json[0]["span"]["start"]["offset"], 5);
+        assert_eq!(json[0]["span"]["end"]["offset"], 11);
+
+        let a = value.select("a").unwrap();
+        let d = render(a, "a", Output::Toml, RationalPolicy::Error).unwrap_err();
+        assert_eq!(d.path.as_deref(), Some("a.b"));
+    }
+
+    #[test]
+    fn help_is_not_an_error() {
+        for args in [
+            &["help"][..],
+            &["-h"],
+            &["--help"],
+            &["export", "--help"],
+            &["eval", "-h"],
+        ] {
+            assert_eq!(parse(args).unwrap().command, Command::Help, "{:?}", args);
+        }
+        assert_eq!(run(vec!["--help".into()]), 0);
     }
 }
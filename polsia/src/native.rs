@@ -0,0 +1,82 @@
+use crate::types::{ValType, Value};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::collections::BTreeMap;
+
+/// A function implemented in Rust and called from Polsia as
+/// `native ["name", arg]`.
+pub trait NativeFunction {
+    /// The shape of the argument, checked like a function's `arg:` member
+    /// before [`NativeFunction::call`] is called.
+    fn arg(&self) -> Value;
+
+    /// Computes the result for `arg`, which has been unified with
+    /// [`NativeFunction::arg`] but may not be concrete yet. Returns
+    /// `Ok(None)` to wait until it is; the call is then kept and tried again
+    /// later.
+    fn call(&self, arg: &Value) -> Result<Option<Value>, String>;
+
+    /// The size of the result for `arg`, counted like the output limit, if
+    /// it can be worked out without building the result. It is checked
+    /// against the limit before [`NativeFunction::call`], so that asking for
+    /// a huge value fails instead of allocating it.
+    fn result_size(&self, _arg: &Value) -> Option<usize> {
+        None
+    }
+}
+
+/// The native functions available to evaluation, by name.
+#[derive(Default)]
+pub struct Natives {
+    functions: BTreeMap<String, Box<dyn NativeFunction>>,
+}
+
+impl Natives {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The natives every document can use: `increment` and the standard
+    /// library.
+    pub fn builtin() -> Self {
+        let mut natives = Natives::new();
+        natives.insert("increment", Increment);
+        crate::stdlib::register(&mut natives);
+        natives
+    }
+
+    /// Registers `function` under `name`, replacing any native of that name.
+    pub fn insert(&mut self, name: impl Into<String>, function: impl NativeFunction + 'static) {
+        self.functions.insert(name.into(), Box::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn NativeFunction> {
+        self.functions.get(name).map(|f| f.as_ref())
+    }
+}
+
+impl std::fmt::Debug for Natives {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.functions.keys()).finish()
+    }
+}
+
+/// Adds one to an `Int`.
+struct Increment;
+
+impl NativeFunction for Increment {
+    fn arg(&self) -> Value {
+        Value::Type(ValType::Int)
+    }
+
+    fn call(&self, arg: &Value) -> Result<Option<Value>, String> {
+        Ok(match arg {
+            Value::Int(n) => Some(n.checked_add(1).map_or_else(
+                || Value::Rational(BigRational::from_integer(BigInt::from(*n) + 1)),
+                Value::Int,
+            )),
+            _ => None,
+        })
+    }
+}
@@ -1,11 +1,29 @@
-use crate::types::{Annotation, Document, Span, SpannedValue, ValType, ValueKind};
+use crate::types::{
+    Annotation, Bound, Document, Import, List, Pattern, Range, SourceId, Span, SpannedValue, Text,
+    ValType, ValueKind,
+};
+use chumsky::input::{Emitter, WithContext};
 use chumsky::prelude::*;
-use chumsky::span::{SimpleSpan, Span as ChumSpan};
+use chumsky::span::Span as ChumSpan;
+use num_bigint::BigInt;
+use num_rational::BigRational;
 
-pub fn document<'a>() -> impl Parser<'a, &'a str, Document, extra::Err<Rich<'a, char>>> {
+/// Parser input: source text tagged with the id of the file it came from, so
+/// every span produced by the parser knows which file it points into.
+pub type ParserInput<'a> = WithContext<Span, &'a str>;
+
+pub type ParseError<'a> = Rich<'a, char, Span>;
+
+/// Tags `src` with `source` for use with [`document`] or [`parser`].
+pub fn input(src: &str, source: SourceId) -> ParserInput<'_> {
+    src.with_context(source)
+}
+
+pub fn document<'a>() -> impl Parser<'a, ParserInput<'a>, Document, extra::Err<ParseError<'a>>> {
     let value = spanned_value();
 
     let comment = just('#')
+        .and_is(raw_start().not())
         .then(none_of('\n').repeated())
         .then_ignore(text::newline().or_not())
         .ignored();
@@ -17,42 +35,17 @@ pub fn document<'a>() -> impl Parser<'a, &'a str, Document, extra::Err<Rich<'a,
         .at_least(1)
         .ignored();
 
-    let escape =
-        just('\\').ignore_then(choice((
-            just('\\'),
-            just('/'),
-            just('"'),
-            just('b').to('\x08'),
-            just('f').to('\x0c'),
-            just('n').to('\n'),
-            just('r').to('\r'),
-            just('t').to('\t'),
-            just('u').ignore_then(text::digits(16).exactly(4).to_slice().map(|digits: &str| {
-                char::from_u32(u32::from_str_radix(digits, 16).unwrap()).unwrap()
-            })),
-        )));
-
-    let string = none_of("\\\"")
-        .or(escape)
-        .repeated()
-        .collect::<String>()
-        .delimited_by(just('"'), just('"'))
-        .map(|s| s);
-
-    let key_string = string;
-    let key = key_string.or(text::ident().map(|s: &str| s.to_string()));
+    let string = string_literal();
+    let key = string
+        .clone()
+        .or(text::ident().map(|s: &str| s.to_string()));
     let key_span = key.map_with(|k: String, e| (k, e.span()));
 
     let member = key_span
+        .then(just('?').or_not().map(|q| q.is_some()))
         .then_ignore(just(':').padded_by(ws))
         .then(spanned_value_no_pad())
-        .map(
-            |((k, k_span), (mut v, anns)): ((String, Span), (SpannedValue, Vec<Annotation>))| {
-                let span = SimpleSpan::new((), k_span.start()..v.span.end());
-                v.span = span;
-                (k, v, span, anns)
-            },
-        );
+        .map(|(((k, k_span), optional), (v, anns))| spanned_member(k, k_span, optional, v, anns));
 
     #[derive(Debug)]
     enum Item {
@@ -86,6 +79,7 @@ pub fn document<'a>() -> impl Parser<'a, &'a str, Document, extra::Err<Rich<'a,
                 }
             }
             Document {
+                imports: Vec::new(),
                 value: SpannedValue {
                     span: e.span(),
                     kind: ValueKind::Object(members),
@@ -93,20 +87,43 @@ pub fn document<'a>() -> impl Parser<'a, &'a str, Document, extra::Err<Rich<'a,
             }
         });
 
-    choice((top_object, value.map(|v| Document { value: v })))
+    let import = text::keyword("import")
+        .ignore_then(ws1)
+        .ignore_then(string)
+        .then_ignore(ws1)
+        .then_ignore(text::keyword("as"))
+        .then_ignore(ws1)
+        .then(text::ident().map(|s: &str| s.to_string()))
+        .map_with(|(path, alias), e| Import {
+            path,
+            alias,
+            span: e.span(),
+        });
+    let imports = import.then_ignore(ws).repeated().collect::<Vec<_>>();
+
+    imports
+        .then(choice((
+            top_object,
+            value.map(|v| Document {
+                imports: Vec::new(),
+                value: v,
+            }),
+        )))
         .padded_by(ws)
-        .map(|d| d)
+        .map(|(imports, d)| Document { imports, ..d })
 }
 
-pub fn parser<'a>() -> impl Parser<'a, &'a str, SpannedValue, extra::Err<Rich<'a, char>>> {
+pub fn parser<'a>() -> impl Parser<'a, ParserInput<'a>, SpannedValue, extra::Err<ParseError<'a>>> {
     document().map(|d| d.value)
 }
 
-fn spanned_value<'a>() -> impl Parser<'a, &'a str, SpannedValue, extra::Err<Rich<'a, char>>> {
+fn spanned_value<'a>() -> impl Parser<'a, ParserInput<'a>, SpannedValue, extra::Err<ParseError<'a>>>
+{
     spanned_value_no_pad().map(|(v, _)| v).padded_by(
         choice((
             text::whitespace().at_least(1).ignored(),
             just('#')
+                .and_is(raw_start().not())
                 .then(none_of('\n').repeated())
                 .then_ignore(text::newline().or_not())
                 .ignored(),
@@ -116,10 +133,28 @@ fn spanned_value<'a>() -> impl Parser<'a, &'a str, SpannedValue, extra::Err<Rich
     )
 }
 
+/// A member `key: value` whose span starts at the key, marked optional for
+/// `key?: value`.
+fn spanned_member(
+    k: String,
+    k_span: Span,
+    optional: bool,
+    mut v: SpannedValue,
+    mut anns: Vec<Annotation>,
+) -> (String, SpannedValue, Span, Vec<Annotation>) {
+    let span = Span::new(k_span.context(), k_span.start()..v.span.end());
+    v.span = span;
+    if optional {
+        anns.push(Annotation::Optional);
+    }
+    (k, v, span, anns)
+}
+
 fn spanned_value_no_pad<'a>()
--> impl Parser<'a, &'a str, (SpannedValue, Vec<Annotation>), extra::Err<Rich<'a, char>>> {
+-> impl Parser<'a, ParserInput<'a>, (SpannedValue, Vec<Annotation>), extra::Err<ParseError<'a>>> {
     recursive(|value| {
         let comment = just('#')
+            .and_is(raw_start().not())
             .then(none_of('\n').repeated())
             .then_ignore(text::newline().or_not())
             .ignored();
@@ -133,105 +168,216 @@ fn spanned_value_no_pad<'a>()
         let digits = text::digits(10);
         let int = text::int(10);
 
+        let fraction = just('.').then(digits).or_not().then(
+            one_of("eE")
+                .then(one_of("+-").or_not())
+                .then(digits)
+                .or_not(),
+        );
         let number = just('-')
             .or_not()
             .then(int)
-            .then(just('.').then(digits).or_not())
-            .then(
-                one_of("eE")
-                    .then(one_of("+-").or_not())
-                    .then(digits)
-                    .or_not(),
-            )
+            .then(fraction)
             .to_slice()
-            .map_with(|s: &str, e| {
-                let kind = if s.contains('.') || s.contains('e') || s.contains('E') {
-                    ValueKind::Float(s.parse().unwrap())
+            .validate(|s: &str, e, emitter| {
+                let kind = if s.contains(['.', 'e', 'E']) {
+                    ValueKind::Decimal(decimal(s).unwrap_or_else(|| {
+                        emitter.emit(exponent_error(s, e.span()));
+                        BigRational::from_integer(0.into())
+                    }))
                 } else {
-                    ValueKind::Int(s.parse().unwrap())
+                    ValueKind::exact(BigRational::from_integer(s.parse().unwrap()))
                 };
                 (
                     SpannedValue {
                         span: e.span(),
                         kind,
                     },
-                    Vec::new(),
+                    Vec::<Annotation>::new(),
                 )
             });
 
-        let escape = just('\\').ignore_then(choice((
-            just('\\'),
-            just('/'),
-            just('"'),
-            just('b').to('\x08'),
-            just('f').to('\x0c'),
-            just('n').to('\n'),
-            just('r').to('\r'),
-            just('t').to('\t'),
-            just('u').ignore_then(text::digits(16).exactly(4).to_slice().map(|digits: &str| {
-                char::from_u32(u32::from_str_radix(digits, 16).unwrap()).unwrap()
-            })),
-        )));
+        // `>=1`, `<65536` or `>1/2`: the numbers past a bound.
+        let bound = choice((just(">="), just("<="), just(">"), just("<")))
+            .then(just('-').or_not().then(int).then(fraction).to_slice())
+            .then(just('/').ignore_then(int).or_not())
+            .validate(
+                |((op, n), denom): ((&str, &str), Option<&str>), e, emitter| {
+                    let n = match n.contains(['.', 'e', 'E']) {
+                        true => decimal(n).unwrap_or_else(|| {
+                            emitter.emit(exponent_error(n, e.span()));
+                            BigRational::from_integer(0.into())
+                        }),
+                        false => BigRational::from_integer(n.parse().unwrap()),
+                    };
+                    let denom = denom.map(|d| d.parse::<BigInt>().unwrap());
+                    let value = match denom {
+                        None => n,
+                        Some(d) if d != BigInt::from(0) => n / d,
+                        _ => {
+                            emitter.emit(Rich::custom(e.span(), "a bound must be a finite number"));
+                            BigRational::from_integer(0.into())
+                        }
+                    };
+                    let bound = Some(Bound {
+                        value,
+                        inclusive: op.ends_with('='),
+                    });
+                    let (min, max) = match op.starts_with('>') {
+                        true => (bound, None),
+                        false => (None, bound),
+                    };
+                    (
+                        SpannedValue {
+                            span: e.span(),
+                            kind: ValueKind::Type(ValType::Range(Box::new(Range {
+                                number: Box::new(ValType::Number),
+                                min,
+                                max,
+                            }))),
+                        },
+                        Vec::new(),
+                    )
+                },
+            );
 
-        let string = none_of("\\\"")
-            .or(escape)
-            .repeated()
-            .collect::<String>()
-            .delimited_by(just('"'), just('"'))
-            .map_with(|s: String, e| {
-                (
+        let interpolation = just("\\(")
+            .ignore_then(value.clone().padded_by(ws))
+            .then_ignore(just(')'))
+            .map(|(v, _)| Piece::Value(v));
+        let string = string_pieces(interpolation).validate(|pieces, e, emitter| {
+            let mut parts = decode(pieces, e.span(), emitter);
+            let kind = match parts.as_slice() {
+                [
                     SpannedValue {
-                        span: e.span(),
-                        kind: ValueKind::String(s),
+                        kind: ValueKind::String(_),
+                        ..
                     },
-                    Vec::new(),
-                )
-            });
+                ] => parts.pop().unwrap().kind,
+                _ => ValueKind::Interpolation(parts),
+            };
+            (
+                SpannedValue {
+                    span: e.span(),
+                    kind,
+                },
+                Vec::new(),
+            )
+        });
 
+        // `[A, ...T]`, optionally followed by `{min,max}`, `{min,}`, `{,max}`
+        // or `{n}`.
+        let count = int.try_map(|s: &str, span| {
+            s.parse::<usize>()
+                .map_err(|_| Rich::custom(span, "length is too large"))
+        });
+        let bounds = count
+            .or_not()
+            .padded_by(ws)
+            .then(just(',').ignore_then(count.padded_by(ws).or_not()).or_not())
+            .delimited_by(just('{'), just('}'))
+            .try_map(|bounds, span| match bounds {
+                (Some(n), None) => Ok((n, Some(n))),
+                (Some(min), Some(max)) => Ok((min, max)),
+                (None, Some(Some(max))) => Ok((0, Some(max))),
+                (None, _) => Err(Rich::custom(
+                    span,
+                    "length bounds need a minimum or maximum",
+                )),
+            });
+        let rest = just("...")
+            .ignore_then(value.clone())
+            .then_ignore(just(',').padded_by(ws).or_not());
         let array = value
             .clone()
             .separated_by(just(',').padded_by(ws))
             .allow_trailing()
             .collect::<Vec<_>>()
+            .then(rest.or_not())
             .delimited_by(just('[').padded_by(ws), ws.then_ignore(just(']')))
-            .map_with(|vals, e| {
+            .then(bounds.or_not())
+            .validate(|((vals, rest), bounds), e, emitter| {
+                let items = vals.into_iter().map(|(v, _)| v).collect();
+                let kind = match (rest, bounds) {
+                    (None, None) => ValueKind::Array(items),
+                    (None, Some(_)) => {
+                        emitter.emit(Rich::custom(
+                            e.span(),
+                            "length bounds need a rest type such as `...Int`",
+                        ));
+                        ValueKind::Array(items)
+                    }
+                    (Some((rest, _)), bounds) => {
+                        let (min, max) = bounds.unwrap_or((0, None));
+                        if let Some(max) = max.filter(|max| *max < min) {
+                            emitter.emit(Rich::custom(
+                                e.span(),
+                                format!("minimum length {} is more than maximum {}", min, max),
+                            ));
+                        }
+                        ValueKind::List(List {
+                            items,
+                            rest: Box::new(rest),
+                            min,
+                            max,
+                        })
+                    }
+                };
                 (
                     SpannedValue {
                         span: e.span(),
-                        kind: ValueKind::Array(vals.into_iter().map(|(v, _)| v).collect()),
+                        kind,
                     },
                     Vec::new(),
                 )
             });
 
-        let key_string = string.map(|(j, _)| {
-            if let SpannedValue {
-                kind: ValueKind::String(s),
-                ..
-            } = j
-            {
-                s
-            } else {
-                unreachable!()
-            }
-        });
-        let key = key_string.or(text::ident().map(|s: &str| s.to_string()));
+        // `=~"^[a-z]+$"` or `!~"^-"`: the strings a regex does or does not
+        // match.
+        let pattern = choice((just("=~"), just("!~")))
+            .then(string.clone())
+            .validate(|(op, (v, _)), e, emitter| {
+                let regex = match v.kind {
+                    ValueKind::String(s) => s,
+                    _ => {
+                        emitter.emit(Rich::custom(v.span, "a pattern cannot be interpolated"));
+                        String::new()
+                    }
+                };
+                if let Err(err) = regex::Regex::new(&regex) {
+                    emitter.emit(pattern_error(&regex, &err, v.span));
+                }
+                let text = Text {
+                    min: 0,
+                    max: None,
+                    patterns: vec![Pattern {
+                        regex,
+                        negated: op == "!~",
+                    }],
+                };
+                (
+                    SpannedValue {
+                        span: e.span(),
+                        kind: ValueKind::Type(ValType::Text(Box::new(text))),
+                    },
+                    Vec::new(),
+                )
+            });
+
+        let key = string_literal().or(text::ident().map(|s: &str| s.to_string()));
 
         let key_span = key.map_with(|k: String, e| (k, e.span()));
 
+        // `key?:` marks an optional member.
+        let key_span = key_span.then(just('?').or_not().map(|q| q.is_some()));
+
         let member = key_span
+            .clone()
             .then_ignore(just(':').padded_by(ws))
             .then(value.clone())
-            .map(
-                |((k, k_span), (mut v, anns)): (
-                    (String, Span),
-                    (SpannedValue, Vec<Annotation>),
-                )| {
-                    let span = SimpleSpan::new((), k_span.start()..v.span.end());
-                    v.span = span;
-                    (k, v, span, anns)
-                },
-            );
+            .map(|(((k, k_span), optional), (v, anns))| {
+                spanned_member(k, k_span, optional, v, anns)
+            });
         let comma = just(',').then_ignore(ws).ignored();
         let object = member
             .separated_by(choice((comma, ws1)))
@@ -255,11 +401,11 @@ fn spanned_value_no_pad<'a>()
             .collect::<Vec<_>>()
             .then(value.clone())
             .map(|(keys, (mut v, anns))| {
-                for (k, k_span) in keys.into_iter().rev() {
-                    let span = SimpleSpan::new((), k_span.start()..v.span.end());
+                for ((k, k_span), optional) in keys.into_iter().rev() {
+                    let member = spanned_member(k, k_span, optional, v, anns.clone());
                     v = SpannedValue {
-                        span,
-                        kind: ValueKind::Object(vec![(k, v, span, anns.clone())]),
+                        span: member.2,
+                        kind: ValueKind::Object(vec![member]),
                     };
                 }
                 (v, Vec::new())
@@ -298,8 +444,41 @@ fn spanned_value_no_pad<'a>()
 
         let hspace = one_of(" \t").repeated().at_least(1).ignored();
 
-        let call = reference.then_ignore(hspace).then(value.clone()).map_with(
-            |((func, _), (arg, _)), e| {
+        let group = value
+            .clone()
+            .delimited_by(just('(').padded_by(ws), ws.then_ignore(just(')')))
+            .map(|(v, _)| (v, Vec::new()));
+
+        // `f(a, b)`, with no space before the parenthesis.
+        let arguments = value
+            .clone()
+            .separated_by(just(',').padded_by(ws))
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .delimited_by(just('(').padded_by(ws), ws.then_ignore(just(')')))
+            .map_with(|vals, e| SpannedValue {
+                span: e.span(),
+                kind: ValueKind::Arguments(vals.into_iter().map(|(v, _)| v).collect()),
+            });
+        let positional_call = reference.then(arguments).map_with(|((func, _), args), e| {
+            let ValueKind::Reference(name) = func.kind else {
+                unreachable!()
+            };
+            (
+                SpannedValue {
+                    span: e.span(),
+                    kind: ValueKind::Call(name, Box::new(args)),
+                },
+                Vec::new(),
+            )
+        });
+
+        // `f >1` compares rather than passing a bound.
+        let call = reference
+            .then_ignore(hspace)
+            .then_ignore(none_of("<>").rewind())
+            .then(value.clone())
+            .map_with(|((func, _), (arg, _)), e| {
                 let name = if let ValueKind::Reference(n) = func.kind {
                     n
                 } else {
@@ -312,8 +491,7 @@ fn spanned_value_no_pad<'a>()
                     },
                     Vec::new(),
                 )
-            },
-        );
+            });
 
         let annotation = just('@')
             .ignore_then(choice((
@@ -391,47 +569,111 @@ fn spanned_value_no_pad<'a>()
                 })
                 .map(|v| (v, Vec::new())),
             text::keyword("String")
-                .map_with(|_, e| SpannedValue {
-                    span: e.span(),
-                    kind: ValueKind::Type(ValType::String),
-                })
-                .map(|v| (v, Vec::new())),
+                .ignore_then(bounds.or_not())
+                .validate(|bounds, e, emitter| {
+                    let kind = match bounds {
+                        None => ValType::String,
+                        Some((min, max)) => {
+                            if let Some(max) = max.filter(|max| *max < min) {
+                                emitter.emit(Rich::custom(
+                                    e.span(),
+                                    format!("minimum length {} is more than maximum {}", min, max),
+                                ));
+                            }
+                            ValType::Text(Box::new(Text {
+                                min,
+                                max,
+                                patterns: Vec::new(),
+                            }))
+                        }
+                    };
+                    (
+                        SpannedValue {
+                            span: e.span(),
+                            kind: ValueKind::Type(kind),
+                        },
+                        Vec::new(),
+                    )
+                }),
             text::keyword("Boolean")
                 .map_with(|_, e| SpannedValue {
                     span: e.span(),
                     kind: ValueKind::Type(ValType::Boolean),
                 })
                 .map(|v| (v, Vec::new())),
-            annotation,
             number,
+            bound,
+            pattern,
             string,
             array,
             object,
+            group,
+            positional_call,
             call,
             chain,
             reference,
-        ));
+        ))
+        .boxed();
 
-        let op = atom_base
-            .clone()
-            .then_ignore(hspace)
-            .then(one_of("+-").map(|c: char| c.to_string()))
-            .then_ignore(hspace)
-            .then(value.clone())
-            .map_with(|(((left, _), op), (right, _)), e| {
-                (
+        // `-x` is read as `0 - x`. A minus sign directly before a number is
+        // part of the number.
+        let unary = recursive(|unary| {
+            atom_base.clone().map(|(v, _)| v).or(just('-')
+                .map_with(|_, e| e.span())
+                .then(unary)
+                .map(|(minus, operand): (Span, SpannedValue)| {
+                    let zero = SpannedValue {
+                        span: minus,
+                        kind: ValueKind::Int(0),
+                    };
+                    SpannedValue {
+                        span: Span::new(minus.context, minus.start..operand.span.end),
+                        kind: ValueKind::OpCall("-".into(), Box::new(zero), Box::new(operand)),
+                    }
+                }))
+        })
+        .boxed();
+        let product = binary(unary, choice((just("//"), just("*"), just("/"), just("%")))).boxed();
+        let sum = binary(product, choice((just("+"), just("-")))).boxed();
+        let comparison = binary(
+            sum,
+            choice((
+                just("=="),
+                just("!="),
+                just("<="),
+                just(">="),
+                just("<"),
+                just(">"),
+            )),
+        );
+        let conjunction = binary(comparison, just("&")).boxed();
+        let atom = choice((annotation, conjunction.map(|v| (v, Vec::new()))));
+
+        // `*value` marks the default alternative of a union.
+        let alternative = just('*')
+            .or_not()
+            .then(atom.clone())
+            .map_with(|(star, (v, anns)), e| match star {
+                Some(_) => (
                     SpannedValue {
                         span: e.span(),
-                        kind: ValueKind::OpCall(op, Box::new(left), Box::new(right)),
+                        kind: ValueKind::Default(Box::new(v)),
                     },
-                    Vec::new(),
-                )
+                    anns,
+                ),
+                None => (v, anns),
+            });
+        let lone_default = just('*')
+            .ignore_then(atom.clone())
+            .validate(|v, e, emitter| {
+                emitter.emit(Rich::custom(
+                    e.span(),
+                    "a default needs other alternatives, as in `*1 | Int`",
+                ));
+                v
             });
 
-        let atom = choice((op, atom_base));
-
-        let union = atom
-            .clone()
+        let union = alternative
             .separated_by(just('|').padded_by(ws))
             .at_least(2)
             .collect::<Vec<_>>()
@@ -445,6 +687,303 @@ fn spanned_value_no_pad<'a>()
                 )
             });
 
-        choice((union, atom))
+        choice((union, lone_default, atom))
+    })
+}
+
+/// Operands separated by any of `ops`, grouped to the left. Operators may be
+/// surrounded by spaces or tabs but not line breaks.
+/// The largest exponent a decimal literal may have, so that writing one
+/// cannot make the parser build a number with millions of digits.
+const MAX_EXPONENT: u64 = 10_000;
+
+/// The exact value of a decimal literal such as `-1.25e-3`, read from its
+/// digits and exponent. `None` if the exponent is larger than
+/// [`MAX_EXPONENT`].
+fn decimal(s: &str) -> Option<BigRational> {
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i64>().ok()?),
+        None => (s, 0),
+    };
+    if exponent.unsigned_abs() > MAX_EXPONENT {
+        return None;
+    }
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    // Digits with an optional sign always parse.
+    let digits: BigInt = format!("{}{}", int, frac).parse().unwrap();
+    let exponent = exponent - frac.len() as i64;
+    let scale = BigInt::from(10).pow(exponent.unsigned_abs() as u32);
+    Some(match exponent >= 0 {
+        true => BigRational::from_integer(digits * scale),
+        false => BigRational::new(digits, scale),
+    })
+}
+
+fn exponent_error<'a>(s: &str, span: Span) -> ParseError<'a> {
+    Rich::custom(
+        span,
+        format!(
+            "exponent of {} is out of range; at most {} is supported",
+            s, MAX_EXPONENT
+        ),
+    )
+}
+
+/// The start of the message for a regex that does not compile, which
+/// [`crate::diagnostic::Diagnostic::parse`] reports as an invalid pattern
+/// rather than a syntax error.
+pub(crate) const INVALID_PATTERN: &str = "invalid pattern ";
+
+/// `invalid pattern "(": unclosed group`. The `regex` crate spreads a
+/// syntax error over several lines with a caret under the fault; only its
+/// last line, the reason, is kept.
+fn pattern_error<'a>(regex: &str, err: &regex::Error, span: Span) -> ParseError<'a> {
+    let err = err.to_string();
+    let reason = err.lines().last().unwrap_or_default();
+    Rich::custom(
+        span,
+        format!(
+            "{}{}: {}",
+            INVALID_PATTERN,
+            serde_json::to_string(regex).unwrap(),
+            reason.strip_prefix("error: ").unwrap_or(reason)
+        ),
+    )
+}
+
+fn binary<'a, P, O>(
+    operand: P,
+    ops: O,
+) -> impl Parser<'a, ParserInput<'a>, SpannedValue, extra::Err<ParseError<'a>>> + Clone
+where
+    P: Parser<'a, ParserInput<'a>, SpannedValue, extra::Err<ParseError<'a>>> + Clone,
+    O: Parser<'a, ParserInput<'a>, &'a str, extra::Err<ParseError<'a>>> + Clone,
+{
+    let hspace = one_of(" \t").repeated();
+    operand.clone().foldl(
+        ops.padded_by(hspace).then(operand).repeated(),
+        |left: SpannedValue, (op, right): (&str, SpannedValue)| SpannedValue {
+            span: Span::new(left.span.context, left.span.start..right.span.end),
+            kind: ValueKind::OpCall(op.to_string(), Box::new(left), Box::new(right)),
+        },
+    )
+}
+
+/// Part of a string literal: a character, one UTF-16 code unit written as
+/// a `\u` escape, an interpolated `\(value)`, or, in a multi-line string, a
+/// line break with the indentation after it.
+#[derive(Clone)]
+enum Piece {
+    Char(char),
+    Unit(u16, Span),
+    Value(SpannedValue),
+    Line(String),
+}
+
+/// A character or escape inside a double-quoted string, with JSON escapes.
+fn string_piece<'a>() -> impl Parser<'a, ParserInput<'a>, Piece, extra::Err<ParseError<'a>>> + Copy
+{
+    let unit = just("\\u")
+        .ignore_then(text::digits(16).exactly(4).to_slice())
+        .map_with(|digits: &str, e| {
+            Piece::Unit(u16::from_str_radix(digits, 16).unwrap(), e.span())
+        });
+    let escape = just('\\')
+        .ignore_then(choice((
+            just('\\'),
+            just('/'),
+            just('"'),
+            just('b').to('\x08'),
+            just('f').to('\x0c'),
+            just('n').to('\n'),
+            just('r').to('\r'),
+            just('t').to('\t'),
+        )))
+        .map(Piece::Char);
+    none_of("\\\"").map(Piece::Char).or(unit).or(escape)
+}
+
+/// The start of a raw string: one or more hashes and a quote. A `#` that
+/// does not start one starts a comment.
+fn raw_start<'a>() -> impl Parser<'a, ParserInput<'a>, (), extra::Err<ParseError<'a>>> + Copy {
+    just('#').repeated().at_least(1).then(just('"')).ignored()
+}
+
+/// The pieces of a string in any of its forms: `"..."`, a multi-line
+/// `"""` string, or a raw `#"..."#` or `##"..."##` string in which
+/// backslashes are literal. `extra` parses pieces allowed in quoted strings besides
+/// characters and escapes.
+fn string_pieces<'a, P>(
+    extra: P,
+) -> impl Parser<'a, ParserInput<'a>, Vec<Piece>, extra::Err<ParseError<'a>>> + Clone
+where
+    P: Parser<'a, ParserInput<'a>, Piece, extra::Err<ParseError<'a>>> + Clone,
+{
+    let line = just('\r')
+        .or_not()
+        .ignore_then(just('\n'))
+        .ignore_then(one_of(" \t").repeated().to_slice())
+        .map(|indent: &str| Piece::Line(indent.to_string()));
+    let quote = just('"').and_is(just("\"\"\"").not()).to(Piece::Char('"'));
+    let multi_line = just("\"\"\"")
+        .ignore_then(one_of(" \t").repeated())
+        .ignore_then(
+            line.then(
+                choice((extra.clone(), line, quote, string_piece()))
+                    .repeated()
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .then_ignore(just("\"\"\""))
+        .map(|(first, mut rest)| {
+            rest.insert(0, first);
+            dedent(rest)
+        });
+    // A raw string opened by `n` hashes and a quote ends at a quote and `n`
+    // hashes, so `##"a "# b"##` can hold `"#`.
+    let raw_close = just('"').ignore_then(
+        just('#')
+            .repeated()
+            .configure(|cfg, hashes: &usize| cfg.exactly(*hashes)),
+    );
+    let raw = just('#')
+        .repeated()
+        .at_least(1)
+        .count()
+        .then_ignore(just('"'))
+        .ignore_with_ctx(
+            any()
+                .and_is(raw_close.not())
+                .map(Piece::Char)
+                .repeated()
+                .collect::<Vec<_>>()
+                .then_ignore(raw_close),
+        );
+    let quoted = extra
+        .or(string_piece())
+        .repeated()
+        .collect::<Vec<_>>()
+        .delimited_by(just('"'), just('"'));
+    choice((raw, multi_line, quoted))
+}
+
+/// Removes the indentation common to the lines of a multi-line string, and
+/// the line breaks after the opening and before the closing quotes. The
+/// closing quotes count as a line; other blank lines do not.
+fn dedent(pieces: Vec<Piece>) -> Vec<Piece> {
+    let closing = matches!(pieces.last(), Some(Piece::Line(_)));
+    let last = pieces.len() - 1;
+    let counts = |i: usize| {
+        (i == last && closing) || !matches!(pieces.get(i + 1), None | Some(Piece::Line(_)))
+    };
+    let common = pieces
+        .iter()
+        .enumerate()
+        .filter_map(|(i, piece)| match piece {
+            Piece::Line(indent) if counts(i) => Some(indent.as_str()),
+            _ => None,
+        })
+        .reduce(|a, b| {
+            let len = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+            &a[..len]
+        })
+        .unwrap_or_default()
+        .to_string();
+    let mut out = Vec::new();
+    for (i, piece) in pieces.into_iter().enumerate() {
+        match piece {
+            Piece::Line(_) if i == last && closing => {}
+            Piece::Line(indent) => {
+                if i > 0 {
+                    out.push(Piece::Char('\n'));
+                }
+                let rest = indent.strip_prefix(common.as_str()).unwrap_or_default();
+                out.extend(rest.chars().map(Piece::Char));
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// A string without interpolation, as used for keys.
+fn string_literal<'a>()
+-> impl Parser<'a, ParserInput<'a>, String, extra::Err<ParseError<'a>>> + Clone {
+    // Keys take no pieces besides characters and escapes.
+    let nothing = any().filter(|_: &char| false).map(Piece::Char);
+    string_pieces(nothing).validate(|pieces, e, emitter| {
+        match decode(pieces, e.span(), emitter).pop() {
+            Some(SpannedValue {
+                kind: ValueKind::String(s),
+                ..
+            }) => s,
+            _ => String::new(),
+        }
     })
 }
+
+/// The text of the string literal `src`, in any of its forms.
+pub(crate) fn unquote(src: &str) -> Option<String> {
+    string_literal()
+        .parse(input(src, SourceId::default()))
+        .into_output()
+}
+
+/// Joins the pieces of a string into text, keeping interpolated values
+/// apart, so that each returned value is a `String` or an interpolated
+/// value. Text takes the `span` of the whole literal. A `\u` escape of a
+/// high surrogate followed by one of a low surrogate stands for a single
+/// character; an unpaired surrogate is an error.
+fn decode<'a>(
+    pieces: Vec<Piece>,
+    span: Span,
+    emitter: &mut Emitter<ParseError<'a>>,
+) -> Vec<SpannedValue> {
+    let mut parts = Vec::new();
+    let mut out = String::new();
+    let mut pieces = pieces.into_iter().peekable();
+    while let Some(piece) = pieces.next() {
+        let (unit, unit_span) = match piece {
+            Piece::Char(c) => {
+                out.push(c);
+                continue;
+            }
+            Piece::Value(v) => {
+                if !out.is_empty() {
+                    parts.push(SpannedValue {
+                        span,
+                        kind: ValueKind::String(std::mem::take(&mut out)),
+                    });
+                }
+                parts.push(v);
+                continue;
+            }
+            Piece::Unit(unit, span) => (unit, span),
+            Piece::Line(_) => unreachable!("lines are removed by dedent"),
+        };
+        if let Some(c) = char::from_u32(unit.into()) {
+            out.push(c);
+            continue;
+        }
+        if (0xD800..0xDC00).contains(&unit)
+            && let Some(Piece::Unit(low @ 0xDC00..0xE000, _)) = pieces.peek()
+        {
+            let c = 0x10000 + ((u32::from(unit) - 0xD800) << 10) + (u32::from(*low) - 0xDC00);
+            out.extend(char::from_u32(c));
+            pieces.next();
+            continue;
+        }
+        emitter.emit(Rich::custom(
+            unit_span,
+            format!("unpaired surrogate \\u{:04x} in string", unit),
+        ));
+        out.push(char::REPLACEMENT_CHARACTER);
+    }
+    if !out.is_empty() || parts.is_empty() {
+        parts.push(SpannedValue {
+            span,
+            kind: ValueKind::String(out),
+        });
+    }
+    parts
+}
@@ -0,0 +1,321 @@
+use crate::types::{Annotation, Document, Span, SpannedValue, ValType, ValueKind, to_float};
+use serde_json::Number;
+
+/// Writes `value` as canonical Polsia source. A top-level object is written
+/// without braces, one member per line. Parsing the output and unifying it
+/// again gives a structurally equal tree.
+///
+/// `NaN` has no literal and is written as `Float`.
+pub fn to_string(value: &SpannedValue) -> String {
+    let mut out = String::new();
+    match &value.kind {
+        ValueKind::Object(members) if !members.is_empty() => write_members(&mut out, members, 0),
+        _ => {
+            write_value(&mut out, value, 0, Position::Any);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Like [`to_string`], with the document's imports written first.
+pub fn document_to_string(doc: &Document) -> String {
+    let mut out = String::new();
+    for import in &doc.imports {
+        out.push_str(&format!(
+            "import {} as {}\n",
+            string(&import.path),
+            import.alias
+        ));
+    }
+    if !doc.imports.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&to_string(&doc.value));
+    out
+}
+
+/// Where a value appears, which decides whether it needs parentheses.
+#[derive(Clone, Copy, PartialEq)]
+enum Position {
+    /// Anywhere a full value is accepted.
+    Any,
+    /// An operand of an operator or one alternative of a union. Operators
+    /// that bind less tightly than the given precedence need parentheses.
+    Operand(u8),
+}
+
+fn write_members(
+    out: &mut String,
+    members: &[(String, SpannedValue, Span, Vec<Annotation>)],
+    indent: usize,
+) {
+    let pad = "  ".repeat(indent);
+    for (k, v, _, anns) in members {
+        let k = if anns.contains(&Annotation::Optional) {
+            format!("{}?", key(k))
+        } else {
+            key(k)
+        };
+        let shown: Vec<_> = anns.iter().filter_map(annotation).collect();
+        for ann in &shown {
+            out.push_str(&format!("{}{}: {}\n", pad, k, ann));
+        }
+        if !shown.is_empty() && v.kind == ValueKind::Type(ValType::Any) {
+            continue;
+        }
+        out.push_str(&pad);
+        out.push_str(&k);
+        out.push_str(": ");
+        write_value(out, v, indent, Position::Any);
+        out.push('\n');
+    }
+}
+
+fn write_value(out: &mut String, value: &SpannedValue, indent: usize, pos: Position) {
+    let parens = match (pos, &value.kind) {
+        (Position::Any, _) => false,
+        (Position::Operand(_), ValueKind::Call(..) | ValueKind::Union(_)) => true,
+        // `&` groups either way, so a range needs no parentheses beside one.
+        (Position::Operand(min), ValueKind::Type(ValType::Range(_) | ValType::Text(_))) => {
+            min > precedence("&") + 1
+        }
+        // Written as a call to `close`.
+        (Position::Operand(_), ValueKind::Object(members)) => members
+            .iter()
+            .any(|(_, _, _, anns)| anns.contains(&Annotation::Closed)),
+        (Position::Operand(min), ValueKind::OpCall(op, _, _)) => precedence(op) < min,
+        // Written as a division.
+        (Position::Operand(min), ValueKind::Rational(n)) => {
+            !n.is_integer() && min > precedence("/")
+        }
+        _ => false,
+    };
+    if parens {
+        out.push('(');
+    }
+    match &value.kind {
+        ValueKind::Null => out.push_str("null"),
+        ValueKind::Bool(b) => out.push_str(&b.to_string()),
+        ValueKind::Int(n) => out.push_str(&n.to_string()),
+        ValueKind::Float(n) => out.push_str(&float(*n)),
+        ValueKind::Decimal(n) => out.push_str(&float(to_float(n))),
+        ValueKind::Rational(n) => out.push_str(&n.to_string()),
+        ValueKind::String(s) if s.contains('\n') => out.push_str(&multi_line(s, indent)),
+        ValueKind::String(s) => out.push_str(&string(s)),
+        ValueKind::Reference(r) => out.push_str(r),
+        ValueKind::Type(t) => out.push_str(&t.to_string()),
+        ValueKind::Array(items) if items.is_empty() => out.push_str("[]"),
+        ValueKind::Array(items) if items.iter().all(is_inline) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, item, indent, Position::Any);
+            }
+            out.push(']');
+        }
+        ValueKind::Array(items) => {
+            let pad = "  ".repeat(indent + 1);
+            out.push_str("[\n");
+            for item in items {
+                out.push_str(&pad);
+                write_value(out, item, indent + 1, Position::Any);
+                out.push_str(",\n");
+            }
+            out.push_str(&"  ".repeat(indent));
+            out.push(']');
+        }
+        ValueKind::List(list) => {
+            let inline = list.values().all(is_inline);
+            let pad = "  ".repeat(indent + 1);
+            out.push('[');
+            for (i, item) in list.values().enumerate() {
+                match (inline, i) {
+                    (true, 0) => {}
+                    (true, _) => out.push_str(", "),
+                    (false, _) => {
+                        out.push('\n');
+                        out.push_str(&pad);
+                    }
+                }
+                if i == list.items.len() {
+                    out.push_str("...");
+                }
+                write_value(out, item, indent + usize::from(!inline), Position::Any);
+                if !inline {
+                    out.push(',');
+                }
+            }
+            if !inline {
+                out.push('\n');
+                out.push_str(&"  ".repeat(indent));
+            }
+            out.push(']');
+            match (list.min, list.max) {
+                (0, None) => {}
+                (min, None) => out.push_str(&format!("{{{},}}", min)),
+                (min, Some(max)) if min == max => out.push_str(&format!("{{{}}}", min)),
+                (min, Some(max)) => out.push_str(&format!("{{{},{}}}", min, max)),
+            }
+        }
+        ValueKind::Object(members) if members.is_empty() => out.push_str("{}"),
+        ValueKind::Object(members) => {
+            if members
+                .iter()
+                .any(|(_, _, _, anns)| anns.contains(&Annotation::Closed))
+            {
+                out.push_str("close ");
+            }
+            out.push_str("{\n");
+            write_members(out, members, indent + 1);
+            out.push_str(&"  ".repeat(indent));
+            out.push('}');
+        }
+        ValueKind::Call(name, arg) => {
+            out.push_str(name);
+            if !matches!(arg.kind, ValueKind::Arguments(_)) {
+                out.push(' ');
+            }
+            write_value(out, arg, indent, Position::Any);
+        }
+        ValueKind::Arguments(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, item, indent, Position::Any);
+            }
+            out.push(')');
+        }
+        ValueKind::OpCall(op, left, right) => {
+            let p = precedence(op);
+            write_value(out, left, indent, Position::Operand(p));
+            out.push_str(&format!(" {} ", op));
+            write_value(out, right, indent, Position::Operand(p + 1));
+        }
+        ValueKind::Interpolation(parts) => {
+            out.push('"');
+            for part in parts {
+                match &part.kind {
+                    ValueKind::String(s) => {
+                        let quoted = string(s);
+                        out.push_str(&quoted[1..quoted.len() - 1]);
+                    }
+                    _ => {
+                        out.push_str("\\(");
+                        write_value(out, part, indent, Position::Any);
+                        out.push(')');
+                    }
+                }
+            }
+            out.push('"');
+        }
+        ValueKind::Union(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(" | ");
+                }
+                write_value(out, item, indent, Position::Operand(0));
+            }
+        }
+        ValueKind::Default(v) => {
+            out.push('*');
+            write_value(out, v, indent, Position::Operand(0));
+        }
+    }
+    if parens {
+        out.push(')');
+    }
+}
+
+/// Arrays of scalars stay on one line; anything with nested structure is
+/// written one item per line.
+fn is_inline(value: &SpannedValue) -> bool {
+    match &value.kind {
+        ValueKind::String(s) => !s.contains('\n'),
+        ValueKind::Array(items) => items.is_empty(),
+        ValueKind::List(list) => list.values().all(is_inline),
+        ValueKind::Object(members) => members.is_empty(),
+        ValueKind::Call(_, arg) | ValueKind::Default(arg) => is_inline(arg),
+        ValueKind::OpCall(_, left, right) => is_inline(left) && is_inline(right),
+        ValueKind::Union(items) | ValueKind::Interpolation(items) | ValueKind::Arguments(items) => {
+            items.iter().all(is_inline)
+        }
+        _ => true,
+    }
+}
+
+/// How tightly a binary operator binds.
+fn precedence(op: &str) -> u8 {
+    match op {
+        "&" => 0,
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => 1,
+        "+" | "-" => 2,
+        _ => 3,
+    }
+}
+
+/// The annotation as written on a line of its own. Optional members are
+/// written `key?:` and closed structs `close { ... }` instead.
+fn annotation(ann: &Annotation) -> Option<&'static str> {
+    match ann {
+        Annotation::NoExport => Some("@NoExport"),
+        Annotation::Function => Some("@Function"),
+        Annotation::Optional | Annotation::Closed => None,
+    }
+}
+
+fn float(n: f64) -> String {
+    if n.is_nan() {
+        "Float".into()
+    } else if n.is_infinite() {
+        // Overflows back to infinity when parsed.
+        if n > 0.0 { "1e999" } else { "-1e999" }.into()
+    } else {
+        Number::from_f64(n).unwrap().to_string()
+    }
+}
+
+fn key(k: &str) -> String {
+    let mut chars = k.chars();
+    let is_ident = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_ident { k.to_string() } else { string(k) }
+}
+
+fn string(s: &str) -> String {
+    // JSON string syntax is valid Polsia string syntax.
+    serde_json::to_string(s).unwrap()
+}
+
+/// A `"""` string with each line indented one level deeper than `indent`.
+fn multi_line(s: &str, indent: usize) -> String {
+    let pad = "  ".repeat(indent + 1);
+    let mut out = String::from("\"\"\"\n");
+    for line in s.split('\n') {
+        if !line.is_empty() {
+            out.push_str(&pad);
+        }
+        for c in line.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '"' if s.contains("\"\"\"") => out.push_str("\\\""),
+                '\t' => out.push('\t'),
+                c if c.is_control() => {
+                    let quoted = string(&c.to_string());
+                    out.push_str(&quoted[1..quoted.len() - 1]);
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str(&pad);
+    out.push_str("\"\"\"");
+    out
+}
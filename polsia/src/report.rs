@@ -0,0 +1,119 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::export::ExportError;
+use crate::loader::LoadError;
+use crate::parser::ParseError;
+use crate::types::{SourceId, Span};
+use crate::unify::UnifyError;
+use ariadne::{Cache, Color, Config, Label, Report, ReportKind, sources};
+use std::ops::Range;
+
+pub type ReportSpan = (String, Range<usize>);
+
+/// The source files taking part in one evaluation, indexed by [`SourceId`].
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<(String, String)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) -> SourceId {
+        self.files.push((name.into(), text.into()));
+        SourceId(self.files.len() - 1)
+    }
+
+    pub fn name(&self, id: SourceId) -> &str {
+        &self.files[id.0].0
+    }
+
+    pub fn text(&self, id: SourceId) -> &str {
+        &self.files[id.0].1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SourceId, &str, &str)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(i, (name, text))| (SourceId(i), name.as_str(), text.as_str()))
+    }
+
+    /// An ariadne cache over every file, keyed by file name.
+    pub fn cache(&self) -> impl Cache<String> + '_ {
+        sources(
+            self.files
+                .iter()
+                .map(|(name, text)| (name.clone(), text.as_str())),
+        )
+    }
+
+    fn report_span(&self, span: Span) -> ReportSpan {
+        (self.name(span.context).to_string(), span.into_range())
+    }
+}
+
+/// Renders `d` with its primary span in red and its labels in yellow.
+pub fn diagnostic_report(
+    d: &Diagnostic,
+    sources: &SourceMap,
+    config: Config,
+) -> Report<'static, ReportSpan> {
+    let span = sources.report_span(d.span);
+    let kind = match d.severity {
+        Severity::Error => ReportKind::Error,
+        Severity::Warning => ReportKind::Warning,
+    };
+    Report::build(kind, span.clone())
+        .with_config(config)
+        .with_code(d.code.as_str())
+        .with_message(&d.message)
+        .with_label(
+            Label::new(span)
+                .with_message(&d.message)
+                .with_color(Color::Red),
+        )
+        .with_labels(d.labels.iter().map(|l| {
+            Label::new(sources.report_span(l.span))
+                .with_message(&l.message)
+                .with_color(Color::Yellow)
+        }))
+        .finish()
+}
+
+pub fn parse_error_report(
+    e: &ParseError,
+    sources: &SourceMap,
+    config: Config,
+) -> Report<'static, ReportSpan> {
+    diagnostic_report(&Diagnostic::parse(e), sources, config)
+}
+
+pub fn unify_error_report(
+    err: &UnifyError,
+    sources: &SourceMap,
+    config: Config,
+) -> Report<'static, ReportSpan> {
+    diagnostic_report(&Diagnostic::unify(err), sources, config)
+}
+
+pub fn export_error_report(
+    span: Span,
+    err: &ExportError,
+    sources: &SourceMap,
+    config: Config,
+) -> Report<'static, ReportSpan> {
+    diagnostic_report(&Diagnostic::export(span, err), sources, config)
+}
+
+pub fn load_error_reports(
+    err: &LoadError,
+    sources: &SourceMap,
+    config: Config,
+) -> Vec<Report<'static, ReportSpan>> {
+    Diagnostic::load(err)
+        .iter()
+        .map(|d| diagnostic_report(d, sources, config))
+        .collect()
+}
@@ -0,0 +1,609 @@
+use crate::export;
+use crate::native::{NativeFunction, Natives};
+use crate::types::{ValType, Value, to_float};
+use crate::unify::value_size;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+
+/// A standard library function. It is only called once its argument is
+/// concrete.
+struct Std {
+    arg: fn() -> Value,
+    call: fn(&Value) -> Result<Value, String>,
+    size: Option<fn(&Value) -> usize>,
+}
+
+impl NativeFunction for Std {
+    fn arg(&self) -> Value {
+        (self.arg)()
+    }
+
+    fn call(&self, arg: &Value) -> Result<Option<Value>, String> {
+        if !is_concrete(arg) {
+            return Ok(None);
+        }
+        (self.call)(arg).map(Some)
+    }
+
+    fn result_size(&self, arg: &Value) -> Option<usize> {
+        self.size.filter(|_| is_concrete(arg)).map(|size| size(arg))
+    }
+}
+
+type Entry = (
+    &'static str,
+    fn() -> Value,
+    fn(&Value) -> Result<Value, String>,
+);
+
+const FUNCTIONS: &[Entry] = &[
+    // Strings.
+    ("upper", string, |v| {
+        Ok(Value::String(text(v).to_uppercase()))
+    }),
+    ("lower", string, |v| {
+        Ok(Value::String(text(v).to_lowercase()))
+    }),
+    ("trim", string, |v| Ok(Value::String(text(v).trim().into()))),
+    ("split", || strings(&["s", "sep"]), split),
+    (
+        "join",
+        || fields(&[("list", any()), ("sep", string())]),
+        join,
+    ),
+    ("replace", || strings(&["s", "from", "to"]), replace),
+    (
+        "contains",
+        || strings(&["s", "part"]),
+        |v| {
+            Ok(Value::Bool(
+                text(field(v, "s")).contains(text(field(v, "part"))),
+            ))
+        },
+    ),
+    (
+        "startsWith",
+        || strings(&["s", "prefix"]),
+        |v| {
+            Ok(Value::Bool(
+                text(field(v, "s")).starts_with(text(field(v, "prefix"))),
+            ))
+        },
+    ),
+    (
+        "format",
+        || fields(&[("template", string()), ("args", any())]),
+        format,
+    ),
+    // Lists.
+    ("len", any, len),
+    ("concat", any, concat),
+    ("flatten", any, |v| {
+        let mut out = Vec::new();
+        flatten(list(v)?, &mut out);
+        Ok(Value::Array(out))
+    }),
+    ("map", || fields(&[("list", any()), ("f", string())]), map),
+    (
+        "filter",
+        || fields(&[("list", any()), ("f", string())]),
+        filter,
+    ),
+    ("sort", any, sort),
+    ("unique", any, |v| {
+        let mut out: Vec<Value> = Vec::new();
+        for item in list(v)? {
+            if !out.contains(item) {
+                out.push(item.clone());
+            }
+        }
+        Ok(Value::Array(out))
+    }),
+    ("range", range_arg, range),
+    // Objects.
+    ("keys", any, |v| {
+        let keys = object(v)?.iter().map(|(k, _)| Value::String(k.clone()));
+        Ok(Value::Array(keys.collect()))
+    }),
+    ("values", any, |v| {
+        Ok(Value::Array(
+            object(v)?.iter().map(|(_, v)| v.clone()).collect(),
+        ))
+    }),
+    ("merge", any, merge),
+    (
+        "pick",
+        || fields(&[("object", any()), ("keys", any())]),
+        |v| select(v, true),
+    ),
+    (
+        "omit",
+        || fields(&[("object", any()), ("keys", any())]),
+        |v| select(v, false),
+    ),
+    // Math.
+    ("min", any, |v| extreme(v, Ordering::Less)),
+    ("max", any, |v| extreme(v, Ordering::Greater)),
+    ("abs", number, |v| {
+        Ok(match v {
+            Value::Float(n) => Value::Float(n.abs()),
+            other => exact(exact_number(other).abs()),
+        })
+    }),
+    ("floor", number, |v| {
+        round_with(v, "floor", BigRational::floor)
+    }),
+    ("ceil", number, |v| round_with(v, "ceil", BigRational::ceil)),
+    ("round", number, |v| {
+        round_with(v, "round", BigRational::round)
+    }),
+    // Encoding.
+    ("base64Encode", string, |v| {
+        Ok(Value::String(STANDARD.encode(text(v))))
+    }),
+    ("base64Decode", string, |v| {
+        let bytes = STANDARD
+            .decode(text(v))
+            .map_err(|e| format!("invalid base64: {}", e))?;
+        String::from_utf8(bytes)
+            .map(Value::String)
+            .map_err(|_| "decoded base64 is not UTF-8 text".to_string())
+    }),
+    ("jsonEncode", any, |v| {
+        crate::json::to_string(v)
+            .map(Value::String)
+            .map_err(|e| e.to_string())
+    }),
+    ("jsonDecode", string, |v| {
+        crate::json::from_str(text(v)).map_err(|e| format!("invalid JSON: {}", e))
+    }),
+    ("sha256", string, |v| {
+        let digest = Sha256::digest(text(v).as_bytes());
+        Ok(Value::String(
+            digest.iter().map(|b| format!("{:02x}", b)).collect(),
+        ))
+    }),
+];
+
+type Size = (&'static str, fn(&Value) -> usize);
+
+/// How large the results of the functions that can build large values
+/// from small arguments will be, so that the limit is checked first.
+const SIZES: &[Size] = &[
+    ("split", split_size),
+    ("join", join_size),
+    ("replace", replace_size),
+    ("concat", concat_size),
+    ("range", range_size),
+];
+
+/// The call `filter` returns to keep the items `f` returned `true` for. It
+/// is not an identifier, so no field or native can share it.
+pub(crate) const FILTER_WHERE: &str = "filter/where";
+
+/// The native behind [`FILTER_WHERE`], which is not in any registry.
+pub(crate) fn filter_where() -> impl NativeFunction {
+    Std {
+        arg: || fields(&[("list", any()), ("keep", any())]),
+        call: keep_where,
+        size: None,
+    }
+}
+
+/// Adds the standard library to `natives`.
+pub(crate) fn register(natives: &mut Natives) {
+    for &(name, arg, call) in FUNCTIONS {
+        let size = SIZES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, size)| size);
+        natives.insert(name, Std { arg, call, size });
+    }
+}
+
+/// How a scalar reads inside a string, or `None` for anything else.
+pub(crate) fn display(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Rational(n) => n.to_string(),
+        Value::Float(n) => n.to_string(),
+        Value::Decimal(n) => to_float(n).to_string(),
+        _ => return None,
+    })
+}
+
+fn is_concrete(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => items.iter().all(is_concrete),
+        Value::Object(members) => members.iter().all(|(_, v)| is_concrete(v)),
+        other => export::unresolved(other).is_none(),
+    }
+}
+
+fn any() -> Value {
+    Value::Type(ValType::Any)
+}
+
+fn string() -> Value {
+    Value::Type(ValType::String)
+}
+
+fn number() -> Value {
+    Value::Type(ValType::Number)
+}
+
+fn fields(fields: &[(&str, Value)]) -> Value {
+    Value::Object(
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect(),
+    )
+}
+
+fn strings(names: &[&str]) -> Value {
+    fields(&names.iter().map(|n| (*n, string())).collect::<Vec<_>>())
+}
+
+fn range_arg() -> Value {
+    let int = Value::Type(ValType::Int);
+    Value::Union(vec![
+        int.clone(),
+        fields(&[("from", int.clone()), ("to", int)]),
+    ])
+}
+
+/// The member `name` of an argument whose shape has been checked.
+fn field<'a>(value: &'a Value, name: &str) -> &'a Value {
+    match value {
+        Value::Object(members) => &members.iter().find(|(k, _)| k == name).unwrap().1,
+        _ => unreachable!("argument shape is checked"),
+    }
+}
+
+/// The text of a value checked to be a `String`.
+fn text(value: &Value) -> &str {
+    match value {
+        Value::String(s) => s,
+        _ => unreachable!("argument shape is checked"),
+    }
+}
+
+fn list(value: &Value) -> Result<&[Value], String> {
+    match value {
+        Value::Array(items) => Ok(items),
+        other => Err(format!("expected a list, got {}", kind(other))),
+    }
+}
+
+fn object(value: &Value) -> Result<&[(String, Value)], String> {
+    match value {
+        Value::Object(members) => Ok(members),
+        other => Err(format!("expected an object, got {}", kind(other))),
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "Boolean",
+        Value::Int(_) => "Int",
+        Value::Float(_) => "Float",
+        Value::Rational(_) => "Rational",
+        Value::String(_) => "String",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "an object",
+        _ => "an expression",
+    }
+}
+
+fn split(v: &Value) -> Result<Value, String> {
+    let (s, sep) = (text(field(v, "s")), text(field(v, "sep")));
+    let parts: Vec<Value> = if sep.is_empty() {
+        s.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        s.split(sep).map(|p| Value::String(p.into())).collect()
+    };
+    Ok(Value::Array(parts))
+}
+
+fn split_size(v: &Value) -> usize {
+    let (s, sep) = (text(field(v, "s")), text(field(v, "sep")));
+    if sep.is_empty() {
+        return 1 + s.chars().count() + s.len();
+    }
+    let count = s.matches(sep).count();
+    2 + count + s.len() - count * sep.len()
+}
+
+fn join(v: &Value) -> Result<Value, String> {
+    let parts = list(field(v, "list"))?
+        .iter()
+        .map(|item| display(item).ok_or_else(|| format!("cannot join {}", kind(item))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::String(parts.join(text(field(v, "sep")))))
+}
+
+fn join_size(v: &Value) -> usize {
+    let items = list(field(v, "list")).unwrap_or_default();
+    let text_len: usize = items.iter().filter_map(display).map(|s| s.len()).sum();
+    let sep = text(field(v, "sep")).len();
+    1 + text_len + items.len().saturating_sub(1).saturating_mul(sep)
+}
+
+fn replace(v: &Value) -> Result<Value, String> {
+    let from = text(field(v, "from"));
+    if from.is_empty() {
+        return Err("cannot replace an empty string".into());
+    }
+    Ok(Value::String(
+        text(field(v, "s")).replace(from, text(field(v, "to"))),
+    ))
+}
+
+fn replace_size(v: &Value) -> usize {
+    let (s, from, to) = (
+        text(field(v, "s")),
+        text(field(v, "from")),
+        text(field(v, "to")),
+    );
+    if from.is_empty() {
+        return 0;
+    }
+    let count = s.matches(from).count();
+    1 + (s.len() - count * from.len()).saturating_add(count.saturating_mul(to.len()))
+}
+
+/// Fills each `{}` in the template with the next argument. `{{` and `}}`
+/// stand for literal braces.
+fn format(v: &Value) -> Result<Value, String> {
+    let args = list(field(v, "args"))?;
+    let mut args_iter = args.iter();
+    let mut out = String::new();
+    let mut chars = text(field(v, "template")).chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                let arg = args_iter
+                    .next()
+                    .ok_or_else(|| format!("too few arguments for format: {}", args.len()))?;
+                out.push_str(&display(arg).ok_or_else(|| format!("cannot format {}", kind(arg)))?);
+            }
+            ('{' | '}', _) => return Err(format!("unmatched {} in format", c)),
+            _ => out.push(c),
+        }
+    }
+    if args_iter.next().is_some() {
+        return Err(format!("too many arguments for format: {}", args.len()));
+    }
+    Ok(Value::String(out))
+}
+
+fn len(v: &Value) -> Result<Value, String> {
+    let n = match v {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.len(),
+        Value::Object(members) => members.len(),
+        other => return Err(format!("{} has no length", kind(other))),
+    };
+    Ok(Value::Int(n as i64))
+}
+
+fn concat(v: &Value) -> Result<Value, String> {
+    let mut out = Vec::new();
+    for item in list(v)? {
+        out.extend_from_slice(list(item)?);
+    }
+    Ok(Value::Array(out))
+}
+
+fn concat_size(v: &Value) -> usize {
+    let items = list(v).unwrap_or_default().iter();
+    let items = items.flat_map(|item| list(item).unwrap_or_default());
+    1 + items
+        .map(|item| value_size(item, usize::MAX))
+        .sum::<usize>()
+}
+
+fn flatten(items: &[Value], out: &mut Vec<Value>) {
+    for item in items {
+        match item {
+            Value::Array(inner) => flatten(inner, out),
+            other => out.push(other.clone()),
+        }
+    }
+}
+
+/// Calls the function named `f` on each item. The calls are evaluated like
+/// any other, so they may wait for their results to be concrete.
+fn map(v: &Value) -> Result<Value, String> {
+    let f = text(field(v, "f"));
+    let calls = list(field(v, "list"))?
+        .iter()
+        .map(|item| Value::Call(f.into(), Box::new(item.clone())));
+    Ok(Value::Array(calls.collect()))
+}
+
+/// Maps the items with `f`, then keeps those it returned `true` for.
+fn filter(v: &Value) -> Result<Value, String> {
+    let items = field(v, "list");
+    let keep = map(v)?;
+    Ok(Value::Call(
+        FILTER_WHERE.into(),
+        Box::new(Value::Object(vec![
+            ("list".into(), items.clone()),
+            ("keep".into(), keep),
+        ])),
+    ))
+}
+
+fn keep_where(v: &Value) -> Result<Value, String> {
+    let (items, keep) = (list(field(v, "list"))?, list(field(v, "keep"))?);
+    if items.len() != keep.len() {
+        return Err("list and keep have different lengths".into());
+    }
+    let mut out = Vec::new();
+    for (item, keep) in items.iter().zip(keep) {
+        match keep {
+            Value::Bool(true) => out.push(item.clone()),
+            Value::Bool(false) => {}
+            other => return Err(format!("expected a Boolean, got {}", kind(other))),
+        }
+    }
+    Ok(Value::Array(out))
+}
+
+fn sort(v: &Value) -> Result<Value, String> {
+    let mut items = list(v)?.to_vec();
+    let mut error = None;
+    items.sort_by(|a, b| {
+        compare(a, b).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            Ordering::Equal
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(Value::Array(items)),
+    }
+}
+
+/// The smallest item for `Less`, the largest for `Greater`.
+fn extreme(v: &Value, wanted: Ordering) -> Result<Value, String> {
+    let items = list(v)?;
+    let mut best = items.first().ok_or("empty list")?;
+    // Comparing the first item with itself checks that it is comparable.
+    for item in items {
+        if compare(item, best)? == wanted {
+            best = item;
+        }
+    }
+    Ok(best.clone())
+}
+
+/// Orders two numbers or two strings.
+fn compare(a: &Value, b: &Value) -> Result<Ordering, String> {
+    let incomparable = || format!("cannot compare {} and {}", kind(a), kind(b));
+    match (a, b) {
+        (Value::String(x), Value::String(y)) => Ok(x.cmp(y)),
+        (Value::Float(_), _) | (_, Value::Float(_)) => {
+            let (x, y) = (
+                float(a).ok_or_else(incomparable)?,
+                float(b).ok_or_else(incomparable)?,
+            );
+            x.partial_cmp(&y)
+                .ok_or_else(|| "cannot compare NaN".to_string())
+        }
+        (Value::Int(_) | Value::Rational(_), Value::Int(_) | Value::Rational(_)) => {
+            Ok(exact_number(a).cmp(&exact_number(b)))
+        }
+        _ => Err(incomparable()),
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float(n) => Some(*n),
+        Value::Int(_) | Value::Rational(_) => exact_number(value).to_f64(),
+        _ => None,
+    }
+}
+
+/// The value of an `Int` or `Rational`.
+fn exact_number(value: &Value) -> BigRational {
+    match value {
+        Value::Int(n) => BigRational::from_integer(BigInt::from(*n)),
+        Value::Rational(n) => n.clone(),
+        _ => unreachable!("not an exact number"),
+    }
+}
+
+/// An `Int` if `n` is an integer that fits in 64 bits, otherwise a
+/// `Rational`.
+fn exact(n: BigRational) -> Value {
+    match n.is_integer().then(|| n.numer().to_i64()).flatten() {
+        Some(i) => Value::Int(i),
+        None => Value::Rational(n),
+    }
+}
+
+fn round_with(v: &Value, name: &str, f: fn(&BigRational) -> BigRational) -> Result<Value, String> {
+    let n = match v {
+        Value::Float(n) => {
+            BigRational::from_float(*n).ok_or_else(|| format!("cannot {} {}", name, n))?
+        }
+        other => exact_number(other),
+    };
+    Ok(exact(f(&n)))
+}
+
+/// The integers from `from` up to but not including `to`. A bare integer,
+/// as in `range 3`, is `to` and counts from `0`; an object needs both.
+fn range(v: &Value) -> Result<Value, String> {
+    let (from, to) = match v {
+        Value::Int(to) => (&0, to),
+        _ => match (field(v, "from"), field(v, "to")) {
+            (Value::Int(from), Value::Int(to)) => (from, to),
+            _ => unreachable!("argument shape is checked"),
+        },
+    };
+    Ok(Value::Array((*from..*to).map(Value::Int).collect()))
+}
+
+fn range_size(v: &Value) -> usize {
+    let (from, to) = match v {
+        Value::Int(to) => (0, *to),
+        _ => match (field(v, "from"), field(v, "to")) {
+            (Value::Int(from), Value::Int(to)) => (*from, *to),
+            _ => unreachable!("argument shape is checked"),
+        },
+    };
+    let count = (i128::from(to) - i128::from(from)).max(0);
+    usize::try_from(count)
+        .unwrap_or(usize::MAX)
+        .saturating_add(1)
+}
+
+/// Combines objects; a later object's members replace earlier ones.
+fn merge(v: &Value) -> Result<Value, String> {
+    let mut out: Vec<(String, Value)> = Vec::new();
+    for item in list(v)? {
+        for (k, v) in object(item)? {
+            match out.iter_mut().find(|(key, _)| key == k) {
+                Some(member) => member.1 = v.clone(),
+                None => out.push((k.clone(), v.clone())),
+            }
+        }
+    }
+    Ok(Value::Object(out))
+}
+
+/// The members of `object` whose keys are in `keys`, or with `keep` false,
+/// those that are not.
+fn select(v: &Value, keep: bool) -> Result<Value, String> {
+    let keys = list(field(v, "keys"))?
+        .iter()
+        .map(|k| match k {
+            Value::String(s) => Ok(s.as_str()),
+            other => Err(format!("expected a String key, got {}", kind(other))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let members = object(field(v, "object"))?
+        .iter()
+        .filter(|(k, _)| keys.contains(&k.as_str()) == keep)
+        .cloned();
+    Ok(Value::Object(members.collect()))
+}
@@ -0,0 +1,167 @@
+use crate::export::{self, ExportError, ExportErrorKind};
+use crate::types::{Value, to_float};
+use serde_json::Number;
+
+/// Renders `value` as a TOML document. The top level must be an object.
+/// Within each table, plain values are written before sub-tables, as TOML
+/// requires; otherwise key order is kept.
+pub fn to_string(value: &Value) -> Result<String, ExportError> {
+    match value {
+        Value::Object(members) => {
+            let mut out = String::new();
+            write_table(&mut out, members, &[], None, "")?;
+            Ok(out)
+        }
+        _ => Err(error("", ExportErrorKind::TopLevelNotTable)),
+    }
+}
+
+fn error(path: &str, kind: ExportErrorKind) -> ExportError {
+    ExportError {
+        path: path.to_string(),
+        kind,
+    }
+}
+
+fn index_path(path: &str, i: usize) -> String {
+    format!("{}[{}]", path, i)
+}
+
+/// Writes the members of one table. `pending` is the table's `[header]`,
+/// written only if the table has plain values of its own.
+fn write_table(
+    out: &mut String,
+    members: &[(String, Value)],
+    header: &[String],
+    mut pending: Option<String>,
+    path: &str,
+) -> Result<(), ExportError> {
+    let mut tables = Vec::new();
+    for (k, v) in members {
+        let member_path = export::member_path(path, k);
+        let is_table = match v {
+            Value::Object(m) => !m.is_empty(),
+            Value::Array(items) => is_table_array(items, &member_path)?,
+            _ => false,
+        };
+        if is_table {
+            tables.push((k, v, member_path));
+        } else {
+            if let Some(header) = pending.take() {
+                push_header(out, &header);
+            }
+            out.push_str(&key(k));
+            out.push_str(" = ");
+            out.push_str(&inline(v, &member_path)?);
+            out.push('\n');
+        }
+    }
+    for (k, v, member_path) in tables {
+        let mut header = header.to_vec();
+        header.push(key(k));
+        match v {
+            Value::Object(m) => {
+                let pending = format!("[{}]", header.join("."));
+                write_table(out, m, &header, Some(pending), &member_path)?;
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if let Value::Object(m) = item {
+                        push_header(out, &format!("[[{}]]", header.join(".")));
+                        write_table(out, m, &header, None, &index_path(&member_path, i))?;
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+fn push_header(out: &mut String, header: &str) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(header);
+    out.push('\n');
+}
+
+/// Whether `items` should be written as an array of tables. Arrays that mix
+/// tables with other values have no TOML representation.
+fn is_table_array(items: &[Value], path: &str) -> Result<bool, ExportError> {
+    let Some(first) = items.first() else {
+        return Ok(false);
+    };
+    let first_is_table = matches!(first, Value::Object(_));
+    for (i, item) in items.iter().enumerate() {
+        if matches!(item, Value::Object(_)) != first_is_table {
+            return Err(error(&index_path(path, i), ExportErrorKind::MixedArray));
+        }
+    }
+    Ok(first_is_table)
+}
+
+fn inline(value: &Value, path: &str) -> Result<String, ExportError> {
+    Ok(match value {
+        Value::Null => return Err(error(path, ExportErrorKind::Null)),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Float(n) if n.is_nan() => "nan".into(),
+        Value::Float(n) if n.is_infinite() => {
+            if *n > 0.0 {
+                "inf".into()
+            } else {
+                "-inf".into()
+            }
+        }
+        Value::Float(n) => Number::from_f64(*n).unwrap().to_string(),
+        Value::Decimal(n) => return inline(&Value::Float(to_float(n)), path),
+        Value::Rational(n) => {
+            return Err(error(path, ExportErrorKind::Inexact(n.clone())));
+        }
+        Value::String(s) => string(s),
+        Value::Array(items) => {
+            is_table_array(items, path)?;
+            let items = items
+                .iter()
+                .enumerate()
+                .map(|(i, v)| inline(v, &index_path(path, i)))
+                .collect::<Result<Vec<_>, _>>()?;
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(members) if members.is_empty() => "{}".into(),
+        Value::Object(members) => {
+            let members = members
+                .iter()
+                .map(|(k, v)| {
+                    Ok(format!(
+                        "{} = {}",
+                        key(k),
+                        inline(v, &export::member_path(path, k))?
+                    ))
+                })
+                .collect::<Result<Vec<_>, ExportError>>()?;
+            format!("{{ {} }}", members.join(", "))
+        }
+        other => {
+            let what = export::unresolved(other).unwrap_or_default();
+            return Err(error(path, ExportErrorKind::Unresolved(what)));
+        }
+    })
+}
+
+fn key(k: &str) -> String {
+    if !k.is_empty()
+        && k.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        k.to_string()
+    } else {
+        string(k)
+    }
+}
+
+fn string(s: &str) -> String {
+    // JSON string escapes are a subset of TOML basic string escapes.
+    serde_json::to_string(s).unwrap()
+}
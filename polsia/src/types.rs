@@ -1,7 +1,15 @@
+use crate::export::{self, ExportError, ExportErrorKind, RationalPolicy};
 use chumsky::span::SimpleSpan;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
 use serde_json::{Map, Number, Value as JsValue};
 
-pub type Span = SimpleSpan<usize>;
+/// Identifies the source file a span points into. Single-file evaluation
+/// uses the default id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceId(pub usize);
+
+pub type Span = SimpleSpan<usize, SourceId>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
@@ -9,27 +17,92 @@ pub enum Value {
     Bool(bool),
     Int(i64),
     Float(f64),
+    /// An exact number that is not an `Int`: a fraction, or an integer too
+    /// large for 64 bits.
+    Rational(BigRational),
+    /// A number written with a decimal point or an exponent, such as `0.1`
+    /// or `1e-400`, kept exact. It is a `Float` unless `Rational` or `Int`
+    /// reads it, and becomes one at the end of evaluation.
+    Decimal(BigRational),
     String(String),
     Array(Vec<Value>),
+    /// A list type such as `[...Int]`.
+    List(List<Value>),
     Object(Vec<(String, Value)>),
     Reference(String),
     Type(ValType),
     Call(String, Box<Value>),
+    /// The positional arguments of a call `f(a, b)`, given to the function's
+    /// parameters in order.
+    Arguments(Vec<Value>),
     OpCall(String, Box<Value>, Box<Value>),
+    /// A string with `\(...)` parts: `String` text and interpolated values,
+    /// in order.
+    Interpolation(Vec<Value>),
     Union(Vec<Value>),
+    /// `*value`: the alternative of a union used when nothing else narrows
+    /// it.
+    Default(Box<Value>),
+}
+
+/// A list type `[A, B, ...T]{min,max}`: arrays that start with `items`,
+/// go on with any number of `rest` values, and have at least `min` and at
+/// most `max` items in all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List<V> {
+    pub items: Vec<V>,
+    pub rest: Box<V>,
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl<V> List<V> {
+    /// The same list type with `f` applied to the item types.
+    pub fn map<W>(&self, mut f: impl FnMut(&V) -> W) -> List<W> {
+        List {
+            items: self.items.iter().map(&mut f).collect(),
+            rest: Box::new(f(&self.rest)),
+            min: self.min,
+            max: self.max,
+        }
+    }
+
+    /// The item types, then the rest type.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.items.iter().chain(std::iter::once(&*self.rest))
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.items
+            .iter_mut()
+            .chain(std::iter::once(&mut *self.rest))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Annotation {
     NoExport,
     Function,
+    /// `key?: value`: left out of the export unless given a concrete value.
+    Optional,
+    /// A member of a `close { ... }` struct, which has no other keys.
+    Closed,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Document {
+    pub imports: Vec<Import>,
     pub value: SpannedValue,
 }
 
+/// `import "path" as alias`, binding another file's value to `alias`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub path: String,
+    pub alias: String,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SpannedValue {
     pub span: Span,
@@ -42,71 +115,212 @@ pub enum ValueKind {
     Bool(bool),
     Int(i64),
     Float(f64),
+    /// See [`Value::Rational`].
+    Rational(BigRational),
+    /// See [`Value::Decimal`].
+    Decimal(BigRational),
     String(String),
     Array(Vec<SpannedValue>),
+    /// See [`Value::List`].
+    List(List<SpannedValue>),
     Object(Vec<(String, SpannedValue, Span, Vec<Annotation>)>),
     Reference(String),
     Type(ValType),
     Call(String, Box<SpannedValue>),
+    /// See [`Value::Arguments`].
+    Arguments(Vec<SpannedValue>),
     OpCall(String, Box<SpannedValue>, Box<SpannedValue>),
+    /// See [`Value::Interpolation`].
+    Interpolation(Vec<SpannedValue>),
     Union(Vec<SpannedValue>),
+    /// See [`Value::Default`].
+    Default(Box<SpannedValue>),
+}
+
+impl ValueKind {
+    /// An exact number: `Int` if it is an integer that fits in 64 bits,
+    /// otherwise `Rational`.
+    pub fn exact(n: BigRational) -> ValueKind {
+        match n.is_integer().then(|| i64::try_from(n.numer())) {
+            Some(Ok(i)) => ValueKind::Int(i),
+            _ => ValueKind::Rational(n),
+        }
+    }
+}
+
+/// The float nearest a decimal, infinite past the range of `f64`.
+pub(crate) fn to_float(n: &BigRational) -> f64 {
+    // Only NaN fails to convert, and a ratio is never NaN.
+    n.to_f64().unwrap()
 }
 
 impl SpannedValue {
+    /// Looks up a nested value by a path such as `a.b[2].c`. An empty path
+    /// selects the value itself.
+    pub fn select(&self, path: &str) -> Option<&SpannedValue> {
+        let mut current = self;
+        for seg in path.split('.').filter(|s| !s.is_empty()) {
+            let (key, indices) = match seg.find('[') {
+                Some(i) => (&seg[..i], &seg[i..]),
+                None => (seg, ""),
+            };
+            if !key.is_empty() {
+                current = match &current.kind {
+                    ValueKind::Object(members) => &members.iter().find(|(k, _, _, _)| k == key)?.1,
+                    _ => return None,
+                };
+            }
+            for index in indices.split('[').skip(1) {
+                let n: usize = index.strip_suffix(']')?.parse().ok()?;
+                current = match &current.kind {
+                    ValueKind::Array(items) => items.get(n)?,
+                    _ => return None,
+                };
+            }
+        }
+        Some(current)
+    }
+
     pub fn to_value(&self) -> Value {
         match &self.kind {
             ValueKind::Null => Value::Null,
             ValueKind::Bool(b) => Value::Bool(*b),
             ValueKind::Int(n) => Value::Int(*n),
             ValueKind::Float(n) => Value::Float(*n),
+            ValueKind::Rational(n) => Value::Rational(n.clone()),
+            ValueKind::Decimal(n) => Value::Decimal(n.clone()),
             ValueKind::String(s) => Value::String(s.clone()),
             ValueKind::Array(a) => Value::Array(a.iter().map(|j| j.to_value()).collect()),
+            ValueKind::List(list) => Value::List(list.map(|v| v.to_value())),
             ValueKind::Object(m) => Value::Object(
                 m.iter()
-                    .filter(|(_, _, _, anns)| {
-                        !anns.contains(&Annotation::NoExport)
-                            && !anns.contains(&Annotation::Function)
-                    })
+                    .filter(|(_, v, _, anns)| export::is_exported(v, anns))
                     .map(|(k, v, _, _)| (k.clone(), v.to_value()))
                     .collect(),
             ),
             ValueKind::Reference(r) => Value::Reference(r.clone()),
             ValueKind::Type(t) => Value::Type(t.clone()),
             ValueKind::Call(name, arg) => Value::Call(name.clone(), Box::new(arg.to_value())),
+            ValueKind::Arguments(items) => {
+                Value::Arguments(items.iter().map(|v| v.to_value()).collect())
+            }
             ValueKind::OpCall(op, left, right) => Value::OpCall(
                 op.clone(),
                 Box::new(left.to_value()),
                 Box::new(right.to_value()),
             ),
+            ValueKind::Interpolation(parts) => {
+                Value::Interpolation(parts.iter().map(|v| v.to_value()).collect())
+            }
             ValueKind::Union(items) => Value::Union(items.iter().map(|v| v.to_value()).collect()),
+            ValueKind::Default(v) => Value::Default(Box::new(v.to_value())),
         }
     }
 }
 
 impl Value {
-    pub fn to_value(&self) -> JsValue {
-        match self {
+    /// Converts to JSON. Fails on values that are not concrete, on floats
+    /// JSON has no literal for, on rationals (see [`Value::with_rationals`])
+    /// and on integers beyond 64 bits, which `serde_json` cannot hold. The
+    /// text exporters such as [`Value::to_pretty_string`] write those.
+    pub fn to_value(&self) -> Result<JsValue, ExportError> {
+        self.to_json("")
+    }
+
+    fn to_json(&self, path: &str) -> Result<JsValue, ExportError> {
+        let error = |kind| ExportError {
+            path: path.to_string(),
+            kind,
+        };
+        Ok(match self {
             Value::Null => JsValue::Null,
             Value::Bool(b) => JsValue::Bool(*b),
             Value::Int(n) => JsValue::Number(Number::from(*n)),
-            Value::Float(n) => JsValue::Number(Number::from_f64(*n).unwrap()),
+            Value::Float(n) => match Number::from_f64(*n) {
+                Some(n) => JsValue::Number(n),
+                None => return Err(error(ExportErrorKind::NonFinite(*n))),
+            },
+            Value::Decimal(n) => return Value::Float(to_float(n)).to_json(path),
+            Value::Rational(n) if n.is_integer() => {
+                match (n.numer().to_i64(), n.numer().to_u64()) {
+                    (Some(i), _) => JsValue::Number(Number::from(i)),
+                    (None, Some(u)) => JsValue::Number(Number::from(u)),
+                    (None, None) => return Err(error(ExportErrorKind::OutOfRange(n.clone()))),
+                }
+            }
+            Value::Rational(n) => return Err(error(ExportErrorKind::Inexact(n.clone()))),
             Value::String(s) => JsValue::String(s.clone()),
-            Value::Array(arr) => JsValue::Array(arr.iter().map(|v| v.to_value()).collect()),
-            Value::Object(obj) => {
-                let map: Map<String, JsValue> =
-                    obj.iter().map(|(k, v)| (k.clone(), v.to_value())).collect();
-                JsValue::Object(map)
+            Value::Array(arr) => JsValue::Array(
+                arr.iter()
+                    .enumerate()
+                    .map(|(i, v)| v.to_json(&format!("{}[{}]", path, i)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(obj) => JsValue::Object(
+                obj.iter()
+                    .map(|(k, v)| Ok((k.clone(), v.to_json(&export::member_path(path, k))?)))
+                    .collect::<Result<Map<_, _>, _>>()?,
+            ),
+            other => {
+                let what = export::unresolved(other).unwrap_or_default();
+                return Err(error(ExportErrorKind::Unresolved(what)));
             }
-            Value::Reference(r) => JsValue::String(r.clone()),
-            Value::Type(t) => panic!("unresolved type {:?}", t),
-            Value::Call(name, _) => panic!("unresolved call {}", name),
-            Value::OpCall(op, _, _) => panic!("unresolved op {}", op),
-            Value::Union(_) => panic!("unresolved union"),
-        }
+        })
+    }
+
+    /// Replaces each rational that is not an integer as `policy` says. With
+    /// [`RationalPolicy::Error`] they are kept, and exporting fails on them.
+    /// With [`RationalPolicy::Float`], one beyond the range of `f64` is an
+    /// error.
+    pub fn with_rationals(&self, policy: RationalPolicy) -> Result<Value, ExportError> {
+        self.with_rationals_at(policy, "")
     }
 
-    pub fn to_pretty_string(&self) -> String {
-        serde_json::to_string_pretty(&self.to_value()).unwrap()
+    fn with_rationals_at(&self, policy: RationalPolicy, path: &str) -> Result<Value, ExportError> {
+        Ok(match self {
+            Value::Rational(n) if !n.is_integer() => match policy {
+                RationalPolicy::Error => self.clone(),
+                RationalPolicy::Float => match to_float(n) {
+                    f if f.is_finite() => Value::Float(f),
+                    _ => {
+                        return Err(ExportError {
+                            path: path.to_string(),
+                            kind: ExportErrorKind::OutOfRange(n.clone()),
+                        });
+                    }
+                },
+                RationalPolicy::String => Value::String(n.to_string()),
+            },
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| v.with_rationals_at(policy, &format!("{}[{}]", path, i)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(members) => Value::Object(
+                members
+                    .iter()
+                    .map(|(k, v)| {
+                        let member = v.with_rationals_at(policy, &export::member_path(path, k))?;
+                        Ok((k.clone(), member))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => self.clone(),
+        })
+    }
+
+    pub fn to_pretty_string(&self) -> Result<String, ExportError> {
+        crate::json::to_string_pretty(self)
+    }
+
+    pub fn to_yaml_string(&self) -> Result<String, ExportError> {
+        crate::yaml::to_string(self)
+    }
+
+    pub fn to_toml_string(&self) -> Result<String, ExportError> {
+        crate::toml::to_string(self)
     }
 }
 
@@ -120,4 +334,122 @@ pub enum ValType {
     Float,
     String,
     Boolean,
+    Range(Box<Range>),
+    Text(Box<Text>),
+}
+
+impl std::fmt::Display for ValType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ValType::Any => "Any",
+            ValType::Nothing => "Nothing",
+            ValType::Int => "Int",
+            ValType::Number => "Number",
+            ValType::Rational => "Rational",
+            ValType::Float => "Float",
+            ValType::String => "String",
+            ValType::Boolean => "Boolean",
+            ValType::Range(range) => return write!(f, "{}", range),
+            ValType::Text(text) => return write!(f, "{}", text),
+        };
+        f.write_str(name)
+    }
+}
+
+/// Numbers of type `number` within bounds, written `Int & >=1 & <=65535`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    /// `Int`, `Rational`, `Float` or `Number`.
+    pub number: Box<ValType>,
+    pub min: Option<Bound>,
+    pub max: Option<Bound>,
+}
+
+/// One end of a [`Range`], which includes `value` if `inclusive`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bound {
+    pub value: BigRational,
+    pub inclusive: bool,
+}
+
+impl Range {
+    /// `>=value` or `>value` when `min` is set, otherwise `<=value` or
+    /// `<value`.
+    pub fn bound(bound: &Bound, min: bool) -> String {
+        let op = match (min, bound.inclusive) {
+            (true, true) => ">=",
+            (true, false) => ">",
+            (false, true) => "<=",
+            (false, false) => "<",
+        };
+        format!("{}{}", op, bound.value)
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if *self.number != ValType::Number {
+            parts.push(self.number.to_string());
+        }
+        if let Some(min) = &self.min {
+            parts.push(Range::bound(min, true));
+        }
+        if let Some(max) = &self.max {
+            parts.push(Range::bound(max, false));
+        }
+        if parts.is_empty() {
+            parts.push(self.number.to_string());
+        }
+        f.write_str(&parts.join(" & "))
+    }
+}
+
+/// Strings with a number of characters within bounds that match patterns,
+/// written `String{1,63} & =~"^[a-z]+$"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub min: usize,
+    pub max: Option<usize>,
+    pub patterns: Vec<Pattern>,
+}
+
+/// `=~"regex"`, or `!~"regex"` for strings the regex does not match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub regex: String,
+    pub negated: bool,
+}
+
+impl Text {
+    /// `String{min,max}`, `String{min,}` or `String{n}`. A maximum alone is
+    /// written with a minimum of 0.
+    pub fn length(&self) -> String {
+        match self.max {
+            Some(max) if max == self.min => format!("String{{{}}}", max),
+            Some(max) => format!("String{{{},{}}}", self.min, max),
+            None => format!("String{{{},}}", self.min),
+        }
+    }
+}
+
+impl std::fmt::Display for Text {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if self.min > 0 || self.max.is_some() || self.patterns.is_empty() {
+            parts.push(match (self.min, self.max) {
+                (0, None) => "String".to_string(),
+                _ => self.length(),
+            });
+        }
+        parts.extend(self.patterns.iter().map(Pattern::to_string));
+        f.write_str(&parts.join(" & "))
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = if self.negated { "!~" } else { "=~" };
+        write!(f, "{}{}", op, serde_json::to_string(&self.regex).unwrap())
+    }
 }
@@ -1,14 +1,542 @@
-use crate::types::{Annotation, Span, SpannedValue, ValType, Value, ValueKind};
-use chumsky::span::{SimpleSpan, Span as ChumSpan};
-use std::collections::BTreeMap;
+use crate::diagnostic::ErrorCode;
+use crate::export::{find_unresolved, is_exported};
+use crate::native::{NativeFunction, Natives};
+use crate::printer;
+use crate::stdlib::{self, display};
+use crate::types::{
+    Annotation, Bound, Document, List, Range, SourceId, Span, SpannedValue, Text, ValType, Value,
+    ValueKind, to_float,
+};
+use chumsky::span::Span as ChumSpan;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnifyError {
+    pub code: ErrorCode,
     pub msg: String,
     pub span: Span,
     pub prev_span: Span,
 }
 
+/// A conflict found by [`unify_tree_collecting`].
+#[derive(Debug)]
+pub struct Conflict {
+    /// Dotted path of the field that failed.
+    pub path: String,
+    pub error: UnifyError,
+    /// Set when the field refers to another field that failed, so the
+    /// conflict is likely a consequence of that one.
+    pub consequential: bool,
+}
+
+/// Records failed fields while unifying in collecting mode.
+struct Collector {
+    conflicts: Vec<(String, UnifyError, BTreeSet<String>)>,
+    /// Every reference in the input, keyed by the field it appears in.
+    edges: Vec<(String, String)>,
+}
+
+impl Collector {
+    /// Records that the field at `path` failed with `err` and returns the
+    /// bottom value that takes its place.
+    fn fail(&mut self, path: &str, err: UnifyError, values: &[SpannedValue]) -> SpannedValue {
+        let mut refs = BTreeSet::new();
+        for v in values {
+            collect_refs(v, path, &mut |_, r| {
+                refs.insert(r.to_string());
+            });
+        }
+        let span = err.span;
+        self.conflicts.push((path.to_string(), err, refs));
+        SpannedValue {
+            span,
+            kind: ValueKind::Type(ValType::Nothing),
+        }
+    }
+
+    fn finish(self) -> Vec<Conflict> {
+        let mut graph: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (field, next) in &self.edges {
+            graph.entry(field).or_default().push(next);
+        }
+        let mut by_path: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (i, (path, _, _)) in self.conflicts.iter().enumerate() {
+            by_path.entry(path).or_default().push(i);
+        }
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        let refs = self.conflicts.iter().flat_map(|(_, _, refs)| refs);
+        for r in refs
+            .map(String::as_str)
+            .chain(graph.values().flatten().copied())
+        {
+            let id = ids.len();
+            ids.entry(r).or_insert(id);
+        }
+        let mut names = vec![""; ids.len()];
+        for (&r, &id) in &ids {
+            names[id] = r;
+        }
+        // A reference leads on to the references in the fields it points into.
+        let next: Vec<Vec<usize>> = names
+            .iter()
+            .map(|r| overlapping(&graph, r).into_iter().map(|n| ids[n]).collect())
+            .collect();
+        // The conflicts each reference depends on, as bit sets.
+        let words = self.conflicts.len().div_ceil(64);
+        let mut reach: Vec<Vec<u64>> = names
+            .iter()
+            .map(|r| {
+                let mut bits = vec![0; words];
+                for &j in overlapping(&by_path, r) {
+                    bits[j / 64] |= 1 << (j % 64);
+                }
+                bits
+            })
+            .collect();
+        let order = post_order(&next);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &r in &order {
+                let mut bits = std::mem::take(&mut reach[r]);
+                for &n in &next[r] {
+                    for (b, more) in bits.iter_mut().zip(&reach[n]) {
+                        if *b | more != *b {
+                            *b |= more;
+                            changed = true;
+                        }
+                    }
+                }
+                reach[r] = bits;
+            }
+        }
+        let deps: Vec<Vec<u64>> = self
+            .conflicts
+            .iter()
+            .map(|(_, _, refs)| {
+                let mut bits = vec![0; words];
+                for r in refs {
+                    for (w, b) in reach[ids[r.as_str()]].iter().enumerate() {
+                        bits[w] |= b;
+                    }
+                }
+                bits
+            })
+            .collect();
+        let depends_on = |i: usize, j: usize| deps[i][j / 64] & (1 << (j % 64)) != 0;
+        let marks: Vec<bool> = self
+            .conflicts
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _, _))| {
+                let repeated = overlapping(&by_path, path).into_iter().any(|&j| j < i);
+                // Two fields that depend on each other are both reported.
+                let downstream = (0..self.conflicts.len()).any(|j| {
+                    depends_on(i, j) && !overlaps(&self.conflicts[j].0, path) && !depends_on(j, i)
+                });
+                repeated || downstream
+            })
+            .collect();
+        self.conflicts
+            .into_iter()
+            .zip(marks)
+            .map(|((path, error, _), consequential)| Conflict {
+                path,
+                error,
+                consequential,
+            })
+            .collect()
+    }
+}
+
+/// The nodes of a graph given by each node's successors, each after the
+/// nodes it leads to unless they lie on a cycle with it.
+fn post_order(next: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut visited = vec![false; next.len()];
+    for start in 0..next.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some(&(node, i)) = stack.last() {
+            match next[node].get(i) {
+                Some(&n) => {
+                    stack.last_mut().unwrap().1 += 1;
+                    if !visited[n] {
+                        visited[n] = true;
+                        stack.push((n, 0));
+                    }
+                }
+                None => {
+                    order.push(node);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order
+}
+
+/// The path itself and each path it lies inside.
+fn within(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('.')
+        .map(move |(i, _)| &path[..i])
+        .chain(std::iter::once(path))
+}
+
+/// The items of `map` filed under paths that overlap `path`.
+fn overlapping<'a, T>(map: &'a BTreeMap<&str, Vec<T>>, path: &str) -> Vec<&'a T> {
+    let inside = format!("{}.", path);
+    let below = (
+        std::ops::Bound::Included(inside.as_str()),
+        std::ops::Bound::Unbounded,
+    );
+    within(path)
+        .filter_map(|p| map.get(p))
+        .chain(
+            map.range::<str, _>(below)
+                .take_while(|(k, _)| k.starts_with(&inside))
+                .map(|(_, v)| v),
+        )
+        .flatten()
+        .collect()
+}
+
+/// How much work unifying one tree may do before it stops with an error,
+/// so that recursive functions and runaway references fail instead of
+/// overflowing the stack or running forever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// How deeply function calls may nest.
+    pub max_depth: usize,
+    /// How deeply expressions, arrays and objects may nest in the tree
+    /// being unified.
+    pub max_nesting: usize,
+    /// How many values may be evaluated or unified in total.
+    pub max_steps: usize,
+    /// The largest value a function, concatenation or interpolation may
+    /// produce, counting each value and each byte of a string.
+    pub max_output: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            // A call nests several evaluation frames, some 20 KB of stack in
+            // a debug build, so 50 stays well within a 2 MB thread stack.
+            max_depth: 50,
+            // Each level of a value is some 12 KB of stack in a debug build.
+            max_nesting: 100,
+            max_steps: 1_000_000,
+            max_output: 10_000_000,
+        }
+    }
+}
+
+/// What unifying one tree runs with: the natives it can call, and its limits
+/// with the work done so far. Shared by every [`Eval`] of that tree.
+struct Env<'a> {
+    natives: &'a Natives,
+    limits: &'a Limits,
+    depth: Cell<usize>,
+    steps: Cell<usize>,
+}
+
+impl<'a> Env<'a> {
+    fn new(natives: &'a Natives, limits: &'a Limits) -> Self {
+        Env {
+            natives,
+            limits,
+            depth: Cell::new(0),
+            steps: Cell::new(0),
+        }
+    }
+
+    /// Counts one step of evaluation.
+    fn step(&self, path: &str, span: Span) -> Result<(), UnifyError> {
+        self.steps.set(self.steps.get() + 1);
+        if self.steps.get() > self.limits.max_steps {
+            return Err(limit_error(
+                ErrorCode::StepLimit,
+                path,
+                format!("evaluation took more than {} steps", self.limits.max_steps),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs `f` one level deeper, failing if that is too deep.
+    fn nested<T>(
+        &self,
+        path: &str,
+        span: Span,
+        f: impl FnOnce() -> Result<T, UnifyError>,
+    ) -> Result<T, UnifyError> {
+        if self.depth.get() >= self.limits.max_depth {
+            return Err(limit_error(
+                ErrorCode::DepthLimit,
+                path,
+                format!(
+                    "function calls nest more than {} deep",
+                    self.limits.max_depth
+                ),
+                span,
+            ));
+        }
+        self.depth.set(self.depth.get() + 1);
+        let result = f();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    /// Fails if a value of `size`, as counted by [`value_size`], is larger
+    /// than the output limit.
+    fn check_size(&self, size: usize, path: &str, span: Span) -> Result<(), UnifyError> {
+        if size > self.limits.max_output {
+            return Err(limit_error(
+                ErrorCode::SizeLimit,
+                path,
+                format!("value is larger than {}", self.limits.max_output),
+                span,
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn limit_error(code: ErrorCode, path: &str, msg: String, span: Span) -> UnifyError {
+    UnifyError {
+        code,
+        msg: add_path(path, msg),
+        span,
+        prev_span: span,
+    }
+}
+
+/// Fails with the field that nests deeper than `limits` allow. Each pass
+/// over the tree recurses once per level, so this runs first and walks it
+/// with its own stack.
+fn check_nesting(value: &SpannedValue, limits: &Limits) -> Result<(), Conflict> {
+    let mut todo = vec![(value, 0, String::new())];
+    while let Some((v, depth, path)) = todo.pop() {
+        if depth > limits.max_nesting {
+            let error = limit_error(
+                ErrorCode::DepthLimit,
+                &path,
+                format!("values nest more than {} deep", limits.max_nesting),
+                v.span,
+            );
+            return Err(Conflict {
+                path,
+                error,
+                consequential: false,
+            });
+        }
+        match &v.kind {
+            ValueKind::Array(items) => todo.extend(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| (item, depth + 1, format!("{}[{}]", path, i))),
+            ),
+            ValueKind::Object(members) => todo.extend(members.iter().map(|(k, v, _, _)| {
+                let path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                (v, depth + 1, path)
+            })),
+            ValueKind::List(list) => {
+                todo.extend(list.values().map(|item| (item, depth + 1, path.clone())))
+            }
+            ValueKind::Union(items)
+            | ValueKind::Interpolation(items)
+            | ValueKind::Arguments(items) => {
+                todo.extend(items.iter().map(|item| (item, depth + 1, path.clone())))
+            }
+            ValueKind::Call(_, arg) | ValueKind::Default(arg) => todo.push((arg, depth + 1, path)),
+            ValueKind::OpCall(_, left, right) => {
+                todo.push((left, depth + 1, path.clone()));
+                todo.push((right, depth + 1, path));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The number of values in `value` plus the bytes of its strings, counted
+/// until the total is over `max`.
+pub(crate) fn value_size(value: &Value, max: usize) -> usize {
+    let mut size = 0;
+    let mut todo = vec![value];
+    while let Some(v) = todo.pop() {
+        size += 1;
+        match v {
+            Value::String(s) => size += s.len(),
+            Value::Array(items)
+            | Value::Union(items)
+            | Value::Interpolation(items)
+            | Value::Arguments(items) => todo.extend(items),
+            Value::List(list) => todo.extend(list.values()),
+            Value::Object(members) => todo.extend(members.iter().map(|(_, v)| v)),
+            Value::Call(_, arg) | Value::Default(arg) => todo.push(arg),
+            Value::OpCall(_, left, right) => todo.extend([&**left, &**right]),
+            _ => {}
+        }
+        if size > max {
+            break;
+        }
+    }
+    size
+}
+
+/// The state of one evaluation: its [`Env`], and the references being
+/// followed, to detect cycles.
+struct Eval<'a> {
+    env: &'a Env<'a>,
+    seen: HashSet<String>,
+    /// References already resolved against the current root, so that each
+    /// is resolved once however many fields refer to it. A failure is kept
+    /// too, with the path it was first reported at.
+    resolved: HashMap<String, Result<SpannedValue, UnifyError>>,
+    /// How many times a cycle cut resolution short. A value resolved while
+    /// this changed depends on what was being resolved, so it is not kept.
+    cycles: usize,
+    /// The functions whose bodies are being evaluated, as written. Inside a
+    /// body the function's name is bound to a copy whose `arg` is the
+    /// argument, so a recursive call finds the original spec here.
+    calling: Vec<(String, SpannedValue)>,
+}
+
+impl<'a> Eval<'a> {
+    fn new(env: &'a Env<'a>) -> Self {
+        Eval {
+            env,
+            seen: HashSet::new(),
+            resolved: HashMap::new(),
+            cycles: 0,
+            calling: Vec::new(),
+        }
+    }
+
+    /// The definition a call to `name` runs: the innermost function of that
+    /// name being evaluated, otherwise the field in `root`.
+    fn definition(
+        &self,
+        name: &str,
+        root: &BTreeMap<String, SpannedValue>,
+    ) -> Option<SpannedValue> {
+        self.calling
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, def)| def)
+            .or_else(|| root.get(name))
+            .cloned()
+    }
+
+    /// Runs `f` remembering no resolved references, for a function body
+    /// whose `arg` differs from call to call.
+    fn isolated<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = std::mem::take(&mut self.resolved);
+        let result = f(self);
+        self.resolved = outer;
+        result
+    }
+
+    /// Follows the reference `value` through fields that are themselves
+    /// references, marking each as seen, and returns their names with the
+    /// last reference. A loop rather than recursion, so that long chains of
+    /// aliases do not grow the stack. The chain ends at a name with several
+    /// definitions, which the caller unifies with each other; the loader
+    /// rejects an import alias that would be one of them.
+    fn follow_aliases<'v>(
+        &mut self,
+        mut value: &'v SpannedValue,
+        path: &str,
+        root: &'v BTreeMap<String, SpannedValue>,
+    ) -> Result<(Vec<String>, &'v SpannedValue), UnifyError> {
+        let mut followed = Vec::new();
+        while let ValueKind::Reference(name) = &value.kind {
+            let [next] = lookup_all(root, name)[..] else {
+                break;
+            };
+            if !matches!(next.kind, ValueKind::Reference(_))
+                || self.seen.contains(name)
+                || self.resolved.contains_key(name)
+            {
+                break;
+            }
+            if let Err(err) = self.env.step(path, next.span) {
+                self.forget(followed);
+                return Err(err);
+            }
+            self.seen.insert(name.clone());
+            followed.push(name.clone());
+            value = next;
+        }
+        Ok((followed, value))
+    }
+
+    fn forget(&mut self, names: Vec<String>) {
+        for name in names {
+            self.seen.remove(&name);
+        }
+    }
+}
+
+/// Whether one path is the other or lies inside it.
+fn overlaps(a: &str, b: &str) -> bool {
+    let inside = |a: &str, b: &str| a.strip_prefix(b).is_some_and(|rest| rest.starts_with('.'));
+    a == b || inside(a, b) || inside(b, a)
+}
+
+/// Calls `f` with each reference in `value` and the path of the field it
+/// appears in.
+fn collect_refs(value: &SpannedValue, path: &str, f: &mut impl FnMut(&str, &str)) {
+    match &value.kind {
+        ValueKind::Reference(r) => f(path, r),
+        ValueKind::Array(items)
+        | ValueKind::Union(items)
+        | ValueKind::Interpolation(items)
+        | ValueKind::Arguments(items) => {
+            for item in items {
+                collect_refs(item, path, f);
+            }
+        }
+        ValueKind::List(list) => {
+            for item in list.values() {
+                collect_refs(item, path, f);
+            }
+        }
+        ValueKind::Call(_, arg) | ValueKind::Default(arg) => collect_refs(arg, path, f),
+        ValueKind::OpCall(_, left, right) => {
+            collect_refs(left, path, f);
+            collect_refs(right, path, f);
+        }
+        ValueKind::Object(members) => {
+            for (k, v, _, _) in members {
+                let new_path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                collect_refs(v, &new_path, f);
+            }
+        }
+        _ => {}
+    }
+}
+
 fn branch_matches(
     branch: &SpannedValue,
     value: &SpannedValue,
@@ -41,7 +569,162 @@ fn type_name(t: &ValType) -> &'static str {
         ValType::Float => "Float",
         ValType::String => "String",
         ValType::Boolean => "Boolean",
+        ValType::Range(_) => "a range",
+        ValType::Text(_) => "a string constraint",
+    }
+}
+
+/// Orders the number types from the narrowest, `Int`, to `Number`.
+fn number_rank(t: &ValType) -> Option<u8> {
+    match t {
+        ValType::Int => Some(0),
+        ValType::Rational => Some(1),
+        ValType::Float => Some(2),
+        ValType::Number => Some(3),
+        _ => None,
+    }
+}
+
+/// A number type as a range without bounds.
+fn as_range(t: &ValType) -> Option<Range> {
+    match t {
+        ValType::Range(range) => Some((**range).clone()),
+        t if number_rank(t).is_some() => Some(Range {
+            number: Box::new(t.clone()),
+            min: None,
+            max: None,
+        }),
+        _ => None,
+    }
+}
+
+/// The numbers in both ranges, of the narrower number type, or an error
+/// naming the bounds if there are none.
+fn intersect_ranges(a: &Range, b: &Range) -> Result<ValType, String> {
+    let number = if number_rank(&a.number) <= number_rank(&b.number) {
+        a.number.clone()
+    } else {
+        b.number.clone()
+    };
+    // The tighter of two bounds; `wins` says whether `x` is tighter than `y`.
+    let tighter = |x: &Option<Bound>, y: &Option<Bound>, wins: std::cmp::Ordering| match (x, y) {
+        (Some(x), Some(y)) => Some(match x.value.cmp(&y.value) {
+            std::cmp::Ordering::Equal => Bound {
+                value: x.value.clone(),
+                inclusive: x.inclusive && y.inclusive,
+            },
+            o if o == wins => x.clone(),
+            _ => y.clone(),
+        }),
+        (x, y) => x.clone().or_else(|| y.clone()),
+    };
+    let range = Range {
+        number,
+        min: tighter(&a.min, &b.min, std::cmp::Ordering::Greater),
+        max: tighter(&a.max, &b.max, std::cmp::Ordering::Less),
+    };
+    if let (Some(min), Some(max)) = (&range.min, &range.max) {
+        let is_int = *range.number == ValType::Int;
+        let empty = if is_int {
+            let lo = match min.inclusive {
+                true => min.value.ceil(),
+                false => min.value.floor() + BigInt::from(1),
+            };
+            let hi = match max.inclusive {
+                true => max.value.floor(),
+                false => max.value.ceil() - BigInt::from(1),
+            };
+            lo > hi
+        } else {
+            min.value > max.value || (min.value == max.value && !(min.inclusive && max.inclusive))
+        };
+        if empty {
+            return Err(format!(
+                "no {} is {} and {}",
+                if is_int { "integer" } else { "number" },
+                Range::bound(min, true),
+                Range::bound(max, false)
+            ));
+        }
+    }
+    Ok(ValType::Range(Box::new(range)))
+}
+
+/// `String` as a string constraint without bounds or patterns.
+fn as_text(t: &ValType) -> Option<Text> {
+    match t {
+        ValType::Text(text) => Some((**text).clone()),
+        ValType::String => Some(Text {
+            min: 0,
+            max: None,
+            patterns: Vec::new(),
+        }),
+        _ => None,
+    }
+}
+
+/// The strings both constraints allow, or an error if their lengths do not
+/// overlap.
+fn intersect_texts(a: &Text, b: &Text) -> Result<ValType, String> {
+    let mut text = Text {
+        min: a.min.max(b.min),
+        max: match (a.max, b.max) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        },
+        patterns: a.patterns.clone(),
+    };
+    for pattern in &b.patterns {
+        if !text.patterns.contains(pattern) {
+            text.patterns.push(pattern.clone());
+        }
+    }
+    match text.max {
+        Some(max) if max < text.min => Err(format!(
+            "no string has at least {} and at most {} characters",
+            text.min, max
+        )),
+        _ => Ok(ValType::Text(Box::new(text))),
+    }
+}
+
+/// Checks the string `s` against the length and patterns of `text`.
+fn check_text(text: &Text, s: &str) -> Result<(), String> {
+    let got = || serde_json::to_string(s).unwrap();
+    let len = s.chars().count();
+    if len < text.min || text.max.is_some_and(|max| len > max) {
+        return Err(format!("expected {}, got {}", text.length(), got()));
+    }
+    for pattern in &text.patterns {
+        let regex = regex::Regex::new(&pattern.regex).map_err(|e| e.to_string())?;
+        if regex.is_match(s) == pattern.negated {
+            return Err(format!("expected {}, got {}", pattern, got()));
+        }
     }
+    Ok(())
+}
+
+/// Checks the number `val` against the bounds of `range`.
+fn check_bounds(range: &Range, val: &Value) -> Result<(), String> {
+    let ordering = |bound: &Bound| match val {
+        Value::Int(n) => Some(BigRational::from_integer((*n).into()).cmp(&bound.value)),
+        Value::Rational(n) | Value::Decimal(n) => Some(n.cmp(&bound.value)),
+        Value::Float(n) => n.partial_cmp(&bound.value.to_f64()?),
+        _ => None,
+    };
+    let fits = |bound: &Bound, past: std::cmp::Ordering| {
+        ordering(bound).is_some_and(|o| o == past || (bound.inclusive && o.is_eq()))
+    };
+    let violated = match (&range.min, &range.max) {
+        (Some(min), _) if !fits(min, std::cmp::Ordering::Greater) => Range::bound(min, true),
+        (_, Some(max)) if !fits(max, std::cmp::Ordering::Less) => Range::bound(max, false),
+        _ => return Ok(()),
+    };
+    Err(format!(
+        "expected {}, got {}",
+        violated,
+        display(val).unwrap_or_default()
+    ))
 }
 
 fn unify_types(a: &ValType, b: &ValType) -> Result<ValType, String> {
@@ -57,14 +740,19 @@ fn unify_types(a: &ValType, b: &ValType) -> Result<ValType, String> {
     if matches!(a, ValType::Nothing) || matches!(b, ValType::Nothing) {
         return Err("cannot unify Nothing".into());
     }
-    let rank = |t: &ValType| match t {
-        ValType::Int => Some(0),
-        ValType::Rational => Some(1),
-        ValType::Float => Some(2),
-        ValType::Number => Some(3),
-        _ => None,
-    };
-    match (rank(a), rank(b)) {
+    if matches!(a, ValType::Text(_)) || matches!(b, ValType::Text(_)) {
+        return match (as_text(a), as_text(b)) {
+            (Some(a), Some(b)) => intersect_texts(&a, &b),
+            _ => Err(format!("{} cannot be unified with {}", a, b)),
+        };
+    }
+    if matches!(a, ValType::Range(_)) || matches!(b, ValType::Range(_)) {
+        return match (as_range(a), as_range(b)) {
+            (Some(a), Some(b)) => intersect_ranges(&a, &b),
+            _ => Err(format!("{} cannot be unified with {}", a, b)),
+        };
+    }
+    match (number_rank(a), number_rank(b)) {
         (Some(ra), Some(rb)) => {
             let r = ra.max(rb);
             Ok(match r {
@@ -74,11 +762,7 @@ fn unify_types(a: &ValType, b: &ValType) -> Result<ValType, String> {
                 _ => ValType::Number,
             })
         }
-        _ => Err(format!(
-            "{} cannot be unified with {}",
-            type_name(a),
-            type_name(b)
-        )),
+        _ => Err(format!("{} cannot be unified with {}", a, b)),
     }
 }
 
@@ -88,13 +772,34 @@ fn unify_type_value(t: &ValType, val: &Value) -> Result<Value, String> {
         ValType::Nothing => Err("cannot unify Nothing".into()),
         ValType::Int => match val {
             Value::Int(n) => Ok(Value::Int(*n)),
-            Value::Float(n) if n.fract() == 0.0 => Ok(Value::Int(*n as i64)),
+            Value::Rational(n) if n.is_integer() => Ok(Value::Rational(n.clone())),
+            Value::Decimal(n) if n.is_integer() => Ok(exact_value(n.clone())),
+            Value::Float(n) if n.fract() == 0.0 => {
+                Ok(exact_value(BigRational::from_float(*n).unwrap()))
+            }
             Value::Type(other) => unify_types(t, other).map(Value::Type),
             _ => Err("expected integer".into()),
         },
-        ValType::Rational | ValType::Float | ValType::Number => match val {
+        ValType::Rational => match val {
+            Value::Int(n) => Ok(Value::Int(*n)),
+            Value::Rational(n) => Ok(Value::Rational(n.clone())),
+            Value::Decimal(n) => Ok(exact_value(n.clone())),
+            // A computed float is read as the binary fraction it holds.
+            Value::Float(n) => BigRational::from_float(*n)
+                .map(exact_value)
+                .ok_or_else(|| format!("{} is not a rational", n)),
+            Value::Type(other) => unify_types(t, other).map(Value::Type),
+            _ => Err("expected rational".into()),
+        },
+        ValType::Float | ValType::Number => match val {
             Value::Int(n) => Ok(Value::Int(*n)),
             Value::Float(n) => Ok(Value::Float(*n)),
+            // A fraction read as a `Float` becomes the float nearest it.
+            Value::Rational(n) if matches!(t, ValType::Float) && !n.is_integer() => {
+                Ok(Value::Decimal(n.clone()))
+            }
+            Value::Rational(n) => Ok(Value::Rational(n.clone())),
+            Value::Decimal(n) => Ok(Value::Decimal(n.clone())),
             Value::Type(other) => unify_types(t, other).map(Value::Type),
             _ => Err("expected number".into()),
         },
@@ -108,6 +813,37 @@ fn unify_type_value(t: &ValType, val: &Value) -> Result<Value, String> {
             Value::Type(other) => unify_types(t, other).map(Value::Type),
             _ => Err("expected boolean".into()),
         },
+        ValType::Text(text) => match val {
+            Value::Type(other) => unify_types(t, other).map(Value::Type),
+            _ => {
+                let val = unify_type_value(&ValType::String, val)?;
+                if let Value::String(s) = &val {
+                    check_text(text, s)?;
+                }
+                Ok(val)
+            }
+        },
+        ValType::Range(range) => match val {
+            Value::Type(other) => unify_types(t, other).map(Value::Type),
+            _ => {
+                let val = unify_type_value(&range.number, val)?;
+                check_bounds(range, &val)?;
+                Ok(val)
+            }
+        },
+    }
+}
+
+fn exact_value(n: BigRational) -> Value {
+    kind_to_value(&ValueKind::exact(n))
+}
+
+/// The value of an `Int` or `Rational`.
+fn exact(kind: &ValueKind) -> Option<BigRational> {
+    match kind {
+        ValueKind::Int(n) => Some(BigRational::from_integer((*n).into())),
+        ValueKind::Rational(n) => Some(n.clone()),
+        _ => None,
     }
 }
 
@@ -116,89 +852,247 @@ fn execute_call(
     arg: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
+    eval: &mut Eval,
     span: Span,
 ) -> Result<SpannedValue, UnifyError> {
-    let resolved = resolve_refs_inner(arg, path, root, seen)?;
+    let resolved = resolve_refs_inner(arg, path, root, eval)?;
+    let keep = |arg: SpannedValue| {
+        Ok(SpannedValue {
+            span,
+            kind: ValueKind::Call(name.to_string(), Box::new(arg)),
+        })
+    };
     match name {
-        "native" => match &resolved.kind {
-            ValueKind::Array(items) if items.len() == 2 => {
-                let func = &items[0];
-                let func_arg = &items[1];
-                if let ValueKind::String(f) = &func.kind {
-                    let arg_val = resolve_refs_inner(func_arg, path, root, seen)?;
-                    match f.as_str() {
-                        "increment" => match arg_val.kind {
-                            ValueKind::Int(n) => Ok(SpannedValue {
-                                span,
-                                kind: ValueKind::Int(n + 1),
-                            }),
-                            other => Ok(SpannedValue {
-                                span,
-                                kind: ValueKind::Call(
-                                    name.to_string(),
-                                    Box::new(SpannedValue {
-                                        span: resolved.span,
-                                        kind: ValueKind::Array(vec![
-                                            func.clone(),
-                                            SpannedValue {
-                                                span: arg_val.span,
-                                                kind: other,
-                                            },
-                                        ]),
-                                    }),
-                                ),
-                            }),
-                        },
-                        _ => Err(UnifyError {
-                            msg: add_path(path, format!("unknown native function {}", f)),
-                            span,
-                            prev_span: span,
-                        }),
-                    }
-                } else {
-                    Ok(SpannedValue {
-                        span,
-                        kind: ValueKind::Call(name.to_string(), Box::new(resolved.clone())),
-                    })
-                }
+        "native" => {
+            let ValueKind::Array(items) = &resolved.kind else {
+                return keep(resolved);
+            };
+            let [func, func_arg] = items.as_slice() else {
+                return keep(resolved);
+            };
+            let ValueKind::String(f) = &func.kind else {
+                return keep(resolved);
+            };
+            let Some(native) = eval.env.natives.get(f) else {
+                return Err(UnifyError {
+                    code: ErrorCode::UnknownFunction,
+                    msg: add_path(path, format!("unknown native function {}", f)),
+                    span,
+                    prev_span: span,
+                });
+            };
+            match call_native(f, native, func_arg, path, root, eval, span)? {
+                Some(result) => Ok(result),
+                None => keep(resolved),
             }
-            _ => Ok(SpannedValue {
+        }
+        stdlib::FILTER_WHERE => {
+            match call_native(
+                "filter",
+                &stdlib::filter_where(),
+                &resolved,
+                path,
+                root,
+                eval,
                 span,
-                kind: ValueKind::Call(name.to_string(), Box::new(resolved.clone())),
-            }),
-        },
-        other => match root.get(other) {
-            Some(func_def) => {
-                execute_user_function(other, func_def, &resolved, path, root, seen, span)
+            )? {
+                Some(result) => Ok(result),
+                None => keep(resolved),
             }
-            None => Err(UnifyError {
-                msg: add_path(path, format!("unknown function {}", name)),
+        }
+        // Like a native, `close` gives way to a function of that name.
+        "close" if eval.definition(name, root).is_none() => match resolved.kind {
+            ValueKind::Object(members) if !members.is_empty() => Ok(SpannedValue {
+                span,
+                kind: ValueKind::Object(
+                    members
+                        .into_iter()
+                        .map(|(k, v, span, mut anns)| {
+                            if !anns.contains(&Annotation::Closed) {
+                                anns.push(Annotation::Closed);
+                            }
+                            (k, v, span, anns)
+                        })
+                        .collect(),
+                ),
+            }),
+            ValueKind::Reference(_) | ValueKind::Call(..) | ValueKind::OpCall(..) => keep(resolved),
+            ref kind => Err(UnifyError {
+                code: ErrorCode::Arguments,
+                msg: add_path(
+                    path,
+                    format!(
+                        "close expects an object with at least one member, got {}",
+                        match kind {
+                            ValueKind::Object(_) => "an empty object",
+                            kind => kind_name(kind),
+                        }
+                    ),
+                ),
                 span,
                 prev_span: span,
             }),
         },
+        other => match eval.definition(other, root) {
+            Some(func_def) => {
+                eval.calling.push((other.to_string(), func_def.clone()));
+                let result =
+                    execute_user_function(other, &func_def, &resolved, path, root, eval, span);
+                eval.calling.pop();
+                result
+            }
+            // A native can be called by name unless a field shadows it.
+            None => match eval.env.natives.get(other) {
+                Some(native) => {
+                    match call_native(other, native, &resolved, path, root, eval, span)? {
+                        Some(result) => Ok(result),
+                        None => keep(resolved),
+                    }
+                }
+                None => Err(UnifyError {
+                    code: ErrorCode::UnknownFunction,
+                    msg: add_path(path, format!("unknown function {}", name)),
+                    span,
+                    prev_span: span,
+                }),
+            },
+        },
+    }
+}
+
+/// Calls `native` with `arg` once it unifies with the declared shape, and
+/// evaluates the result. Returns `None` if the native waits for a more
+/// concrete argument.
+fn call_native(
+    name: &str,
+    native: &dyn NativeFunction,
+    arg: &SpannedValue,
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
+    span: Span,
+) -> Result<Option<SpannedValue>, UnifyError> {
+    let spec = spanned(native.arg(), span);
+    let arg = bind_arguments(name, Some(&spec), None, arg, path, span)?;
+    let mut arg = unify_with(&spec, &arg, path, root, eval.env)?;
+    float_decimals(&mut arg);
+    let arg = arg.to_value();
+    if let Some(size) = native.result_size(&arg) {
+        eval.env.check_size(size, path, span)?;
+    }
+    match native.call(&arg) {
+        Ok(Some(result)) => {
+            let size = value_size(&result, eval.env.limits.max_output);
+            eval.env.check_size(size, path, span)?;
+            resolve_refs_inner(&spanned(result, span), path, root, eval).map(Some)
+        }
+        Ok(None) => Ok(None),
+        Err(msg) => Err(UnifyError {
+            code: ErrorCode::Native,
+            msg: add_path(path, format!("{}: {}", name, msg)),
+            span,
+            prev_span: span,
+        }),
     }
 }
 
+/// Matches a call's argument to the members of `spec`, the function's
+/// `arg`. Positional arguments `f(a, b)` are given to those members in
+/// order, and `defaults` fills in members not passed. Passing a member the
+/// function does not have, or leaving out one with no concrete value, is an
+/// error at the call.
+fn bind_arguments(
+    name: &str,
+    spec: Option<&SpannedValue>,
+    defaults: Option<&SpannedValue>,
+    arg: &SpannedValue,
+    path: &str,
+    span: Span,
+) -> Result<SpannedValue, UnifyError> {
+    let error = |msg: String, at: Span| UnifyError {
+        code: ErrorCode::Arguments,
+        msg: add_path(path, format!("{}: {}", name, msg)),
+        span: at,
+        prev_span: at,
+    };
+    let Some(ValueKind::Object(params)) = spec.map(|s| &s.kind) else {
+        return match &arg.kind {
+            ValueKind::Arguments(items) if items.len() != 1 => Err(error(
+                format!("expected 1 argument, got {}", items.len()),
+                arg.span,
+            )),
+            ValueKind::Arguments(items) => Ok(items[0].clone()),
+            _ => Ok(arg.clone()),
+        };
+    };
+    let mut bound = match &arg.kind {
+        ValueKind::Arguments(items) => {
+            if let Some(extra) = items.get(params.len()) {
+                return Err(error(
+                    format!(
+                        "expected at most {} arguments, got {}",
+                        params.len(),
+                        items.len()
+                    ),
+                    extra.span,
+                ));
+            }
+            params
+                .iter()
+                .zip(items)
+                .map(|((k, _, _, _), v)| (k.clone(), v.clone(), v.span, Vec::new()))
+                .collect()
+        }
+        ValueKind::Object(members) => members.clone(),
+        _ => return Ok(arg.clone()),
+    };
+    for (k, v, _, _) in &bound {
+        if !params.iter().any(|(p, _, _, _)| p == k) {
+            return Err(error(format!("unknown argument {}", k), v.span));
+        }
+    }
+    if let Some(ValueKind::Object(defaults)) = defaults.map(|d| &d.kind) {
+        for member in defaults {
+            if !bound.iter().any(|(k, _, _, _)| *k == member.0) {
+                bound.push(member.clone());
+            }
+        }
+    }
+    for (p, v, _, _) in params {
+        if !bound.iter().any(|(k, _, _, _)| k == p) && find_unresolved(v).is_some() {
+            return Err(error(format!("missing argument {}", p), span));
+        }
+    }
+    Ok(SpannedValue {
+        span: arg.span,
+        kind: ValueKind::Object(bound),
+    })
+}
+
 fn execute_user_function(
     name: &str,
     func_def: &SpannedValue,
     arg: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
+    eval: &mut Eval,
     span: Span,
 ) -> Result<SpannedValue, UnifyError> {
-    let resolved_arg = arg.clone();
     let mut arg_spec: Option<SpannedValue> = None;
+    let mut defaults: Option<SpannedValue> = None;
     let mut return_vals: Vec<SpannedValue> = Vec::new();
     if let ValueKind::Object(members) = &func_def.kind {
         for (k, v, _, _) in members {
             if k == "arg" {
                 arg_spec = match arg_spec {
                     None => Some(v.clone()),
-                    Some(ref current) => Some(unify_spanned(current, v, path, root)?),
+                    Some(ref current) => Some(unify_with(current, v, path, root, eval.env)?),
+                };
+            }
+            if k == "default" {
+                defaults = match defaults {
+                    None => Some(v.clone()),
+                    Some(ref current) => Some(unify_with(current, v, path, root, eval.env)?),
                 };
             }
             if k == "return" {
@@ -207,99 +1101,295 @@ fn execute_user_function(
         }
     } else {
         return Err(UnifyError {
+            code: ErrorCode::NotAFunction,
             msg: add_path(path, format!("{} is not a function", name)),
             span,
             prev_span: span,
         });
     }
 
+    let resolved_arg = bind_arguments(name, arg_spec.as_ref(), defaults.as_ref(), arg, path, span)?;
     if let Some(spec) = &arg_spec {
-        let _ = unify_spanned(spec, &resolved_arg, path, root)?;
+        let _ = unify_with(spec, &resolved_arg, path, root, eval.env)?;
     }
 
     let mut extended = root.clone();
     if let ValueKind::Object(_) = &func_def.kind {
         let mut func_clone = func_def.clone();
-        if let ValueKind::Object(ref mut ms) = func_clone.kind {
-            if let Some((_, v, _, _)) = ms.iter_mut().find(|(k, _, _, _)| k == "arg") {
-                *v = resolved_arg.clone();
-            }
+        if let ValueKind::Object(ref mut ms) = func_clone.kind
+            && let Some((_, v, _, _)) = ms.iter_mut().find(|(k, _, _, _)| k == "arg")
+        {
+            *v = resolved_arg.clone();
         }
         extended.insert(name.to_string(), func_clone);
     }
 
     let mut result: Option<SpannedValue> = None;
+    let env = eval.env;
     for ret in return_vals {
-        let evaluated = resolve_refs_inner(&ret, path, &extended, seen)?;
+        let evaluated = env.nested(path, span, || {
+            eval.isolated(|eval| resolve_refs_inner(&ret, path, &extended, eval))
+        })?;
         result = match result {
             None => Some(evaluated),
-            Some(ref current) => Some(unify_spanned(current, &evaluated, path, &extended)?),
+            Some(ref current) => Some(unify_with(current, &evaluated, path, &extended, eval.env)?),
         };
     }
     result.ok_or(UnifyError {
+        code: ErrorCode::MissingReturn,
         msg: add_path(path, format!("{} has no return", name)),
         span,
         prev_span: span,
     })
 }
 
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "//", "%", "==", "!=", "<", "<=", ">", ">=",
+];
+
 fn execute_operator(
     op: &str,
     left: &SpannedValue,
     right: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
+    eval: &mut Eval,
     span: Span,
 ) -> Result<SpannedValue, UnifyError> {
-    let l = resolve_refs_inner(left, path, root, seen)?;
-    let r = resolve_refs_inner(right, path, root, seen)?;
-    match op {
-        "+" => match (l.kind, r.kind) {
-            (ValueKind::Int(a), ValueKind::Int(b)) => Ok(SpannedValue {
-                span,
-                kind: ValueKind::Int(a + b),
-            }),
-            (lk, rk) => Ok(SpannedValue {
-                span,
-                kind: ValueKind::OpCall(
-                    op.to_string(),
-                    Box::new(SpannedValue {
-                        span: l.span,
-                        kind: lk,
-                    }),
-                    Box::new(SpannedValue {
-                        span: r.span,
-                        kind: rk,
-                    }),
-                ),
-            }),
-        },
-        "-" => match (l.kind, r.kind) {
-            (ValueKind::Int(a), ValueKind::Int(b)) => Ok(SpannedValue {
-                span,
-                kind: ValueKind::Int(a - b),
-            }),
-            (lk, rk) => Ok(SpannedValue {
-                span,
-                kind: ValueKind::OpCall(
-                    op.to_string(),
-                    Box::new(SpannedValue {
-                        span: l.span,
-                        kind: lk,
-                    }),
-                    Box::new(SpannedValue {
-                        span: r.span,
-                        kind: rk,
-                    }),
-                ),
-            }),
-        },
-        _ => Err(UnifyError {
-            msg: add_path(path, format!("unknown operator {}", op)),
+    // `a & b` is the value both operands unify to.
+    if op == "&" {
+        return unify_spanned_inner(left, right, path, root, eval);
+    }
+    let l = resolve_refs_inner(left, path, root, eval)?;
+    let r = resolve_refs_inner(right, path, root, eval)?;
+    let error = |code, msg: String| UnifyError {
+        code,
+        msg: add_path(path, msg),
+        span,
+        prev_span: span,
+    };
+    if !OPERATORS.contains(&op) {
+        return Err(error(
+            ErrorCode::UnknownOperator,
+            format!("unknown operator {}", op),
+        ));
+    }
+    // Anything not yet concrete waits.
+    if find_unresolved(&l).is_some() || find_unresolved(&r).is_some() {
+        return Ok(SpannedValue {
             span,
-            prev_span: span,
+            kind: ValueKind::OpCall(op.to_string(), Box::new(l), Box::new(r)),
+        });
+    }
+    let kind = match (number(&l.kind), number(&r.kind)) {
+        (Some(a), Some(b)) => arithmetic(op, a, b).map_err(|(code, msg)| error(code, msg))?,
+        _ => operate(op, &l.kind, &r.kind),
+    };
+    if let Some(ValueKind::String(s)) = &kind {
+        eval.env.check_size(s.len(), path, span)?;
+    }
+    kind.map(|kind| SpannedValue { span, kind }).ok_or_else(|| {
+        error(
+            ErrorCode::TypeMismatch,
+            format!(
+                "cannot apply {} to {} and {}",
+                op,
+                kind_name(&l.kind),
+                kind_name(&r.kind)
+            ),
+        )
+    })
+}
+
+/// A concrete operand of an arithmetic operator.
+enum Number {
+    Exact(BigRational),
+    Decimal(BigRational),
+    Float(f64),
+}
+
+fn number(kind: &ValueKind) -> Option<Number> {
+    match kind {
+        ValueKind::Float(n) => Some(Number::Float(*n)),
+        // A decimal past the range of a float, like `1e999`, counts as the
+        // infinity it stands for.
+        ValueKind::Decimal(n) => Some(match to_float(n) {
+            f if f.is_finite() => Number::Decimal(n.clone()),
+            f => Number::Float(f),
         }),
+        other => exact(other).map(Number::Exact),
+    }
+}
+
+impl Number {
+    fn exact(&self) -> Option<&BigRational> {
+        match self {
+            Number::Exact(n) | Number::Decimal(n) => Some(n),
+            Number::Float(_) => None,
+        }
+    }
+
+    fn to_f64(&self) -> Result<f64, (ErrorCode, String)> {
+        match self {
+            Number::Float(n) => Ok(*n),
+            Number::Decimal(n) => Ok(to_float(n)),
+            Number::Exact(n) => n.to_f64().filter(|f| f.is_finite()).ok_or_else(|| {
+                (
+                    ErrorCode::Overflow,
+                    format!("{} is too large for a float", n),
+                )
+            }),
+        }
+    }
+}
+
+/// Applies `op` to two numbers. Integers, rationals and decimals are
+/// computed exactly, and a decimal operand makes the result a decimal that
+/// must stay within the range of a float; if either side is a float, so is
+/// the result. `//` rounds the quotient down and `%` takes the sign of the
+/// divisor. Returns `None` for an operator that does not apply to numbers.
+fn arithmetic(op: &str, a: Number, b: Number) -> Result<Option<ValueKind>, (ErrorCode, String)> {
+    let divides = matches!(op, "/" | "//" | "%");
+    let division_by_zero = || (ErrorCode::DivisionByZero, "division by zero".to_string());
+    let overflow = || {
+        (
+            ErrorCode::Overflow,
+            format!("result of {} is too large for a float", op),
+        )
+    };
+    if let (Some(x), Some(y)) = (a.exact(), b.exact()) {
+        if divides && y.is_zero() {
+            return Err(division_by_zero());
+        }
+        let n = match op {
+            "+" => x + y,
+            "-" => x - y,
+            "*" => x * y,
+            "/" => x / y,
+            "//" => (x / y).floor(),
+            "%" => x - y * (x / y).floor(),
+            _ => return Ok(compare(op, Some(x.cmp(y))).map(ValueKind::Bool)),
+        };
+        if let (Number::Exact(_), Number::Exact(_)) = (&a, &b) {
+            return Ok(Some(ValueKind::exact(n)));
+        }
+        if to_float(&n).is_infinite() {
+            return Err(overflow());
+        }
+        return Ok(Some(ValueKind::Decimal(n)));
+    }
+    let (x, y) = (a.to_f64()?, b.to_f64()?);
+    if divides && y == 0.0 {
+        return Err(division_by_zero());
+    }
+    let n = match op {
+        "+" => x + y,
+        "-" => x - y,
+        "*" => x * y,
+        "/" => x / y,
+        "//" => (x / y).floor(),
+        "%" => x - y * (x / y).floor(),
+        _ => return Ok(compare(op, x.partial_cmp(&y)).map(ValueKind::Bool)),
+    };
+    if !n.is_finite() && x.is_finite() && y.is_finite() {
+        return Err(overflow());
+    }
+    Ok(Some(ValueKind::Float(n)))
+}
+
+/// Applies `op` to values other than two numbers. Strings can be
+/// concatenated with `+` and are ordered; other values can only be tested
+/// for equality.
+fn operate(op: &str, a: &ValueKind, b: &ValueKind) -> Option<ValueKind> {
+    let ordering = match (a, b) {
+        (ValueKind::String(a), ValueKind::String(b)) if op == "+" => {
+            return Some(ValueKind::String(format!("{}{}", a, b)));
+        }
+        (ValueKind::String(a), ValueKind::String(b)) => Some(a.cmp(b)),
+        _ if matches!(op, "==" | "!=") => {
+            (kind_to_value(a) == kind_to_value(b)).then_some(std::cmp::Ordering::Equal)
+        }
+        _ => return None,
+    };
+    compare(op, ordering).map(ValueKind::Bool)
+}
+
+/// Whether two values ordered as `ordering` satisfy the comparison `op`.
+/// `None` means the values are unordered, as with NaN.
+fn compare(op: &str, ordering: Option<std::cmp::Ordering>) -> Option<bool> {
+    use std::cmp::Ordering::*;
+    Some(match op {
+        "==" => ordering == Some(Equal),
+        "!=" => ordering != Some(Equal),
+        "<" => ordering == Some(Less),
+        "<=" => matches!(ordering, Some(Less | Equal)),
+        ">" => ordering == Some(Greater),
+        ">=" => matches!(ordering, Some(Greater | Equal)),
+        _ => return None,
+    })
+}
+
+/// Evaluates the parts of an interpolated string and joins them into a
+/// `String`. While any part is not yet concrete, the interpolation is kept.
+fn interpolate(
+    parts: &[SpannedValue],
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
+    span: Span,
+) -> Result<SpannedValue, UnifyError> {
+    let mut resolved = Vec::new();
+    for part in parts {
+        resolved.push(resolve_refs_inner(part, path, root, eval)?);
+    }
+    if resolved.iter().any(|part| find_unresolved(part).is_some()) {
+        return Ok(SpannedValue {
+            span,
+            kind: ValueKind::Interpolation(resolved),
+        });
+    }
+    let mut out = String::new();
+    for part in &resolved {
+        match display(&part.to_value()) {
+            Some(text) => out.push_str(&text),
+            None => {
+                return Err(UnifyError {
+                    code: ErrorCode::TypeMismatch,
+                    msg: add_path(
+                        path,
+                        format!("cannot interpolate {}", kind_name(&part.kind)),
+                    ),
+                    span: part.span,
+                    prev_span: span,
+                });
+            }
+        }
+    }
+    eval.env.check_size(out.len(), path, span)?;
+    Ok(SpannedValue {
+        span,
+        kind: ValueKind::String(out),
+    })
+}
+
+fn kind_name(kind: &ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Null => "null",
+        ValueKind::Bool(_) => "Boolean",
+        ValueKind::Int(_) => "Int",
+        ValueKind::Float(_) | ValueKind::Decimal(_) => "Float",
+        ValueKind::Rational(_) => "Rational",
+        ValueKind::String(_) => "String",
+        ValueKind::Array(_) => "an array",
+        ValueKind::List(_) => "a list type",
+        ValueKind::Object(_) => "an object",
+        ValueKind::Type(t) => type_name(t),
+        ValueKind::Reference(_) => "a reference",
+        ValueKind::Call(..) | ValueKind::OpCall(..) => "an expression",
+        ValueKind::Interpolation(_) => "an interpolation",
+        ValueKind::Arguments(_) => "arguments",
+        ValueKind::Union(_) => "a union",
+        ValueKind::Default(v) => kind_name(&v.kind),
     }
 }
 
@@ -310,11 +1400,12 @@ fn unify_array_spanned(
     b_items: &[SpannedValue],
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
+    eval: &mut Eval,
     span: Span,
 ) -> Result<SpannedValue, UnifyError> {
     if a_items.len() != b_items.len() {
         return Err(UnifyError {
+            code: ErrorCode::Conflict,
             msg: add_path(path, "array lengths differ".into()),
             span,
             prev_span: span,
@@ -327,7 +1418,7 @@ fn unify_array_spanned(
         } else {
             format!("{}[{}]", path, i)
         };
-        out.push(unify_spanned_inner(av, bv, &new_path, root, seen)?);
+        out.push(unify_spanned_inner(av, bv, &new_path, root, eval)?);
     }
     Ok(SpannedValue {
         span,
@@ -335,15 +1426,180 @@ fn unify_array_spanned(
     })
 }
 
+/// How many items a list type allows, counting the items it starts with.
+fn list_length<V>(list: &List<V>) -> (usize, Option<usize>) {
+    (list.min.max(list.items.len()), list.max)
+}
+
+fn describe_length((min, max): (usize, Option<usize>)) -> String {
+    let (bound, last) = match (min, max) {
+        (min, None) => (format!("at least {}", min), min),
+        (0, Some(max)) => (format!("at most {}", max), max),
+        (min, Some(max)) if min == max => (format!("exactly {}", min), min),
+        (min, Some(max)) => (format!("between {} and {}", min, max), max),
+    };
+    format!("{} item{}", bound, if last == 1 { "" } else { "s" })
+}
+
+/// Unifies a list type with an array, item by item. `list_first` says
+/// whether the list type came first, to keep the order of the two sides.
+fn unify_list_array(
+    list: &List<SpannedValue>,
+    items: &[SpannedValue],
+    list_first: bool,
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
+    span: Span,
+) -> Result<SpannedValue, UnifyError> {
+    let (min, max) = list_length(list);
+    if items.len() < min || max.is_some_and(|max| items.len() > max) {
+        return Err(UnifyError {
+            code: ErrorCode::Conflict,
+            msg: add_path(
+                path,
+                format!(
+                    "expected {}, got {}",
+                    describe_length((min, max)),
+                    items.len()
+                ),
+            ),
+            span,
+            prev_span: span,
+        });
+    }
+    let mut out = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let new_path = format!("{}[{}]", path, i);
+        let spec = list.items.get(i).unwrap_or(&list.rest);
+        out.push(if list_first {
+            unify_spanned_inner(spec, item, &new_path, root, eval)?
+        } else {
+            unify_spanned_inner(item, spec, &new_path, root, eval)?
+        });
+    }
+    Ok(SpannedValue {
+        span,
+        kind: ValueKind::Array(out),
+    })
+}
+
+/// Unifies two list types: item by item, the rests with each other, and
+/// the lengths to those both allow.
+fn unify_lists(
+    a: &List<SpannedValue>,
+    b: &List<SpannedValue>,
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
+    span: Span,
+    prev_span: Span,
+) -> Result<SpannedValue, UnifyError> {
+    let (a_min, a_max) = list_length(a);
+    let (b_min, b_max) = list_length(b);
+    let min = a_min.max(b_min);
+    let max = match (a_max, b_max) {
+        (Some(x), Some(y)) => Some(x.min(y)),
+        (x, y) => x.or(y),
+    };
+    if max.is_some_and(|max| max < min) {
+        return Err(UnifyError {
+            code: ErrorCode::Conflict,
+            msg: add_path(
+                path,
+                format!(
+                    "list lengths do not overlap: {} and {}",
+                    describe_length((a_min, a_max)),
+                    describe_length((b_min, b_max))
+                ),
+            ),
+            span,
+            prev_span,
+        });
+    }
+    let mut items = Vec::new();
+    for i in 0..a.items.len().max(b.items.len()) {
+        let new_path = format!("{}[{}]", path, i);
+        let x = a.items.get(i).unwrap_or(&a.rest);
+        let y = b.items.get(i).unwrap_or(&b.rest);
+        items.push(unify_spanned_inner(x, y, &new_path, root, eval)?);
+    }
+    let rest_path = format!("{}[...]", path);
+    let rest = unify_spanned_inner(&a.rest, &b.rest, &rest_path, root, eval)?;
+    Ok(SpannedValue {
+        span,
+        kind: ValueKind::List(List {
+            min: if min > items.len() { min } else { 0 },
+            items,
+            rest: Box::new(rest),
+            max,
+        }),
+    })
+}
+
+/// Combines the annotations of two occurrences of a member. The member stays
+/// optional only if both occurrences are.
+fn merge_annotations(a: &[Annotation], b: &[Annotation]) -> Vec<Annotation> {
+    let optional = a.contains(&Annotation::Optional) && b.contains(&Annotation::Optional);
+    let mut out: Vec<Annotation> = Vec::new();
+    for ann in a.iter().chain(b) {
+        if *ann != Annotation::Optional && !out.contains(ann) {
+            out.push(ann.clone());
+        }
+    }
+    if optional {
+        out.push(Annotation::Optional);
+    }
+    out
+}
+
+fn is_closed(members: &[(String, SpannedValue, Span, Vec<Annotation>)]) -> bool {
+    members
+        .iter()
+        .any(|(_, _, _, anns)| anns.contains(&Annotation::Closed))
+}
+
+/// Fails on the first member of `members` whose key is not in the closed
+/// struct `closed`.
+fn check_closed(
+    closed: &[(String, SpannedValue, Span, Vec<Annotation>)],
+    members: &[(String, SpannedValue, Span, Vec<Annotation>)],
+    path: &str,
+    closed_span: Span,
+) -> Result<(), UnifyError> {
+    match members
+        .iter()
+        .find(|(k, _, _, _)| !closed.iter().any(|(c, _, _, _)| c == k))
+    {
+        Some((k, _, span, _)) => Err(UnifyError {
+            code: ErrorCode::UnknownField,
+            msg: add_path(
+                path,
+                format!("field {} is not allowed by a closed struct", k),
+            ),
+            span: *span,
+            prev_span: closed_span,
+        }),
+        None => Ok(()),
+    }
+}
+
 fn unify_object_spanned(
     a_members: &[(String, SpannedValue, Span, Vec<Annotation>)],
     b_members: &[(String, SpannedValue, Span, Vec<Annotation>)],
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
+    eval: &mut Eval,
     span: Span,
+    prev_span: Span,
 ) -> Result<SpannedValue, UnifyError> {
     use std::collections::BTreeMap;
+    if is_closed(a_members) {
+        check_closed(a_members, b_members, path, prev_span)?;
+    }
+    if is_closed(b_members) {
+        check_closed(b_members, a_members, path, span)?;
+    }
     let mut map: BTreeMap<String, (SpannedValue, Vec<Annotation>)> = BTreeMap::new();
     for (k, v, _, anns) in a_members {
         map.insert(k.clone(), (v.clone(), anns.clone()));
@@ -355,10 +1611,8 @@ fn unify_object_spanned(
             } else {
                 format!("{}.{}", path, k)
             };
-            let merged = unify_spanned_inner(prev, v, &new_path, root, seen)?;
-            let mut combined = prev_anns.clone();
-            combined.extend(anns.clone());
-            (merged, combined)
+            let merged = unify_spanned_inner(prev, v, &new_path, root, eval)?;
+            (merged, merge_annotations(prev_anns, anns))
         } else {
             (v.clone(), anns.clone())
         };
@@ -377,33 +1631,42 @@ fn unify_object_spanned(
     })
 }
 
-fn unify_union_pairs_spanned(
-    a_opts: &[SpannedValue],
-    b_opts: &[SpannedValue],
+/// A union alternative and whether it is the default, `*value`.
+fn alternative(item: &SpannedValue) -> (&SpannedValue, bool) {
+    match &item.kind {
+        ValueKind::Default(v) => (v, true),
+        _ => (item, false),
+    }
+}
+
+fn mark_default(value: SpannedValue) -> SpannedValue {
+    SpannedValue {
+        span: value.span,
+        kind: ValueKind::Default(Box::new(value)),
+    }
+}
+
+/// The union of the alternatives that unified. A single one is the result
+/// itself, and no longer a default.
+fn union_of(
+    mut results: Vec<SpannedValue>,
     path: &str,
-    root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
     span: Span,
     prev_span: Span,
 ) -> Result<SpannedValue, UnifyError> {
-    let mut results: Vec<SpannedValue> = Vec::new();
-    for ao in a_opts {
-        for bo in b_opts {
-            if branch_matches(ao, bo, root) && branch_matches(bo, ao, root) {
-                if let Ok(res) = unify_spanned_inner(ao, bo, path, root, seen) {
-                    results.push(res);
-                }
-            }
-        }
-    }
     if results.is_empty() {
         Err(UnifyError {
+            code: ErrorCode::Conflict,
             msg: add_path(path, "values do not unify".into()),
             span,
             prev_span,
         })
     } else if results.len() == 1 {
-        Ok(results.pop().unwrap())
+        let result = results.pop().unwrap();
+        Ok(match result.kind {
+            ValueKind::Default(v) => *v,
+            _ => result,
+        })
     } else {
         Ok(SpannedValue {
             span,
@@ -412,42 +1675,89 @@ fn unify_union_pairs_spanned(
     }
 }
 
+/// Unifies two unions alternative by alternative. When both have a default,
+/// the result's defaults are the pairs of defaults that unify, and it is an
+/// error if there are none. When only one has, its defaults stay defaults.
+fn unify_union_pairs_spanned(
+    a_opts: &[SpannedValue],
+    b_opts: &[SpannedValue],
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
+    span: Span,
+    prev_span: Span,
+) -> Result<SpannedValue, UnifyError> {
+    let a_default = a_opts.iter().find(|o| alternative(o).1);
+    let b_default = b_opts.iter().find(|o| alternative(o).1);
+    let mut defaults_meet = false;
+    let mut results: Vec<SpannedValue> = Vec::new();
+    for ao in a_opts {
+        let (av, a_is_default) = alternative(ao);
+        for bo in b_opts {
+            let (bv, b_is_default) = alternative(bo);
+            if branch_matches(av, bv, root)
+                && branch_matches(bv, av, root)
+                && let Ok(res) = unify_spanned_inner(av, bv, path, root, eval)
+            {
+                defaults_meet |= a_is_default && b_is_default;
+                let is_default = match (a_default, b_default) {
+                    (Some(_), Some(_)) => a_is_default && b_is_default,
+                    (Some(_), None) => a_is_default,
+                    (None, _) => b_is_default,
+                };
+                results.push(if is_default { mark_default(res) } else { res });
+            }
+        }
+    }
+    if let (Some(a_default), Some(b_default)) = (a_default, b_default)
+        && !defaults_meet
+    {
+        let show = |v: &SpannedValue| printer::to_string(alternative(v).0).trim_end().to_string();
+        return Err(UnifyError {
+            code: ErrorCode::Conflict,
+            msg: add_path(
+                path,
+                format!(
+                    "defaults {} and {} do not unify",
+                    show(a_default),
+                    show(b_default)
+                ),
+            ),
+            span: b_default.span,
+            prev_span: a_default.span,
+        });
+    }
+    union_of(results, path, span, prev_span)
+}
+
 fn unify_union_against_spanned(
     opts: &[SpannedValue],
     other: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut std::collections::HashSet<String>,
+    eval: &mut Eval,
     span: Span,
     prev_span: Span,
 ) -> Result<SpannedValue, UnifyError> {
+    // With a default, other alternatives may still apply even if one is
+    // exactly `other`.
+    let has_default = opts.iter().any(|o| alternative(o).1);
     let mut results: Vec<SpannedValue> = Vec::new();
     for o in opts {
-        if branch_matches(o, other, root) {
-            if let Ok(res) = unify_spanned_inner(o, other, path, root, seen) {
-                if res.to_value() == other.to_value() {
-                    return Ok(res);
-                }
-                if !results.iter().any(|r| r.to_value() == res.to_value()) {
-                    results.push(res);
-                }
+        let (o, is_default) = alternative(o);
+        if branch_matches(o, other, root)
+            && let Ok(res) = unify_spanned_inner(o, other, path, root, eval)
+        {
+            if !has_default && res.to_value() == other.to_value() {
+                return Ok(res);
+            }
+            let res = if is_default { mark_default(res) } else { res };
+            if !results.iter().any(|r| r.to_value() == res.to_value()) {
+                results.push(res);
             }
         }
     }
-    if results.is_empty() {
-        Err(UnifyError {
-            msg: add_path(path, "values do not unify".into()),
-            span,
-            prev_span,
-        })
-    } else if results.len() == 1 {
-        Ok(results.pop().unwrap())
-    } else {
-        Ok(SpannedValue {
-            span,
-            kind: ValueKind::Union(results),
-        })
-    }
+    union_of(results, path, span, prev_span)
 }
 
 fn add_path(path: &str, msg: String) -> String {
@@ -474,6 +1784,29 @@ fn lookup<'a>(root: &'a BTreeMap<String, SpannedValue>, path: &str) -> Option<&'
     Some(current)
 }
 
+/// Like [`lookup`], but with every value at `path`: an object that is not
+/// unified yet may still list a key several times.
+fn lookup_all<'a>(root: &'a BTreeMap<String, SpannedValue>, path: &str) -> Vec<&'a SpannedValue> {
+    let mut segments = path.split('.');
+    let mut current: Vec<&SpannedValue> = segments
+        .next()
+        .and_then(|k| root.get(k))
+        .into_iter()
+        .collect();
+    for seg in segments {
+        current = current
+            .into_iter()
+            .flat_map(|v| match &v.kind {
+                ValueKind::Object(members) => members.as_slice(),
+                _ => &[],
+            })
+            .filter(|(k, _, _, _)| k == seg)
+            .map(|(_, v, _, _)| v)
+            .collect();
+    }
+    current
+}
+
 use std::collections::HashSet;
 
 pub fn unify_spanned(
@@ -482,8 +1815,36 @@ pub fn unify_spanned(
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
 ) -> Result<SpannedValue, UnifyError> {
-    let mut seen = HashSet::new();
-    unify_spanned_inner(a, b, path, root, &mut seen)
+    let natives = Natives::builtin();
+    let limits = Limits::default();
+    unify_with(a, b, path, root, &Env::new(&natives, &limits))
+}
+
+fn unify_with(
+    a: &SpannedValue,
+    b: &SpannedValue,
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    env: &Env,
+) -> Result<SpannedValue, UnifyError> {
+    unify_spanned_inner(a, b, path, root, &mut Eval::new(env))
+}
+
+/// Whether `value` has optional members without a value, or is a closed
+/// struct, neither of which shows in [`SpannedValue::to_value`].
+fn has_hidden_constraints(value: &SpannedValue) -> bool {
+    match &value.kind {
+        ValueKind::Array(items) => items.iter().any(has_hidden_constraints),
+        ValueKind::List(list) => list.values().any(has_hidden_constraints),
+        ValueKind::Object(members) => {
+            is_closed(members)
+                || members.iter().any(|(_, v, _, anns)| {
+                    (anns.contains(&Annotation::Optional) && find_unresolved(v).is_some())
+                        || has_hidden_constraints(v)
+                })
+        }
+        _ => false,
+    }
 }
 
 fn unify_spanned_inner(
@@ -491,76 +1852,115 @@ fn unify_spanned_inner(
     b: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut HashSet<String>,
+    eval: &mut Eval,
 ) -> Result<SpannedValue, UnifyError> {
-    if a.to_value() == b.to_value() {
+    eval.env.step(path, b.span)?;
+    if a.to_value() == b.to_value() && !has_hidden_constraints(a) {
         return Ok(b.clone());
     }
     match (&a.kind, &b.kind) {
-        (ValueKind::Reference(pa), _) => {
-            if !seen.insert(pa.clone()) {
-                return Ok(b.clone());
-            }
-            let res = match lookup(root, pa) {
-                Some(val) => unify_spanned_inner(val, b, path, root, seen),
-                None => Err(UnifyError {
-                    msg: add_path(path, format!("unresolved reference {}", pa)),
-                    span: b.span,
-                    prev_span: a.span,
-                }),
+        (ValueKind::Reference(_), _) => {
+            let (followed, a) = eval.follow_aliases(a, path, root)?;
+            let ValueKind::Reference(pa) = &a.kind else {
+                unreachable!()
+            };
+            let res = if !eval.seen.insert(pa.clone()) {
+                eval.cycles += 1;
+                Ok(b.clone())
+            } else {
+                let res = match lookup_all(root, pa).as_slice() {
+                    [] => Err(UnifyError {
+                        code: ErrorCode::UnresolvedReference,
+                        msg: add_path(path, format!("unresolved reference {}", pa)),
+                        span: b.span,
+                        prev_span: a.span,
+                    }),
+                    vals => vals.iter().try_fold(b.clone(), |b, val| {
+                        unify_spanned_inner(val, &b, path, root, eval)
+                    }),
+                };
+                eval.seen.remove(pa);
+                res
             };
-            seen.remove(pa);
+            eval.forget(followed);
             res
         }
-        (_, ValueKind::Reference(pb)) => {
-            if !seen.insert(pb.clone()) {
-                return Ok(a.clone());
-            }
-            let res = match lookup(root, pb) {
-                Some(val) => unify_spanned_inner(a, val, path, root, seen),
-                None => Err(UnifyError {
-                    msg: add_path(path, format!("unresolved reference {}", pb)),
-                    span: b.span,
-                    prev_span: a.span,
-                }),
+        (_, ValueKind::Reference(_)) => {
+            let (followed, b) = eval.follow_aliases(b, path, root)?;
+            let ValueKind::Reference(pb) = &b.kind else {
+                unreachable!()
+            };
+            let res = if !eval.seen.insert(pb.clone()) {
+                eval.cycles += 1;
+                Ok(a.clone())
+            } else {
+                let res = match lookup_all(root, pb).as_slice() {
+                    [] => Err(UnifyError {
+                        code: ErrorCode::UnresolvedReference,
+                        msg: add_path(path, format!("unresolved reference {}", pb)),
+                        span: b.span,
+                        prev_span: a.span,
+                    }),
+                    vals => vals.iter().try_fold(a.clone(), |a, val| {
+                        unify_spanned_inner(&a, val, path, root, eval)
+                    }),
+                };
+                eval.seen.remove(pb);
+                res
             };
-            seen.remove(pb);
+            eval.forget(followed);
             res
         }
         (ValueKind::OpCall(op, left, right), _) => {
-            let evaluated = execute_operator(op, left, right, path, root, seen, a.span)?;
+            let evaluated = execute_operator(op, left, right, path, root, eval, a.span)?;
             if matches!(evaluated.kind, ValueKind::OpCall(..)) {
                 Ok(evaluated)
             } else {
-                unify_spanned_inner(&evaluated, b, path, root, seen)
+                unify_spanned_inner(&evaluated, b, path, root, eval)
             }
         }
         (_, ValueKind::OpCall(op, left, right)) => {
-            let evaluated = execute_operator(op, left, right, path, root, seen, b.span)?;
+            let evaluated = execute_operator(op, left, right, path, root, eval, b.span)?;
             if matches!(evaluated.kind, ValueKind::OpCall(..)) {
                 Ok(evaluated)
             } else {
-                unify_spanned_inner(a, &evaluated, path, root, seen)
+                unify_spanned_inner(a, &evaluated, path, root, eval)
+            }
+        }
+        (ValueKind::Interpolation(parts), _) => {
+            let evaluated = interpolate(parts, path, root, eval, a.span)?;
+            if matches!(evaluated.kind, ValueKind::Interpolation(_)) {
+                Ok(evaluated)
+            } else {
+                unify_spanned_inner(&evaluated, b, path, root, eval)
+            }
+        }
+        (_, ValueKind::Interpolation(parts)) => {
+            let evaluated = interpolate(parts, path, root, eval, b.span)?;
+            if matches!(evaluated.kind, ValueKind::Interpolation(_)) {
+                Ok(evaluated)
+            } else {
+                unify_spanned_inner(a, &evaluated, path, root, eval)
             }
         }
         (ValueKind::Call(name, arg), _) => {
-            let evaluated = execute_call(name, arg, path, root, seen, a.span)?;
+            let evaluated = execute_call(name, arg, path, root, eval, a.span)?;
             if matches!(evaluated.kind, ValueKind::Call(..)) {
                 Ok(evaluated)
             } else {
-                unify_spanned_inner(&evaluated, b, path, root, seen)
+                unify_spanned_inner(&evaluated, b, path, root, eval)
             }
         }
         (_, ValueKind::Call(name, arg)) => {
-            let evaluated = execute_call(name, arg, path, root, seen, b.span)?;
+            let evaluated = execute_call(name, arg, path, root, eval, b.span)?;
             if matches!(evaluated.kind, ValueKind::Call(..)) {
                 Ok(evaluated)
             } else {
-                unify_spanned_inner(a, &evaluated, path, root, seen)
+                unify_spanned_inner(a, &evaluated, path, root, eval)
             }
         }
         (ValueKind::Union(a_opts), ValueKind::Union(b_opts)) => {
-            unify_union_pairs_spanned(a_opts, b_opts, path, root, seen, b.span, a.span)
+            unify_union_pairs_spanned(a_opts, b_opts, path, root, eval, b.span, a.span)
         }
         (ValueKind::Union(opts), _) | (_, ValueKind::Union(opts)) => {
             let other = if matches!(&a.kind, ValueKind::Union(_)) {
@@ -568,14 +1968,18 @@ fn unify_spanned_inner(
             } else {
                 a
             };
-            unify_union_against_spanned(opts, other, path, root, seen, b.span, a.span)
+            unify_union_against_spanned(opts, other, path, root, eval, b.span, a.span)
         }
+        // Only meaningful inside a union.
+        (ValueKind::Default(v), _) => unify_spanned_inner(v, b, path, root, eval),
+        (_, ValueKind::Default(v)) => unify_spanned_inner(a, v, path, root, eval),
         (ValueKind::Type(ta), ValueKind::Type(tb)) => match unify_types(ta, tb) {
             Ok(t) => Ok(SpannedValue {
                 span: b.span,
                 kind: ValueKind::Type(t),
             }),
             Err(e) => Err(UnifyError {
+                code: ErrorCode::TypeMismatch,
                 msg: add_path(path, e),
                 span: b.span,
                 prev_span: a.span,
@@ -584,12 +1988,20 @@ fn unify_spanned_inner(
         (ValueKind::Type(t), other) | (other, ValueKind::Type(t)) => {
             let is_a_type = matches!(&a.kind, ValueKind::Type(_));
             let span = if is_a_type { b.span } else { a.span };
+            if matches!(t, ValType::Any) {
+                // Kept as is, with the annotations of its members.
+                return Ok(SpannedValue {
+                    span,
+                    kind: other.clone(),
+                });
+            }
             match unify_type_value(t, &kind_to_value(other)) {
                 Ok(j) => Ok(SpannedValue {
                     span,
                     kind: value_to_kind(j),
                 }),
                 Err(e) => Err(UnifyError {
+                    code: ErrorCode::TypeMismatch,
                     msg: add_path(path, e),
                     span,
                     prev_span: a.span,
@@ -597,12 +2009,32 @@ fn unify_spanned_inner(
             }
         }
         (ValueKind::Array(a_items), ValueKind::Array(b_items)) => {
-            unify_array_spanned(a_items, b_items, path, root, seen, b.span)
+            unify_array_spanned(a_items, b_items, path, root, eval, b.span)
+        }
+        (ValueKind::List(list), ValueKind::Array(items)) => {
+            unify_list_array(list, items, true, path, root, eval, b.span)
+        }
+        (ValueKind::Array(items), ValueKind::List(list)) => {
+            unify_list_array(list, items, false, path, root, eval, a.span)
+        }
+        (ValueKind::List(a_list), ValueKind::List(b_list)) => {
+            unify_lists(a_list, b_list, path, root, eval, b.span, a.span)
         }
         (ValueKind::Object(a_members), ValueKind::Object(b_members)) => {
-            unify_object_spanned(a_members, b_members, path, root, seen, b.span)
+            unify_object_spanned(a_members, b_members, path, root, eval, b.span, a.span)
+        }
+        // A decimal is the exact number it is written as, so it unifies
+        // with an `Int` or `Rational` of the same value, which is kept.
+        (ValueKind::Decimal(d), other) | (other, ValueKind::Decimal(d))
+            if exact(other).is_some_and(|n| n == *d) =>
+        {
+            Ok(SpannedValue {
+                span: b.span,
+                kind: other.clone(),
+            })
         }
         _ => Err(UnifyError {
+            code: ErrorCode::Conflict,
             msg: add_path(path, "values do not unify".into()),
             span: b.span,
             prev_span: a.span,
@@ -614,23 +2046,45 @@ fn unify_tree_inner(
     value: &SpannedValue,
     path: &str,
     root: &mut BTreeMap<String, SpannedValue>,
+    env: &Env,
     is_root: bool,
+    mut collector: Option<&mut Collector>,
 ) -> Result<SpannedValue, UnifyError> {
     match &value.kind {
         ValueKind::Array(items) => {
             let mut out = Vec::new();
             for item in items {
-                out.push(unify_tree_inner(item, path, root, false)?);
+                out.push(unify_tree_inner(
+                    item,
+                    path,
+                    root,
+                    env,
+                    false,
+                    collector.as_deref_mut(),
+                )?);
             }
             Ok(SpannedValue {
                 span: value.span,
                 kind: ValueKind::Array(out),
             })
         }
+        ValueKind::Default(v) => Ok(SpannedValue {
+            span: value.span,
+            kind: ValueKind::Default(Box::new(unify_tree_inner(
+                v, path, root, env, false, collector,
+            )?)),
+        }),
         ValueKind::Union(items) => {
             let mut out = Vec::new();
             for item in items {
-                out.push(unify_tree_inner(item, path, root, false)?);
+                out.push(unify_tree_inner(
+                    item,
+                    path,
+                    root,
+                    env,
+                    false,
+                    collector.as_deref_mut(),
+                )?);
             }
             Ok(SpannedValue {
                 span: value.span,
@@ -662,16 +2116,18 @@ fn unify_tree_inner(
                 let unified_v = if is_fn {
                     v.clone()
                 } else {
-                    unify_tree_inner(v, &new_path, root, false)?
+                    unify_tree_inner(v, &new_path, root, env, false, collector.as_deref_mut())?
                 };
                 all_values
                     .entry(k.clone())
                     .or_default()
                     .push(unified_v.clone());
-                all_annotations
-                    .entry(k.clone())
-                    .or_default()
-                    .extend(anns.clone());
+                match all_annotations.get_mut(k) {
+                    Some(prev) => *prev = merge_annotations(prev, anns),
+                    None => {
+                        all_annotations.insert(k.clone(), anns.clone());
+                    }
+                }
                 if let Some(&i) = indices.get(k) {
                     // already recorded first occurrence
                     let _ = i; // suppress unused warning in some compilers
@@ -685,12 +2141,19 @@ fn unify_tree_inner(
             }
 
             // Repeatedly unify duplicates until results stabilize so that
-            // reference resolution does not depend on ordering.
+            // reference resolution does not depend on ordering. Keys are visited
+            // in first-appearance order so each pass is deterministic.
+            // In collecting mode a failed key keeps its bottom value.
+            let mut failed: HashSet<String> = HashSet::new();
             let mut changed = true;
             while changed {
                 changed = false;
-                for (k, values) in &all_values {
-                    let i = indices[k];
+                for entry in out.iter_mut() {
+                    let k = entry.0.clone();
+                    if failed.contains(&k) {
+                        continue;
+                    }
+                    let values = &all_values[&k];
                     let entry_path = if path.is_empty() {
                         k.clone()
                     } else {
@@ -698,13 +2161,51 @@ fn unify_tree_inner(
                     };
                     let mut current = values[0].clone();
                     for v in &values[1..] {
-                        current = unify_spanned(&current, v, &entry_path, root)?;
+                        current = match env
+                            .step(&entry_path, v.span)
+                            .and_then(|()| unify_with(&current, v, &entry_path, root, env))
+                        {
+                            Ok(unified) => unified,
+                            Err(err) => {
+                                let Some(c) = collector.as_deref_mut() else {
+                                    return Err(err);
+                                };
+                                failed.insert(k.clone());
+                                let is_fn = all_annotations[&k].contains(&Annotation::Function);
+                                let objects: Option<Vec<_>> = values
+                                    .iter()
+                                    .map(|v| match &v.kind {
+                                        ValueKind::Object(ms) => Some(ms.clone()),
+                                        _ => None,
+                                    })
+                                    .collect();
+                                match objects {
+                                    // Merge the objects member by member so
+                                    // only the conflicting fields fail.
+                                    Some(objects) if !is_fn => unify_tree_inner(
+                                        &SpannedValue {
+                                            span: v.span,
+                                            kind: ValueKind::Object(objects.concat()),
+                                        },
+                                        &entry_path,
+                                        root,
+                                        env,
+                                        false,
+                                        Some(c),
+                                    )?,
+                                    _ => c.fail(&entry_path, err, values),
+                                }
+                            }
+                        };
                         if is_root {
                             root.insert(k.clone(), current.clone());
                         }
+                        if failed.contains(&k) {
+                            break;
+                        }
                     }
-                    if current.to_value() != out[i].1.to_value() {
-                        out[i].1 = current.clone();
+                    if current.to_value() != entry.1.to_value() {
+                        entry.1 = current.clone();
                         changed = true;
                     }
                     if is_root {
@@ -728,6 +2229,26 @@ fn unify_tree_inner(
 }
 
 pub fn unify_tree(value: &SpannedValue) -> Result<SpannedValue, UnifyError> {
+    unify_tree_with_natives(value, &Natives::builtin())
+}
+
+/// Like [`unify_tree`], calling `native ["name", arg]` through `natives`.
+pub fn unify_tree_with_natives(
+    value: &SpannedValue,
+    natives: &Natives,
+) -> Result<SpannedValue, UnifyError> {
+    unify_tree_with_limits(value, natives, &Limits::default())
+}
+
+/// Like [`unify_tree_with_natives`], stopping with an error when evaluation
+/// goes past `limits`.
+pub fn unify_tree_with_limits(
+    value: &SpannedValue,
+    natives: &Natives,
+    limits: &Limits,
+) -> Result<SpannedValue, UnifyError> {
+    check_nesting(value, limits).map_err(|conflict| conflict.error)?;
+    let env = Env::new(natives, limits);
     let mut pre = value.clone();
     use std::collections::BTreeMap;
     let empty = BTreeMap::new();
@@ -739,8 +2260,221 @@ pub fn unify_tree(value: &SpannedValue) -> Result<SpannedValue, UnifyError> {
             root.insert(k.clone(), v.clone());
         }
     }
-    let unified = unify_tree_inner(&pre, "", &mut root, true)?;
-    resolve_refs(&unified, "", &root)
+    let mut unified = unify_tree_inner(&pre, "", &mut root, &env, true, None)?;
+    settle_defaults(&mut unified, &mut root);
+    let mut eval = Eval::new(&env);
+    warm_references(&unified, &root, &mut eval);
+    let mut resolved = resolve_refs_inner(&unified, "", &root, &mut eval)?;
+    apply_defaults(&mut resolved);
+    float_decimals(&mut resolved);
+    Ok(resolved)
+}
+
+/// Like [`unify_tree`], but keeps going past conflicts. Each field that
+/// fails is reported and replaced by `Nothing`, so the rest of the tree is
+/// still unified. Conflicts in fields that refer to a failed field are
+/// marked as consequential.
+pub fn unify_tree_collecting(value: &SpannedValue) -> (SpannedValue, Vec<Conflict>) {
+    unify_tree_collecting_with_natives(value, &Natives::builtin())
+}
+
+/// Like [`unify_tree_collecting`], calling natives through `natives`.
+pub fn unify_tree_collecting_with_natives(
+    value: &SpannedValue,
+    natives: &Natives,
+) -> (SpannedValue, Vec<Conflict>) {
+    unify_tree_collecting_with_limits(value, natives, &Limits::default())
+}
+
+/// Like [`unify_tree_collecting_with_natives`], reporting a conflict where
+/// evaluation goes past `limits`.
+pub fn unify_tree_collecting_with_limits(
+    value: &SpannedValue,
+    natives: &Natives,
+    limits: &Limits,
+) -> (SpannedValue, Vec<Conflict>) {
+    if let Err(conflict) = check_nesting(value, limits) {
+        let nothing = SpannedValue {
+            span: value.span,
+            kind: ValueKind::Type(ValType::Nothing),
+        };
+        return (nothing, vec![conflict]);
+    }
+    let env = Env::new(natives, limits);
+    let mut pre = value.clone();
+    resolve_relative_refs(&mut pre, "".to_string(), &BTreeMap::new());
+
+    let mut root: BTreeMap<String, SpannedValue> = BTreeMap::new();
+    if let ValueKind::Object(members) = &pre.kind {
+        for (k, v, _, _) in members {
+            root.insert(k.clone(), v.clone());
+        }
+    }
+    let mut edges = Vec::new();
+    collect_refs(&pre, "", &mut |field, r| {
+        edges.push((field.to_string(), r.to_string()))
+    });
+    let mut collector = Collector {
+        conflicts: Vec::new(),
+        edges,
+    };
+    let mut unified = match unify_tree_inner(&pre, "", &mut root, &env, true, Some(&mut collector))
+    {
+        Ok(unified) => unified,
+        Err(err) => collector.fail("", err, std::slice::from_ref(&pre)),
+    };
+    settle_defaults(&mut unified, &mut root);
+    let mut eval = Eval::new(&env);
+    warm_references(&unified, &root, &mut eval);
+    let mut resolved = resolve_collecting(&unified, "", &root, &mut eval, &mut collector);
+    apply_defaults(&mut resolved);
+    float_decimals(&mut resolved);
+    (resolved, collector.finish())
+}
+
+/// Turns the decimals that no `Rational` or `Int` read into the floats they
+/// stand for, as natives and exports expect.
+fn float_decimals(value: &mut SpannedValue) {
+    match &mut value.kind {
+        ValueKind::Decimal(n) => value.kind = ValueKind::Float(to_float(n)),
+        ValueKind::Array(items)
+        | ValueKind::Arguments(items)
+        | ValueKind::Interpolation(items)
+        | ValueKind::Union(items) => items.iter_mut().for_each(float_decimals),
+        ValueKind::List(list) => list.values_mut().for_each(float_decimals),
+        ValueKind::Object(members) => {
+            for (_, v, _, anns) in members {
+                if !anns.contains(&Annotation::Function) {
+                    float_decimals(v);
+                }
+            }
+        }
+        ValueKind::Call(_, v) | ValueKind::Default(v) => float_decimals(v),
+        ValueKind::OpCall(_, left, right) => {
+            float_decimals(left);
+            float_decimals(right);
+        }
+        _ => {}
+    }
+}
+
+/// Replaces each union with exactly one default by that default, leaving
+/// `@Function` members alone.
+fn apply_defaults(value: &mut SpannedValue) {
+    match &mut value.kind {
+        ValueKind::Union(items) => {
+            let mut defaults = items.iter().filter(|item| alternative(item).1);
+            match (defaults.next(), defaults.next()) {
+                (Some(default), None) => {
+                    *value = alternative(default).0.clone();
+                    apply_defaults(value);
+                }
+                _ => items.iter_mut().for_each(apply_defaults),
+            }
+        }
+        ValueKind::Default(v) => apply_defaults(v),
+        ValueKind::Array(items) => items.iter_mut().for_each(apply_defaults),
+        ValueKind::List(list) => list.values_mut().for_each(apply_defaults),
+        ValueKind::Object(members) => {
+            for (_, v, _, anns) in members {
+                if !anns.contains(&Annotation::Function) {
+                    apply_defaults(v);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Applies defaults once unification is done, before references are
+/// resolved, so that they resolve to the defaults too.
+fn settle_defaults(unified: &mut SpannedValue, root: &mut BTreeMap<String, SpannedValue>) {
+    apply_defaults(unified);
+    if let ValueKind::Object(members) = &unified.kind {
+        for (k, _, _, anns) in members {
+            if let Some(v) = root.get_mut(k)
+                && !anns.contains(&Annotation::Function)
+            {
+                apply_defaults(v);
+            }
+        }
+    }
+}
+
+/// Resolves references field by field, replacing fields that fail with
+/// `Nothing`.
+fn resolve_collecting(
+    value: &SpannedValue,
+    path: &str,
+    root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
+    collector: &mut Collector,
+) -> SpannedValue {
+    match &value.kind {
+        ValueKind::Object(members) => {
+            let mut out = Vec::new();
+            for (k, v, span, anns) in members {
+                if anns.contains(&Annotation::Function) {
+                    out.push((k.clone(), v.clone(), *span, anns.clone()));
+                    continue;
+                }
+                let new_path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                let resolved = resolve_collecting(v, &new_path, root, eval, collector);
+                out.push((k.clone(), resolved, *span, anns.clone()));
+            }
+            SpannedValue {
+                span: value.span,
+                kind: ValueKind::Object(out),
+            }
+        }
+        _ => match resolve_refs_inner(value, path, root, eval) {
+            Ok(resolved) => resolved,
+            Err(err) => collector.fail(path, err, std::slice::from_ref(value)),
+        },
+    }
+}
+
+/// Combines the top-level objects of several documents into one object so
+/// they can be evaluated together by [`unify_tree`]. Duplicate keys are kept
+/// as-is and unified like any other repeated key. Documents nested deeper
+/// than the default [`Limits`] allow are rejected, as copying them would
+/// overflow the stack.
+pub fn merge_documents(docs: &[Document]) -> Result<SpannedValue, UnifyError> {
+    for doc in docs {
+        check_nesting(&doc.value, &Limits::default()).map_err(|conflict| conflict.error)?;
+    }
+    match docs {
+        [] => Ok(SpannedValue {
+            span: Span::new(SourceId::default(), 0..0),
+            kind: ValueKind::Object(Vec::new()),
+        }),
+        [doc] => Ok(doc.value.clone()),
+        [first, ..] => {
+            let mut members = Vec::new();
+            for doc in docs {
+                match &doc.value.kind {
+                    ValueKind::Object(ms) => members.extend(ms.iter().cloned()),
+                    _ => {
+                        return Err(UnifyError {
+                            code: ErrorCode::Merge,
+                            msg: "top-level value must be an object to merge with other files"
+                                .into(),
+                            span: doc.value.span,
+                            prev_span: first.value.span,
+                        });
+                    }
+                }
+            }
+            Ok(SpannedValue {
+                span: first.value.span,
+                kind: ValueKind::Object(members),
+            })
+        }
+    }
 }
 
 fn resolve_relative_refs(
@@ -779,12 +2513,19 @@ fn resolve_relative_refs(
                     resolve_relative_refs(item, prefix.clone(), inherited);
                 }
             }
-            ValueKind::Union(items) => {
+            ValueKind::List(list) => {
+                for item in list.values_mut() {
+                    resolve_relative_refs(item, prefix.clone(), inherited);
+                }
+            }
+            ValueKind::Union(items)
+            | ValueKind::Interpolation(items)
+            | ValueKind::Arguments(items) => {
                 for item in items {
                     resolve_relative_refs(item, prefix.clone(), inherited);
                 }
             }
-            ValueKind::Call(_, arg) => {
+            ValueKind::Call(_, arg) | ValueKind::Default(arg) => {
                 resolve_relative_refs(arg, prefix, inherited);
             }
             ValueKind::OpCall(_, left, right) => {
@@ -844,12 +2585,17 @@ fn convert_refs(value: &mut SpannedValue, map: &BTreeMap<String, String>, curren
                 convert_refs(item, map, current);
             }
         }
-        ValueKind::Union(items) => {
+        ValueKind::List(list) => {
+            for item in list.values_mut() {
+                convert_refs(item, map, current);
+            }
+        }
+        ValueKind::Union(items) | ValueKind::Interpolation(items) | ValueKind::Arguments(items) => {
             for item in items {
                 convert_refs(item, map, current);
             }
         }
-        ValueKind::Call(_, arg) => {
+        ValueKind::Call(_, arg) | ValueKind::Default(arg) => {
             convert_refs(arg, map, current);
         }
         ValueKind::OpCall(_, left, right) => {
@@ -861,68 +2607,160 @@ fn convert_refs(value: &mut SpannedValue, map: &BTreeMap<String, String>, curren
     }
 }
 
-fn resolve_refs(
+/// Resolves the references reachable from the non-function fields of
+/// `value`, dependencies first, so that long chains of computed references
+/// are already cached when they are resolved and do not nest deeply.
+fn warm_references(value: &SpannedValue, root: &BTreeMap<String, SpannedValue>, eval: &mut Eval) {
+    let ValueKind::Object(members) = &value.kind else {
+        return;
+    };
+    let mut stack = Vec::new();
+    for (_, v, _, anns) in members.iter().rev() {
+        if !anns.contains(&Annotation::Function) {
+            collect_refs(v, "", &mut |_, r| stack.push((r.to_string(), false)));
+        }
+    }
+    let mut visited = HashSet::new();
+    while let Some((r, expanded)) = stack.pop() {
+        let Some(target) = lookup(root, &r) else {
+            continue;
+        };
+        if expanded {
+            let reference = SpannedValue {
+                span: target.span,
+                kind: ValueKind::Reference(r.clone()),
+            };
+            let _ = resolve_reference(&reference, &r, root, eval);
+        } else if visited.insert(r.clone()) {
+            stack.push((r, true));
+            collect_refs(target, "", &mut |_, dep| {
+                if !visited.contains(dep) {
+                    stack.push((dep.to_string(), false));
+                }
+            });
+        }
+    }
+}
+
+/// Resolves the reference `value` to the value it points to.
+fn resolve_reference(
     value: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
+    eval: &mut Eval,
 ) -> Result<SpannedValue, UnifyError> {
-    let mut seen = HashSet::new();
-    resolve_refs_inner(value, path, root, &mut seen)
+    let ValueKind::Reference(p) = &value.kind else {
+        unreachable!()
+    };
+    if let Some(resolved) = eval.resolved.get(p) {
+        return resolved.clone();
+    }
+    match lookup(root, p) {
+        Some(v) => {
+            if !eval.seen.insert(p.clone()) {
+                eval.cycles += 1;
+                if !matches!(v.kind, ValueKind::Reference(_)) {
+                    return Err(UnifyError {
+                        code: ErrorCode::Cycle,
+                        msg: add_path(path, "infinite structural cycle".into()),
+                        span: value.span,
+                        prev_span: value.span,
+                    });
+                }
+                return Ok(value.clone());
+            }
+            let cycles = eval.cycles;
+            let res = resolve_refs_inner(v, path, root, eval);
+            eval.seen.remove(p);
+            if eval.cycles == cycles {
+                eval.resolved.insert(p.clone(), res.clone());
+            }
+            res
+        }
+        None => Err(UnifyError {
+            code: ErrorCode::UnresolvedReference,
+            msg: add_path(path, format!("unresolved reference {}", p)),
+            span: value.span,
+            prev_span: value.span,
+        }),
+    }
 }
 
 fn resolve_refs_inner(
     value: &SpannedValue,
     path: &str,
     root: &BTreeMap<String, SpannedValue>,
-    seen: &mut HashSet<String>,
+    eval: &mut Eval,
 ) -> Result<SpannedValue, UnifyError> {
+    eval.env.step(path, value.span)?;
     match &value.kind {
-        ValueKind::Reference(p) => match lookup(root, p) {
-            Some(v) => {
-                if !seen.insert(p.clone()) {
-                    if !matches!(v.kind, ValueKind::Reference(_)) {
-                        return Err(UnifyError {
-                            msg: add_path(path, "infinite structural cycle".into()),
-                            span: value.span,
-                            prev_span: value.span,
-                        });
-                    }
-                    return Ok(value.clone());
+        ValueKind::Reference(_) => {
+            let cycles = eval.cycles;
+            let (followed, value) = eval.follow_aliases(value, path, root)?;
+            let res = resolve_reference(value, path, root, eval);
+            if eval.cycles == cycles {
+                for name in &followed {
+                    eval.resolved.insert(name.clone(), res.clone());
                 }
-                let res = resolve_refs_inner(v, path, root, seen);
-                seen.remove(p);
-                res
             }
-            None => Err(UnifyError {
-                msg: add_path(path, format!("unresolved reference {}", p)),
-                span: value.span,
-                prev_span: value.span,
-            }),
-        },
-        ValueKind::Call(name, arg) => execute_call(name, arg, path, root, seen, value.span),
+            eval.forget(followed);
+            res
+        }
+        ValueKind::Call(name, arg) => execute_call(name, arg, path, root, eval, value.span),
         ValueKind::OpCall(op, left, right) => {
-            execute_operator(op, left, right, path, root, seen, value.span)
+            execute_operator(op, left, right, path, root, eval, value.span)
         }
+        ValueKind::Interpolation(parts) => interpolate(parts, path, root, eval, value.span),
         ValueKind::Array(items) => {
             let mut out = Vec::new();
             for item in items {
-                out.push(resolve_refs_inner(item, path, root, seen)?);
+                out.push(resolve_refs_inner(item, path, root, eval)?);
             }
             Ok(SpannedValue {
                 span: value.span,
                 kind: ValueKind::Array(out),
             })
         }
+        ValueKind::List(list) => {
+            let mut items = Vec::new();
+            for item in &list.items {
+                items.push(resolve_refs_inner(item, path, root, eval)?);
+            }
+            let rest = resolve_refs_inner(&list.rest, path, root, eval)?;
+            Ok(SpannedValue {
+                span: value.span,
+                kind: ValueKind::List(List {
+                    items,
+                    rest: Box::new(rest),
+                    min: list.min,
+                    max: list.max,
+                }),
+            })
+        }
+        ValueKind::Default(v) => Ok(SpannedValue {
+            span: value.span,
+            kind: ValueKind::Default(Box::new(resolve_refs_inner(v, path, root, eval)?)),
+        }),
         ValueKind::Union(items) => {
             let mut out = Vec::new();
             for item in items {
-                out.push(resolve_refs_inner(item, path, root, seen)?);
+                out.push(resolve_refs_inner(item, path, root, eval)?);
             }
             Ok(SpannedValue {
                 span: value.span,
                 kind: ValueKind::Union(out),
             })
         }
+        ValueKind::Arguments(items) => {
+            let mut out = Vec::new();
+            for item in items {
+                out.push(resolve_refs_inner(item, path, root, eval)?);
+            }
+            Ok(SpannedValue {
+                span: value.span,
+                kind: ValueKind::Arguments(out),
+            })
+        }
         ValueKind::Object(members) => {
             let mut out = Vec::new();
             for (k, v, span, anns) in members {
@@ -935,7 +2773,7 @@ fn resolve_refs_inner(
                     out.push((k.clone(), v.clone(), *span, anns.clone()));
                     continue;
                 }
-                let resolved = resolve_refs_inner(v, &new_path, root, seen)?;
+                let resolved = resolve_refs_inner(v, &new_path, root, eval)?;
                 out.push((k.clone(), resolved, *span, anns.clone()));
             }
             Ok(SpannedValue {
@@ -948,65 +2786,41 @@ fn resolve_refs_inner(
 }
 
 fn value_to_kind(j: Value) -> ValueKind {
-    match j {
+    spanned(j, Span::new(SourceId::default(), 0..0)).kind
+}
+
+/// `j` with `span` on it and on everything inside it.
+fn spanned(j: Value, span: Span) -> SpannedValue {
+    let all = |items: Vec<Value>| items.into_iter().map(|v| spanned(v, span)).collect();
+    let kind = match j {
         Value::Null => ValueKind::Null,
         Value::Bool(b) => ValueKind::Bool(b),
         Value::Int(n) => ValueKind::Int(n),
         Value::Float(n) => ValueKind::Float(n),
+        Value::Rational(n) => ValueKind::Rational(n),
+        Value::Decimal(n) => ValueKind::Decimal(n),
         Value::String(s) => ValueKind::String(s),
-        Value::Array(arr) => ValueKind::Array(
-            arr.into_iter()
-                .map(|v| SpannedValue {
-                    span: SimpleSpan::new((), 0..0),
-                    kind: value_to_kind(v),
-                })
-                .collect(),
-        ),
+        Value::Array(arr) => ValueKind::Array(all(arr)),
+        Value::List(list) => ValueKind::List(list.map(|v| spanned(v.clone(), span))),
         Value::Object(obj) => ValueKind::Object(
             obj.into_iter()
-                .map(|(k, v)| {
-                    (
-                        k,
-                        SpannedValue {
-                            span: SimpleSpan::new((), 0..0),
-                            kind: value_to_kind(v),
-                        },
-                        SimpleSpan::new((), 0..0),
-                        Vec::new(),
-                    )
-                })
+                .map(|(k, v)| (k, spanned(v, span), span, Vec::new()))
                 .collect(),
         ),
         Value::Reference(r) => ValueKind::Reference(r),
         Value::Type(t) => ValueKind::Type(t),
-        Value::Call(name, arg) => ValueKind::Call(
-            name,
-            Box::new(SpannedValue {
-                span: SimpleSpan::new((), 0..0),
-                kind: value_to_kind(*arg),
-            }),
-        ),
+        Value::Call(name, arg) => ValueKind::Call(name, Box::new(spanned(*arg, span))),
+        Value::Arguments(items) => ValueKind::Arguments(all(items)),
         Value::OpCall(op, left, right) => ValueKind::OpCall(
             op,
-            Box::new(SpannedValue {
-                span: SimpleSpan::new((), 0..0),
-                kind: value_to_kind(*left),
-            }),
-            Box::new(SpannedValue {
-                span: SimpleSpan::new((), 0..0),
-                kind: value_to_kind(*right),
-            }),
+            Box::new(spanned(*left, span)),
+            Box::new(spanned(*right, span)),
         ),
-        Value::Union(items) => ValueKind::Union(
-            items
-                .into_iter()
-                .map(|v| SpannedValue {
-                    span: SimpleSpan::new((), 0..0),
-                    kind: value_to_kind(v),
-                })
-                .collect(),
-        ),
-    }
+        Value::Interpolation(parts) => ValueKind::Interpolation(all(parts)),
+        Value::Union(items) => ValueKind::Union(all(items)),
+        Value::Default(v) => ValueKind::Default(Box::new(spanned(*v, span))),
+    };
+    SpannedValue { span, kind }
 }
 
 fn kind_to_value(k: &ValueKind) -> Value {
@@ -1015,24 +2829,32 @@ fn kind_to_value(k: &ValueKind) -> Value {
         ValueKind::Bool(b) => Value::Bool(*b),
         ValueKind::Int(n) => Value::Int(*n),
         ValueKind::Float(n) => Value::Float(*n),
+        ValueKind::Rational(n) => Value::Rational(n.clone()),
+        ValueKind::Decimal(n) => Value::Decimal(n.clone()),
         ValueKind::String(s) => Value::String(s.clone()),
         ValueKind::Array(arr) => Value::Array(arr.iter().map(|v| v.to_value()).collect()),
+        ValueKind::List(list) => Value::List(list.map(|v| v.to_value())),
         ValueKind::Object(obj) => Value::Object(
             obj.iter()
-                .filter(|(_, _, _, anns)| {
-                    !anns.contains(&Annotation::NoExport) && !anns.contains(&Annotation::Function)
-                })
+                .filter(|(_, v, _, anns)| is_exported(v, anns))
                 .map(|(k, v, _, _)| (k.clone(), v.to_value()))
                 .collect(),
         ),
         ValueKind::Reference(r) => Value::Reference(r.clone()),
         ValueKind::Type(t) => Value::Type(t.clone()),
         ValueKind::Call(name, arg) => Value::Call(name.clone(), Box::new(arg.to_value())),
+        ValueKind::Arguments(items) => {
+            Value::Arguments(items.iter().map(|v| v.to_value()).collect())
+        }
         ValueKind::OpCall(op, left, right) => Value::OpCall(
             op.clone(),
             Box::new(left.to_value()),
             Box::new(right.to_value()),
         ),
+        ValueKind::Interpolation(parts) => {
+            Value::Interpolation(parts.iter().map(|v| v.to_value()).collect())
+        }
         ValueKind::Union(items) => Value::Union(items.iter().map(|v| v.to_value()).collect()),
+        ValueKind::Default(v) => Value::Default(Box::new(v.to_value())),
     }
 }
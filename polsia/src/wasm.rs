@@ -1,7 +1,37 @@
-use crate::parse_to_json;
+use crate::diagnostic;
+use crate::{
+    MemoryLoader, SourceMap, parse_to_json, parse_to_json_with_diagnostics,
+    parse_to_json_with_loader,
+};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub fn polsia_to_json(src: &str) -> Result<String, String> {
     parse_to_json(src)
 }
+
+/// Evaluates `src` with `names[i]` importable as a file containing
+/// `contents[i]`.
+#[wasm_bindgen]
+pub fn polsia_to_json_with_files(
+    src: &str,
+    names: Vec<String>,
+    contents: Vec<String>,
+) -> Result<String, String> {
+    let loader: MemoryLoader = names.into_iter().zip(contents).collect();
+    parse_to_json_with_loader(src, &loader)
+}
+
+/// Like [`polsia_to_json_with_files`], but fails with a JSON array of
+/// diagnostics (see [`crate::Diagnostic::to_json`]) instead of rendered text.
+#[wasm_bindgen]
+pub fn polsia_to_json_with_diagnostics(
+    src: &str,
+    names: Vec<String>,
+    contents: Vec<String>,
+) -> Result<String, String> {
+    let loader: MemoryLoader = names.into_iter().zip(contents).collect();
+    let mut sources = SourceMap::new();
+    parse_to_json_with_diagnostics(src, &loader, &mut sources)
+        .map_err(|diagnostics| diagnostic::to_json_string(&diagnostics, &sources))
+}
@@ -0,0 +1,214 @@
+use crate::export::{self, ExportError, ExportErrorKind};
+use crate::types::{Value, to_float};
+use serde_json::Number;
+
+/// Renders `value` as a single YAML document. Object keys keep their order.
+/// Fails on values that are not concrete.
+pub fn to_string(value: &Value) -> Result<String, ExportError> {
+    let mut out = String::new();
+    write_document(&mut out, value, "")?;
+    Ok(out)
+}
+
+/// Renders `value` as a YAML stream: a non-empty top-level array becomes one
+/// document per element, anything else a single document.
+pub fn to_documents(value: &Value) -> Result<String, ExportError> {
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            let mut out = String::new();
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("---\n");
+                }
+                write_document(&mut out, item, &format!("[{}]", i))?;
+            }
+            Ok(out)
+        }
+        _ => to_string(value),
+    }
+}
+
+fn write_document(out: &mut String, value: &Value, path: &str) -> Result<(), ExportError> {
+    if is_block(value) {
+        write_block(out, value, 0, path)
+    } else {
+        out.push_str(&scalar(value, path)?);
+        out.push('\n');
+        Ok(())
+    }
+}
+
+/// Non-empty arrays and objects are written as indented blocks; everything
+/// else fits on the line of its key or list marker.
+fn is_block(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(members) => !members.is_empty(),
+        _ => false,
+    }
+}
+
+fn write_block(
+    out: &mut String,
+    value: &Value,
+    indent: usize,
+    path: &str,
+) -> Result<(), ExportError> {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(members) => {
+            for (k, v) in members {
+                let member_path = export::member_path(path, k);
+                out.push_str(&pad);
+                out.push_str(&string(k));
+                out.push(':');
+                if is_block(v) {
+                    out.push('\n');
+                    write_block(out, v, indent + 2, &member_path)?;
+                } else {
+                    out.push(' ');
+                    out.push_str(&scalar(v, &member_path)?);
+                    out.push('\n');
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                let item_path = format!("{}[{}]", path, i);
+                out.push_str(&pad);
+                out.push('-');
+                if is_block(v) {
+                    // Write the nested block at the item's indentation and pull
+                    // its first line up next to the dash.
+                    let mut nested = String::new();
+                    write_block(&mut nested, v, indent + 2, &item_path)?;
+                    out.push(' ');
+                    out.push_str(&nested[indent + 2..]);
+                } else {
+                    out.push(' ');
+                    out.push_str(&scalar(v, &item_path)?);
+                    out.push('\n');
+                }
+            }
+        }
+        _ => unreachable!("write_block called on a scalar"),
+    }
+    Ok(())
+}
+
+fn scalar(value: &Value, path: &str) -> Result<String, ExportError> {
+    Ok(match value {
+        Value::Null => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Float(n) if n.is_nan() => ".nan".into(),
+        Value::Float(n) if n.is_infinite() => {
+            if *n > 0.0 {
+                ".inf".into()
+            } else {
+                "-.inf".into()
+            }
+        }
+        Value::Float(n) => float(*n),
+        Value::Decimal(n) => return scalar(&Value::Float(to_float(n)), path),
+        Value::Rational(n) if n.is_integer() => n.numer().to_string(),
+        Value::Rational(n) => {
+            return Err(ExportError {
+                path: path.to_string(),
+                kind: ExportErrorKind::Inexact(n.clone()),
+            });
+        }
+        Value::String(s) => string(s),
+        Value::Array(_) => "[]".into(),
+        Value::Object(_) => "{}".into(),
+        other => {
+            return Err(ExportError {
+                path: path.to_string(),
+                kind: ExportErrorKind::Unresolved(export::unresolved(other).unwrap_or_default()),
+            });
+        }
+    })
+}
+
+/// A finite float in a form YAML 1.1 and 1.2 both read as a float: the
+/// mantissa always has a point and the exponent a sign, as in `1.0e+19`.
+fn float(n: f64) -> String {
+    let s = Number::from_f64(n).unwrap().to_string();
+    let (mantissa, exponent) = match s.split_once('e') {
+        Some((m, e)) => (m, Some(e)),
+        None => (s.as_str(), None),
+    };
+    let mut out = mantissa.to_string();
+    if !out.contains('.') {
+        out.push_str(".0");
+    }
+    if let Some(e) = exponent {
+        out.push('e');
+        if !e.starts_with('-') {
+            out.push('+');
+        }
+        out.push_str(e);
+    }
+    out
+}
+
+fn string(s: &str) -> String {
+    if needs_quotes(s) {
+        // JSON string syntax is valid YAML double-quoted scalar syntax.
+        serde_json::to_string(s).unwrap()
+    } else {
+        s.to_string()
+    }
+}
+
+/// Whether a plain (unquoted) scalar would be read back as something other
+/// than this string, by either a YAML 1.1 or a YAML 1.2 parser.
+fn needs_quotes(s: &str) -> bool {
+    if s.is_empty() || s.trim() != s {
+        return true;
+    }
+    if s.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.chars().any(|c| c.is_control())
+    {
+        return true;
+    }
+    let lower = s.to_ascii_lowercase();
+    if matches!(
+        lower.as_str(),
+        "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n" | "null" | "~"
+    ) {
+        return true;
+    }
+    looks_numeric(&lower) || looks_like_date(s)
+}
+
+fn looks_numeric(s: &str) -> bool {
+    let unsigned = s.trim_start_matches(['+', '-']);
+    if unsigned.replace('_', "").parse::<f64>().is_ok() || matches!(unsigned, ".inf" | ".nan") {
+        return true;
+    }
+    if let Some(digits) = ["0x", "0o", "0b"]
+        .iter()
+        .find_map(|p| unsigned.strip_prefix(p))
+    {
+        return !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit() || c == '_');
+    }
+    // YAML 1.1 sexagesimal numbers such as `1:30`.
+    unsigned.contains(':')
+        && unsigned
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, ':' | '.' | '_'))
+}
+
+fn looks_like_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() >= 10
+        && b[..4].iter().all(u8::is_ascii_digit)
+        && b[4] == b'-'
+        && b[5..7].iter().all(u8::is_ascii_digit)
+        && b[7] == b'-'
+        && b[8..10].iter().all(u8::is_ascii_digit)
+}